@@ -1,11 +1,78 @@
+use catalog::mini_attribute::TypeLabel;
+
 #[derive(Debug)]
 pub enum Stmt {
-    // expr, dbname, tablename, where_clause, sort_clause
-    SelectStmt(Box<Expr>, String, String, Option<Box<Expr>>, Option<String>),
-    // dbname, tablename, keys, values
-    InsertStmt(String, String, Vec<String>, Vec<Vec<String>>),
+    // target_list, dbname, tablename, where_clause, sort_clause
+    SelectStmt(Vec<ResTarget>, String, String, Option<Box<Expr>>, Vec<SortBy>),
+    // dbname, tablename, keys, values (None means null)
+    InsertStmt(String, String, Vec<String>, Vec<Vec<Option<String>>>),
     // dbname, tablename, where_clause
     DeleteStmt(String, String, Option<Box<Expr>>),
+    // dbname, tablename, set_clause (column name, expr), where_clause
+    UpdateStmt(String, String, Vec<(String, Box<Expr>)>, Option<Box<Expr>>),
+    // dbname, tablename, column definitions
+    CreateTableStmt(String, String, Vec<ColumnDef>),
+    // dbname, tablename, index name, column name
+    CreateIndexStmt(String, String, String, String),
+    // dbname
+    CreateDatabaseStmt(String),
+    // dbname, missing_ok
+    DropDatabaseStmt(String, bool),
+    // dbname, tablename, missing_ok
+    DropTableStmt(String, String, bool),
+    // kind
+    TransactionStmt(TransactionStmtKind),
+    // dbname, tablename
+    VacuumStmt(String, String),
+}
+
+// `TransactionStmtKind` in pg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionStmtKind {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+// `SortByDir` in pg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortByDir {
+    Asc,
+    Desc,
+}
+
+// `SortByNulls` in pg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortByNulls {
+    // NULLS LAST for ASC and NULLS FIRST for DESC
+    Default,
+    First,
+    Last,
+}
+
+// `SortBy` in pg. An item of ORDER BY.
+#[derive(Debug)]
+pub struct SortBy {
+    pub node: Box<Expr>,
+    pub dir: SortByDir,
+    pub nulls: SortByNulls,
+}
+
+// `ColumnDef` in pg.
+#[derive(Debug)]
+pub struct ColumnDef {
+    pub name: String,
+    pub ty: TypeLabel,
+    // Byte length of fixed length type, or max length of variable length type.
+    pub len: usize,
+}
+
+// `ResTarget` in pg. An item of the target list of SELECT.
+#[derive(Debug)]
+pub struct ResTarget {
+    // column label given by AS
+    pub name: Option<String>,
+    pub val: Box<Expr>,
 }
 
 #[derive(Debug)]
@@ -14,6 +81,65 @@ pub enum Expr {
     Count, // "count()"
     Bool(bool),
     Number(i32),
+    String(String),
+    Null,
     OpEq(Box<Expr>, Box<Expr>), // "="
+    OpNe(Box<Expr>, Box<Expr>), // "<>" or "!="
+    OpLt(Box<Expr>, Box<Expr>), // "<"
+    OpLe(Box<Expr>, Box<Expr>), // "<="
+    OpGt(Box<Expr>, Box<Expr>), // ">"
+    OpGe(Box<Expr>, Box<Expr>), // ">="
+    And(Box<Expr>, Box<Expr>), // "AND"
+    Or(Box<Expr>, Box<Expr>), // "OR"
+    Not(Box<Expr>), // "NOT"
+    Between(Box<Expr>, Box<Expr>, Box<Expr>), // expr "BETWEEN" lower "AND" upper
+    In(Box<Expr>, Vec<Box<Expr>>), // expr "IN" "(" list ")"
+    OpAdd(Box<Expr>, Box<Expr>), // "+"
+    OpSub(Box<Expr>, Box<Expr>), // "-"
+    OpMul(Box<Expr>, Box<Expr>), // "*"
+    OpDiv(Box<Expr>, Box<Expr>), // "/"
+    OpMod(Box<Expr>, Box<Expr>), // "%"
+    UMinus(Box<Expr>), // unary "-"
+    FuncCall(String, Vec<Box<Expr>>), // function name, arguments
+    IsNull(Box<Expr>), // "IS NULL"
+    IsNotNull(Box<Expr>), // "IS NOT NULL"
     ColumnRef(String), // column name
 }
+
+impl ResTarget {
+    pub fn new(name: Option<String>, val: Box<Expr>) -> ResTarget {
+        ResTarget {
+            name: name,
+            val: val,
+        }
+    }
+}
+
+impl SortBy {
+    pub fn new(node: Box<Expr>, dir: SortByDir, nulls: SortByNulls) -> SortBy {
+        SortBy {
+            node: node,
+            dir: dir,
+            nulls: nulls,
+        }
+    }
+
+    // Whether nulls are sorted before non-null values.
+    pub fn nulls_first(&self) -> bool {
+        match self.nulls {
+            SortByNulls::Default => self.dir == SortByDir::Desc,
+            SortByNulls::First => true,
+            SortByNulls::Last => false,
+        }
+    }
+}
+
+impl ColumnDef {
+    pub fn new(name: String, ty: TypeLabel, len: usize) -> ColumnDef {
+        ColumnDef {
+            name: name,
+            ty: ty,
+            len: len,
+        }
+    }
+}
@@ -0,0 +1,650 @@
+// B+tree index access method (nbtree in pg).
+//
+// Block 0 of an index relation is a meta page which has the block number of
+// the root page. Other pages have `BTPageOpaqueData` in the special space
+// and index tuples sorted by key.
+//
+// Index tuple (`IndexTupleData` in pg):
+//
+// +-----------------+------------------+------------------------+
+// | block num (u32) | offset num (u16) | key (data of a column) |
+// +-----------------+------------------+------------------------+
+//
+// In leaf pages, the tid points to a heap tuple. In internal pages,
+// block num points to a child page and key is the lower bound of keys in
+// the child. Key of the first item of internal pages is treated as
+// "minus infinity" and never compared.
+//
+// Insertion into a page is logged as a WAL record. Pages changed by a split
+// are logged as full page images.
+
+use std::cmp::Ordering;
+use std::mem;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use buffer_manager::{Buffer, BlockNumber, BufferManager, InvalidBlockNumber};
+use catalog::mini_attribute::TypeLabel;
+use config::DEFAULT_BLOCK_SIZE;
+use error::Error;
+use off::OffsetNumber;
+use page::Page;
+use storage_manager::{RelationData, StorageError};
+use tuple::{ItemPointerData, item_pointer_set, item_pointer_get_block_number};
+use ty::VARHDRSZ;
+use xlog::XLOG_BTREE_INSERT;
+
+// `BTREE_METAPAGE` in pg.
+const BTREE_METAPAGE: BlockNumber = 0;
+
+// Bits of `btpo_flags`.
+const BTP_LEAF: u16 = 1 << 0;
+const BTP_ROOT: u16 = 1 << 1;
+
+const SIZE_OF_INDEX_TUPLE_HEADER: usize = 6;
+
+// `BTMaxItemSize` in pg.
+// Keep at least 3 items in a page, leaving room for page header,
+// special space and line pointers.
+const BT_MAX_ITEM_SIZE: usize = (DEFAULT_BLOCK_SIZE as usize - 64) / 3;
+
+// `BTPageOpaqueData` in pg.
+#[derive(Debug, Clone, Copy)]
+struct BTPageOpaqueData {
+    // left sibling, or InvalidBlockNumber if leftmost
+    btpo_prev: BlockNumber,
+    // right sibling, or InvalidBlockNumber if rightmost
+    btpo_next: BlockNumber,
+    // tree level, zero for leaf pages
+    btpo_level: u32,
+    btpo_flags: u16,
+}
+
+const SIZE_OF_BT_PAGE_OPAQUE_DATA: usize = mem::size_of::<BTPageOpaqueData>();
+
+// `BTMetaPageData` in pg.
+// In pg this is stored in the contents of the meta page, we store this
+// in the special space of the meta page.
+#[derive(Debug, Clone, Copy)]
+struct BTMetaPageData {
+    // current root location
+    btm_root: BlockNumber,
+    // tree level of the root page
+    btm_level: u32,
+}
+
+const SIZE_OF_BT_META_PAGE_DATA: usize = mem::size_of::<BTMetaPageData>();
+
+// `StrategyNumber` of btree in pg.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+}
+
+// `ScanKeyData` in pg.
+// Condition of index scan, e.g. "key >= argument".
+#[derive(Debug, Clone)]
+pub struct ScanKeyData {
+    pub strategy: Strategy,
+    // key bytes to be compared
+    pub argument: Vec<u8>,
+}
+
+pub struct BTree<'a> {
+    relation: &'a RelationData,
+    // type of the key column
+    key_type: &'a TypeLabel,
+}
+
+fn page_get_opaque(page: &Page) -> BTPageOpaqueData {
+    unsafe { *(page.page_get_special_pointer() as *const BTPageOpaqueData) }
+}
+
+fn page_set_opaque(page: &mut Page, opaque: &BTPageOpaqueData) {
+    unsafe { *(page.page_get_special_pointer() as *mut BTPageOpaqueData) = *opaque; }
+}
+
+// `BTPageGetMeta` in pg.
+fn page_get_meta(page: &Page) -> BTMetaPageData {
+    unsafe { *(page.page_get_special_pointer() as *const BTMetaPageData) }
+}
+
+fn page_set_meta(page: &mut Page, meta: &BTMetaPageData) {
+    unsafe { *(page.page_get_special_pointer() as *mut BTMetaPageData) = *meta; }
+}
+
+fn page_get_items(page: &Page) -> Vec<Vec<u8>> {
+    (0..page.page_get_max_offset_number()).map(|i| page.get_entry(i).unwrap()).collect()
+}
+
+// `index_form_tuple` in pg.
+fn form_index_tuple(block_num: BlockNumber, offnum: OffsetNumber, key: &[u8]) -> Vec<u8> {
+    let mut itup = vec![0; SIZE_OF_INDEX_TUPLE_HEADER];
+    LittleEndian::write_u32(&mut itup[0..4], block_num);
+    LittleEndian::write_u16(&mut itup[4..6], offnum);
+    itup.extend_from_slice(key);
+    itup
+}
+
+fn index_tuple_block_number(itup: &[u8]) -> BlockNumber {
+    LittleEndian::read_u32(&itup[0..4])
+}
+
+fn index_tuple_offset_number(itup: &[u8]) -> OffsetNumber {
+    LittleEndian::read_u16(&itup[4..6])
+}
+
+fn index_tuple_key(itup: &[u8]) -> &[u8] {
+    &itup[SIZE_OF_INDEX_TUPLE_HEADER..]
+}
+
+// Compare two keys of `ty` type. See `_bt_compare` in pg.
+pub fn compare_keys(ty: &TypeLabel, a: &[u8], b: &[u8]) -> Ordering {
+    match ty {
+        TypeLabel::Integer => LittleEndian::read_i32(a).cmp(&LittleEndian::read_i32(b)),
+        TypeLabel::Text | TypeLabel::Varchar => a[VARHDRSZ..].cmp(&b[VARHDRSZ..]),
+    }
+}
+
+impl ScanKeyData {
+    pub fn new(strategy: Strategy, argument: Vec<u8>) -> ScanKeyData {
+        ScanKeyData {
+            strategy: strategy,
+            argument: argument,
+        }
+    }
+
+    // Whether this key limits the start position of scan.
+    fn is_lower_bound(&self) -> bool {
+        match self.strategy {
+            Strategy::Equal | Strategy::GreaterEqual | Strategy::Greater => true,
+            Strategy::Less | Strategy::LessEqual => false,
+        }
+    }
+
+    fn satisfies(&self, ty: &TypeLabel, key: &[u8]) -> bool {
+        let ord = compare_keys(ty, key, &self.argument);
+
+        match self.strategy {
+            Strategy::Less => ord == Ordering::Less,
+            Strategy::LessEqual => ord != Ordering::Greater,
+            Strategy::Equal => ord == Ordering::Equal,
+            Strategy::GreaterEqual => ord != Ordering::Less,
+            Strategy::Greater => ord == Ordering::Greater,
+        }
+    }
+
+    // Whether `key` and all keys after it can not satisfy this key.
+    // See `_bt_checkkeys` in pg.
+    fn is_end_of_scan(&self, ty: &TypeLabel, key: &[u8]) -> bool {
+        let ord = compare_keys(ty, key, &self.argument);
+
+        match self.strategy {
+            Strategy::Less => ord != Ordering::Less,
+            Strategy::LessEqual | Strategy::Equal => ord == Ordering::Greater,
+            Strategy::GreaterEqual | Strategy::Greater => false,
+        }
+    }
+}
+
+impl<'a> BTree<'a> {
+    pub fn new(relation: &'a RelationData, key_type: &'a TypeLabel) -> BTree<'a> {
+        BTree {
+            relation: relation,
+            key_type: key_type,
+        }
+    }
+
+    // `btbuildempty` in pg.
+    //
+    // Create the meta page and an empty root page.
+    pub fn init(&self, bufmrg: &mut BufferManager) -> Result<(), StorageError> {
+        let meta = bufmrg.read_buffer_new_page(self.relation)?;
+        assert_eq!(bufmrg.buffer_get_block_number(meta), BTREE_METAPAGE);
+        bufmrg.get_page_mut(meta).page_init_with_special(DEFAULT_BLOCK_SIZE, SIZE_OF_BT_META_PAGE_DATA as u16);
+
+        let (root_buffer, root) = self.new_page(bufmrg, 0, BTP_LEAF | BTP_ROOT)?;
+        let meta_data = BTMetaPageData {
+            btm_root: root,
+            btm_level: 0,
+        };
+        page_set_meta(bufmrg.get_page_mut(meta), &meta_data);
+
+        bufmrg.log_newpage(meta)?;
+        bufmrg.log_newpage(root_buffer)?;
+        bufmrg.release_buffer(meta);
+        bufmrg.release_buffer(root_buffer);
+        Ok(())
+    }
+
+    // `btinsert` and `_bt_doinsert` in pg.
+    pub fn insert(&self, bufmrg: &mut BufferManager, key: &[u8], tid: &ItemPointerData) -> Result<(), Error> {
+        let itup = form_index_tuple(item_pointer_get_block_number(tid), tid.item_pointer_get_offset_number(), key);
+
+        if itup.len() > BT_MAX_ITEM_SIZE {
+            return Err(Error::ProgramLimitExceeded(format!("Index row size {} exceeds maximum {}", itup.len(), BT_MAX_ITEM_SIZE)));
+        }
+
+        let mut stack = Vec::new();
+        // Insert new tuple after existing tuples which have the same key.
+        let blkno = self.search(bufmrg, Some(key), true, &mut stack)?;
+        let offnum = {
+            let buffer = bufmrg.read_buffer(self.relation, blkno)?;
+            let offnum = self.binsrch(bufmrg.get_page(buffer), 0, key, true);
+            bufmrg.release_buffer(buffer);
+            offnum
+        };
+        self.insert_onto_page(bufmrg, blkno, offnum, itup, &mut stack)?;
+
+        Ok(())
+    }
+
+    // `btgetbitmap` in pg.
+    //
+    // Returns tids of heap tuples whose keys satisfy all `scan_keys`.
+    pub fn scan(&self, bufmrg: &mut BufferManager, scan_keys: &Vec<ScanKeyData>) -> Result<Vec<ItemPointerData>, StorageError> {
+        let mut tids = Vec::new();
+        let mut stack = Vec::new();
+
+        // `_bt_first` in pg. Start from the largest lower bound if exists,
+        // otherwise start from the leftmost leaf page.
+        let start = scan_keys.iter()
+                        .filter(|k| k.is_lower_bound())
+                        .max_by(|a, b| compare_keys(self.key_type, &a.argument, &b.argument));
+
+        let (mut blkno, mut offnum) = match start {
+            Some(k) => {
+                let blkno = self.search(bufmrg, Some(&k.argument), false, &mut stack)?;
+                let buffer = bufmrg.read_buffer(self.relation, blkno)?;
+                let offnum = self.binsrch(bufmrg.get_page(buffer), 0, &k.argument, false);
+                bufmrg.release_buffer(buffer);
+                (blkno, offnum)
+            },
+            None => (self.search(bufmrg, None, false, &mut stack)?, 0),
+        };
+
+        // `_bt_next` in pg. Move right until the end of scan.
+        loop {
+            let buffer = bufmrg.read_buffer(self.relation, blkno)?;
+            let mut end_of_scan = false;
+
+            {
+                let page = bufmrg.get_page(buffer);
+
+                for i in offnum..page.page_get_max_offset_number() {
+                    let itup = page.get_entry(i).unwrap();
+                    let key = index_tuple_key(&itup);
+
+                    if scan_keys.iter().any(|k| k.is_end_of_scan(self.key_type, key)) {
+                        end_of_scan = true;
+                        break;
+                    }
+
+                    if scan_keys.iter().all(|k| k.satisfies(self.key_type, key)) {
+                        let mut tid = ItemPointerData::new();
+                        item_pointer_set(&mut tid, index_tuple_block_number(&itup), index_tuple_offset_number(&itup));
+                        tids.push(tid);
+                    }
+                }
+
+                blkno = page_get_opaque(page).btpo_next;
+            }
+
+            bufmrg.release_buffer(buffer);
+            offnum = 0;
+
+            if end_of_scan || blkno == InvalidBlockNumber {
+                return Ok(tids);
+            }
+        }
+    }
+
+    // `btbulkdelete` in pg.
+    //
+    // Delete index tuples which point to `dead_tuples` (sorted by tid),
+    // visiting all leaf pages from the leftmost one. Empty pages are kept
+    // in the tree. Returns the number of deleted index tuples.
+    pub fn bulk_delete(&self, bufmrg: &mut BufferManager, dead_tuples: &Vec<ItemPointerData>) -> Result<usize, StorageError> {
+        let mut stack = Vec::new();
+        let mut blkno = self.search(bufmrg, None, false, &mut stack)?;
+        let mut ndeleted = 0;
+
+        loop {
+            let buffer = bufmrg.read_buffer(self.relation, blkno)?;
+            let deletable: Vec<OffsetNumber> = {
+                let page = bufmrg.get_page(buffer);
+                blkno = page_get_opaque(page).btpo_next;
+
+                (0..page.page_get_max_offset_number()).filter(|i| {
+                    let itup = page.get_entry(*i).unwrap();
+                    let mut tid = ItemPointerData::new();
+                    item_pointer_set(&mut tid, index_tuple_block_number(&itup), index_tuple_offset_number(&itup));
+                    dead_tuples.binary_search(&tid).is_ok()
+                }).collect()
+            };
+
+            if !deletable.is_empty() {
+                bufmrg.get_page_mut(buffer).page_index_multi_delete(&deletable);
+                bufmrg.log_newpage(buffer)?;
+                ndeleted += deletable.len();
+            }
+
+            bufmrg.release_buffer(buffer);
+
+            if blkno == InvalidBlockNumber {
+                return Ok(ndeleted);
+            }
+        }
+    }
+
+    // `_bt_getroot` in pg.
+    fn get_root(&self, bufmrg: &mut BufferManager) -> Result<BlockNumber, StorageError> {
+        let meta = bufmrg.read_buffer(self.relation, BTREE_METAPAGE)?;
+        let meta_data = page_get_meta(bufmrg.get_page(meta));
+        bufmrg.release_buffer(meta);
+        debug!("Root of btree: (block: {}, level: {})", meta_data.btm_root, meta_data.btm_level);
+        Ok(meta_data.btm_root)
+    }
+
+    // `_bt_getbuf(rel, P_NEW, BT_WRITE)` and `_bt_pageinit` in pg.
+    //
+    // The returned buffer is pinned.
+    fn new_page(&self, bufmrg: &mut BufferManager, level: u32, flags: u16) -> Result<(Buffer, BlockNumber), StorageError> {
+        let buffer = bufmrg.read_buffer_new_page(self.relation)?;
+        let blkno = bufmrg.buffer_get_block_number(buffer);
+        let page = bufmrg.get_page_mut(buffer);
+        let opaque = BTPageOpaqueData {
+            btpo_prev: InvalidBlockNumber,
+            btpo_next: InvalidBlockNumber,
+            btpo_level: level,
+            btpo_flags: flags,
+        };
+
+        page.page_init_with_special(DEFAULT_BLOCK_SIZE, SIZE_OF_BT_PAGE_OPAQUE_DATA as u16);
+        page_set_opaque(page, &opaque);
+
+        Ok((buffer, blkno))
+    }
+
+    // `_bt_search` in pg.
+    //
+    // Descend the tree to the leaf page where `key` should be. If `nextkey` is
+    // true, returns the rightmost page which can have `key`, otherwise returns
+    // the leftmost page. If `key` is None, returns the leftmost leaf page.
+    // Block numbers of parent pages are pushed to `stack`.
+    fn search(&self, bufmrg: &mut BufferManager, key: Option<&[u8]>, nextkey: bool, stack: &mut Vec<BlockNumber>) -> Result<BlockNumber, StorageError> {
+        let mut blkno = self.get_root(bufmrg)?;
+
+        loop {
+            let buffer = bufmrg.read_buffer(self.relation, blkno)?;
+            let child = {
+                let page = bufmrg.get_page(buffer);
+
+                if page_get_opaque(page).btpo_flags & BTP_LEAF != 0 {
+                    None
+                } else {
+                    // Skip the first item because its key is minus infinity.
+                    let offnum = match key {
+                        Some(k) => self.binsrch(page, 1, k, nextkey) - 1,
+                        None => 0,
+                    };
+
+                    Some(index_tuple_block_number(&page.get_entry(offnum).unwrap()))
+                }
+            };
+            bufmrg.release_buffer(buffer);
+
+            match child {
+                Some(c) => {
+                    stack.push(blkno);
+                    blkno = c;
+                },
+                None => return Ok(blkno),
+            }
+        }
+    }
+
+    // `_bt_binsrch` in pg.
+    //
+    // Returns the first offset, at or after `low`, whose key is greater than
+    // `key` (if `nextkey` is true) or greater than or equal to `key`
+    // (if `nextkey` is false).
+    fn binsrch(&self, page: &Page, low: OffsetNumber, key: &[u8], nextkey: bool) -> OffsetNumber {
+        let mut low = low;
+        let mut high = page.page_get_max_offset_number();
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let itup = page.get_entry(mid).unwrap();
+            let ord = compare_keys(self.key_type, index_tuple_key(&itup), key);
+
+            if ord == Ordering::Greater || (!nextkey && ord == Ordering::Equal) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        low
+    }
+
+    // `_bt_insertonpg` in pg.
+    //
+    // Insert `itup` at `offnum` of the page. If there is no enough space,
+    // split the page and insert a downlink for the new page into the parent.
+    fn insert_onto_page(&self, bufmrg: &mut BufferManager, blkno: BlockNumber, offnum: OffsetNumber, itup: Vec<u8>, stack: &mut Vec<BlockNumber>) -> Result<(), StorageError> {
+        let buffer = bufmrg.read_buffer(self.relation, blkno)?;
+
+        if bufmrg.get_page_mut(buffer).add_entry_at(itup.as_ptr() as *const libc::c_void, itup.len() as u16, offnum).is_ok() {
+            bufmrg.xlog_insert(buffer, XLOG_BTREE_INSERT, offnum, itup)?;
+            bufmrg.release_buffer(buffer);
+            return Ok(());
+        }
+
+        let (rblkno, sep_key) = self.split(bufmrg, buffer, blkno, offnum, itup)?;
+        bufmrg.release_buffer(buffer);
+
+        match stack.pop() {
+            Some(parent) => {
+                let pbuffer = bufmrg.read_buffer(self.relation, parent)?;
+                let poffnum = page_get_items(bufmrg.get_page(pbuffer)).iter()
+                                  .position(|item| index_tuple_block_number(item) == blkno)
+                                  .expect("Downlink to the split page should exist in the parent page") + 1;
+                bufmrg.release_buffer(pbuffer);
+                let downlink = form_index_tuple(rblkno, 0, &sep_key);
+                self.insert_onto_page(bufmrg, parent, poffnum as OffsetNumber, downlink, stack)
+            },
+            None => {
+                self.new_root(bufmrg, blkno, rblkno, &sep_key)
+            }
+        }
+    }
+
+    // `_bt_split` in pg.
+    //
+    // Move upper half of items (including `itup`) to a new right sibling.
+    // Returns the block number of the new page and the first key of it,
+    // which is used as the separator key in the parent page.
+    fn split(&self, bufmrg: &mut BufferManager, buffer: Buffer, blkno: BlockNumber, offnum: OffsetNumber, itup: Vec<u8>) -> Result<(BlockNumber, Vec<u8>), StorageError> {
+        let (mut items, opaque) = {
+            let page = bufmrg.get_page(buffer);
+            (page_get_items(page), page_get_opaque(page))
+        };
+        items.insert(offnum as usize, itup);
+
+        // `_bt_findsplitloc` in pg. Split items so that both pages have
+        // almost the same amount of data.
+        let total: usize = items.iter().map(|item| item.len()).sum();
+        let mut left_size = items[0].len();
+        let mut firstright = 1;
+
+        while firstright < items.len() - 1 && left_size < total / 2 {
+            left_size += items[firstright].len();
+            firstright += 1;
+        }
+
+        let right_items = items.split_off(firstright);
+        let (rbuffer, rblkno) = self.new_page(bufmrg, opaque.btpo_level, opaque.btpo_flags & BTP_LEAF)?;
+
+        {
+            let rpage = bufmrg.get_page_mut(rbuffer);
+            let mut ropaque = page_get_opaque(rpage);
+            ropaque.btpo_prev = blkno;
+            ropaque.btpo_next = opaque.btpo_next;
+            page_set_opaque(rpage, &ropaque);
+
+            for item in right_items.iter() {
+                rpage.add_vec_entry(item).unwrap();
+            }
+        }
+
+        {
+            let page = bufmrg.get_page_mut(buffer);
+            let mut lopaque = opaque;
+            lopaque.btpo_next = rblkno;
+            page.page_init_with_special(DEFAULT_BLOCK_SIZE, SIZE_OF_BT_PAGE_OPAQUE_DATA as u16);
+            page_set_opaque(page, &lopaque);
+
+            for item in items.iter() {
+                page.add_vec_entry(item).unwrap();
+            }
+        }
+
+        if opaque.btpo_next != InvalidBlockNumber {
+            let nbuffer = bufmrg.read_buffer(self.relation, opaque.btpo_next)?;
+            let npage = bufmrg.get_page_mut(nbuffer);
+            let mut nopaque = page_get_opaque(npage);
+            nopaque.btpo_prev = rblkno;
+            page_set_opaque(npage, &nopaque);
+            bufmrg.log_newpage(nbuffer)?;
+            bufmrg.release_buffer(nbuffer);
+        }
+
+        bufmrg.log_newpage(buffer)?;
+        bufmrg.log_newpage(rbuffer)?;
+        bufmrg.release_buffer(rbuffer);
+
+        Ok((rblkno, index_tuple_key(&right_items[0]).to_vec()))
+    }
+
+    // `_bt_newroot` in pg.
+    //
+    // Create a new root page which has downlinks to the old root page
+    // and its new right sibling.
+    fn new_root(&self, bufmrg: &mut BufferManager, lblkno: BlockNumber, rblkno: BlockNumber, sep_key: &[u8]) -> Result<(), StorageError> {
+        let lbuffer = bufmrg.read_buffer(self.relation, lblkno)?;
+        let level = {
+            let lpage = bufmrg.get_page_mut(lbuffer);
+            let mut lopaque = page_get_opaque(lpage);
+            lopaque.btpo_flags &= !BTP_ROOT;
+            page_set_opaque(lpage, &lopaque);
+            lopaque.btpo_level + 1
+        };
+        bufmrg.log_newpage(lbuffer)?;
+        bufmrg.release_buffer(lbuffer);
+
+        let (buffer, blkno) = self.new_page(bufmrg, level, BTP_ROOT)?;
+
+        {
+            let page = bufmrg.get_page_mut(buffer);
+            page.add_vec_entry(&form_index_tuple(lblkno, 0, &[])).unwrap();
+            page.add_vec_entry(&form_index_tuple(rblkno, 0, sep_key)).unwrap();
+        }
+
+        let meta = bufmrg.read_buffer(self.relation, BTREE_METAPAGE)?;
+        let meta_data = BTMetaPageData {
+            btm_root: blkno,
+            btm_level: level,
+        };
+        page_set_meta(bufmrg.get_page_mut(meta), &meta_data);
+
+        bufmrg.log_newpage(buffer)?;
+        bufmrg.log_newpage(meta)?;
+        bufmrg.release_buffer(buffer);
+        bufmrg.release_buffer(meta);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::rc::Rc;
+    use tempfile::tempdir;
+    use config::{Config, N_BUFFERS};
+    use transam::create_clog_file;
+
+    fn int_key(i: i32) -> Vec<u8> {
+        let mut v = vec![0; 4];
+        LittleEndian::write_i32(&mut v, i);
+        v
+    }
+
+    #[test]
+    fn test_compare_keys() {
+        let a: Vec<u8> = vec![1, 0, 0, 0, 97];
+        let b: Vec<u8> = vec![2, 0, 0, 0, 97, 98];
+
+        assert_eq!(compare_keys(&TypeLabel::Integer, &int_key(-1), &int_key(1)), Ordering::Less);
+        assert_eq!(compare_keys(&TypeLabel::Integer, &int_key(300), &int_key(2)), Ordering::Greater);
+        assert_eq!(compare_keys(&TypeLabel::Text, &a, &b), Ordering::Less);
+        assert_eq!(compare_keys(&TypeLabel::Text, &a, &a), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_insert_and_scan() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.table_dir_path(10000, 10001)).unwrap();
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+        create_clog_file(&config).unwrap();
+        let relation = RelationData::new(10000, 10001);
+        let mut bufmrg = BufferManager::new(N_BUFFERS, config.clone()).unwrap();
+        let btree = BTree::new(&relation, &TypeLabel::Integer);
+        btree.init(&mut bufmrg).unwrap();
+
+        // Enough keys to split leaf pages and create a new root page.
+        for i in 0..5000 {
+            let key = (i * 7919) % 5000;
+            let mut tid = ItemPointerData::new();
+            item_pointer_set(&mut tid, key as u32, 0);
+            btree.insert(&mut bufmrg, &int_key(key), &tid).unwrap();
+        }
+
+        let mut tid = ItemPointerData::new();
+        item_pointer_set(&mut tid, 5000, 0);
+        btree.insert(&mut bufmrg, &int_key(10), &tid).unwrap();
+
+        let eq = btree.scan(&mut bufmrg, &vec![ScanKeyData::new(Strategy::Equal, int_key(10))]).unwrap();
+        let blocks: Vec<u32> = eq.iter().map(|t| item_pointer_get_block_number(t)).collect();
+        assert_eq!(blocks, vec![10, 5000]);
+
+        let range = btree.scan(&mut bufmrg, &vec![
+            ScanKeyData::new(Strategy::Greater, int_key(1000)),
+            ScanKeyData::new(Strategy::LessEqual, int_key(3000)),
+        ]).unwrap();
+        let blocks: Vec<u32> = range.iter().map(|t| item_pointer_get_block_number(t)).collect();
+        assert_eq!(blocks, (1001..3001).collect::<Vec<u32>>());
+
+        let all = btree.scan(&mut bufmrg, &vec![]).unwrap();
+        assert_eq!(all.len(), 5001);
+
+        // Delete keys less than 1000 and the second tuple of key 10.
+        let mut dead_tuples: Vec<ItemPointerData> = all.into_iter()
+                                                       .filter(|t| item_pointer_get_block_number(t) < 1000 || item_pointer_get_block_number(t) == 5000)
+                                                       .collect();
+        dead_tuples.sort();
+        assert_eq!(btree.bulk_delete(&mut bufmrg, &dead_tuples).unwrap(), 1001);
+
+        let eq = btree.scan(&mut bufmrg, &vec![ScanKeyData::new(Strategy::Equal, int_key(10))]).unwrap();
+        assert!(eq.is_empty());
+        let all = btree.scan(&mut bufmrg, &vec![]).unwrap();
+        let blocks: Vec<u32> = all.iter().map(|t| item_pointer_get_block_number(t)).collect();
+        assert_eq!(blocks, (1000..5000).collect::<Vec<u32>>());
+    }
+}
@@ -3,11 +3,19 @@
 use std::rc::Rc;
 use std::collections::HashMap;
 
+use byteorder::{ByteOrder, LittleEndian};
+
 use page::{Page, MAX_HEAP_TUPLE_SIZE};
 use tuple::{TupleTableSlot, HeapTupleData, ItemPointerData};
-use config::{Config, N_BUFFERS, DEFAULT_BLOCK_SIZE};
+use config::{Config, DEFAULT_BLOCK_SIZE};
+use error::Error;
+use freespace::FreeSpaceMap;
+use off::OffsetNumber;
 use oid_manager::Oid;
-use storage_manager::{StorageManager, RelationData};
+use storage_manager::{StorageManager, StorageError, RelationData};
+use tqual::{Snapshot, heap_tuple_satisfies_visibility};
+use transam::{TransactionId, TransactionManager, InvalidTransactionId};
+use xlog::{XLogManager, XLogRecord, XLogRecPtr, XLOG_HEAP_INSERT, XLOG_HEAP_DELETE, XLOG_BTREE_INSERT, XLOG_FPI};
 
 // Buffer identifiers
 // Zero is invalid, positive is the index of a shared buffer (1..NBuffers),
@@ -25,6 +33,20 @@ fn unwrap_buffer_id(buffer_id: Buffer) -> usize {
     }
 }
 
+// Mark the tuple as deleted by `xid`.
+fn heap_tuple_set_deleted(page: &mut Page, lineoff: OffsetNumber, xid: TransactionId) {
+    let len = page.get_item_ref(lineoff).lp_len();
+    // TODO: Is this cast correct?
+    let mut tuple_data = HeapTupleData::new_with_full_len(len as u32);
+    let mut tid = ItemPointerData::new();
+    ::tuple::item_pointer_set(&mut tid, InvalidBlockNumber, lineoff);
+
+    tuple_data.load_without_len(page.get_entry_pointer(lineoff).unwrap(), tid);
+    tuple_data.t_data.set_t_xmax(xid);
+    tuple_data.t_data.set_heap_keys_updated();
+    tuple_data.write_data(page.get_entry_pointer(lineoff).unwrap() as *mut libc::c_void);
+}
+
 // block.h in pg.
 // Block number of a data file (start with 0)
 pub type BlockNumber = u32;
@@ -46,143 +68,423 @@ struct BufferTag {
     block_num: BlockNumber,
 }
 
+// Max value of `usage_count` (`BM_MAX_USAGE_COUNT` in pg).
+const BM_MAX_USAGE_COUNT: u8 = 5;
+
 #[derive(Debug)]
 struct BufferDesc {
     tag: BufferTag,
     buf_id: Buffer, // buffer's index number (from 0)
-    locked: bool,
-    dirty: bool,
-    valid: bool,
+    refcount: u32, // number of pins
+    usage_count: u8, // usage counter for clock sweep
+    dirty: bool, // page is modified and not written yet
+    valid: bool, // page has the data of the tag
+}
+
+impl BufferDesc {
+    fn new(buf_id: Buffer) -> BufferDesc {
+        BufferDesc {
+            tag: BufferTag {
+                rnode: RelFileNode { table_oid: 0, db_oid: 0 },
+                block_num: InvalidBlockNumber,
+            },
+            buf_id: buf_id,
+            refcount: 0,
+            usage_count: 0,
+            dirty: false,
+            valid: false,
+        }
+    }
 }
 
 // TODO: Use RwLock to minimize RwLock scope and change ScanState to hold BufferManager ref
 pub struct BufferManager {
     config: Rc<Config>,
     smgr: StorageManager,
+    xlog: XLogManager,
+    fsm: FreeSpaceMap,
     buffer_descriptors: Vec<BufferDesc>,
     pages: Vec<Page>,
     // Hash from BufferTag to index of descriptor and page
     // See LocalBufHash in pg.
     buffer_hash: HashMap<BufferTag, Buffer>,
+    // Clock hand of clock sweep (`nextVictimBuffer` in pg).
+    next_victim_buffer: usize,
+    // WAL is being replayed (`InRecovery` in pg).
+    in_recovery: bool,
 }
 
+// Checkpoint is skipped if pages can not be written, so that changes of
+// them are recovered from WAL. It is also skipped if redo fails, because
+// the checkpoint would move the redo pointer past records not replayed yet.
 impl Drop for BufferManager {
     fn drop(&mut self) {
-        self.flush_buffers();
-    }
-}
+        if self.in_recovery {
+            return;
+        }
 
-macro_rules! ensure_pages_length {
-    ($self:ident) => (
-        if $self.pages.len() > N_BUFFERS {
-            warn!("Page length ({}) exceeds N_BUFFERS ({})", $self.pages.len(), N_BUFFERS);
-            // panic!("Page length ({}) exceeds N_BUFFERS ({})", self.pages.len(), N_BUFFERS);
+        if let Err(e) = self.create_checkpoint() {
+            error!("checkpoint is skipped. '{}'", e);
         }
-    )
+    }
 }
 
 impl BufferManager {
-    pub fn new(size: usize, config: Rc<Config>) -> BufferManager {
-        BufferManager {
+    // `size` is the number of buffers, usually `N_BUFFERS`.
+    pub fn new(size: usize, config: Rc<Config>) -> Result<BufferManager, StorageError> {
+        let mut bufmrg = BufferManager {
             config: config.clone(),
-            smgr: StorageManager::new(config),
-            buffer_descriptors: Vec::with_capacity(N_BUFFERS),
-            pages: Vec::with_capacity(N_BUFFERS),
+            smgr: StorageManager::new(config.clone()),
+            xlog: XLogManager::open(config.clone())?,
+            fsm: FreeSpaceMap::new(config),
+            buffer_descriptors: (0..size).map(|i| BufferDesc::new(Buffer::Buffer(i))).collect(),
+            pages: (0..size).map(|_| Page::new(DEFAULT_BLOCK_SIZE)).collect(),
             buffer_hash: HashMap::new(),
+            next_victim_buffer: 0,
+            in_recovery: true,
+        };
+
+        bufmrg.startup_xlog()?;
+        bufmrg.in_recovery = false;
+        bufmrg.abort_in_progress_transactions()?;
+        Ok(bufmrg)
+    }
+
+    // `CreateCheckPoint` in pg.
+    //
+    // Write all dirty pages and free space maps, then move the redo pointer
+    // of WAL to the current position.
+    pub fn create_checkpoint(&mut self) -> Result<(), StorageError> {
+        self.flush_buffers()?;
+        self.fsm.flush()?;
+        self.smgr.smgrimmedsync_all()?;
+        self.xlog.checkpoint()
+    }
+
+    // `DropRelFileNodeBuffers` in pg.
+    //
+    // Discard pages of the relation without writing them. This is called
+    // before files of the relation are removed.
+    pub fn drop_rel_file_node_buffers(&mut self, rnode: &RelFileNode) {
+        self.invalidate_buffers(|r| r == rnode);
+    }
+
+    // `DropDatabaseBuffers` in pg.
+    pub fn drop_database_buffers(&mut self, db_oid: Oid) {
+        self.invalidate_buffers(|r| r.db_oid == db_oid);
+    }
+
+    // `InvalidateBuffer` in pg for relations which match `f`. Opened files
+    // and free space maps of them are also forgotten.
+    fn invalidate_buffers<F>(&mut self, f: F) where F: Fn(&RelFileNode) -> bool {
+        for descriptor in self.buffer_descriptors.iter_mut() {
+            if !descriptor.valid || !f(&descriptor.tag.rnode) {
+                continue;
+            }
+
+            if descriptor.refcount != 0 {
+                panic!("Buffer {:?} of dropped relation is pinned", descriptor.buf_id);
+            }
+
+            self.buffer_hash.remove(&descriptor.tag);
+            descriptor.valid = false;
+            descriptor.dirty = false;
+            descriptor.usage_count = 0;
+        }
+
+        self.smgr.smgrclose(&f);
+        self.fsm.forget(&f);
+    }
+
+    // `StartupXLOG` in pg.
+    //
+    // Replay WAL records after the last checkpoint. There are such records
+    // only if the last process did not finish normally.
+    fn startup_xlog(&mut self) -> Result<(), StorageError> {
+        let records = self.xlog.read_records()?;
+
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        info!("redo starts at {}", self.xlog.redo_ptr());
+
+        for (lsn, record) in records.iter() {
+            if let Err(e) = self.xlog_redo(*lsn, record) {
+                error!("redo failed at {}", lsn);
+                return Err(e);
+            }
+        }
+
+        info!("redo done at {}", self.xlog.insert_ptr());
+        Ok(())
+    }
+
+    // Transactions which are in progress at startup were interrupted by a
+    // crash, so they never commit. This relies on the data directory being
+    // used by one session at a time, which `Executor` ensures by the lock
+    // file.
+    fn abort_in_progress_transactions(&self) -> Result<(), StorageError> {
+        let transam = TransactionManager::new(self.config.clone());
+        let n = transam.abort_in_progress_transactions()
+                       .map_err(|e| StorageError::Io(format!("abort transactions in file {:?}", self.config.clog_file_path()), e))?;
+
+        if n > 0 {
+            info!("{} transactions left in progress are aborted", n);
+        }
+
+        Ok(())
+    }
+
+    // Apply a WAL record to the page. See `heap_redo` and `btree_redo` in pg.
+    fn xlog_redo(&mut self, lsn: XLogRecPtr, record: &XLogRecord) -> Result<(), StorageError> {
+        // The relation has been dropped after the record.
+        if !self.config.table_dir_path(record.rnode.db_oid, record.rnode.table_oid).exists() {
+            return Ok(());
+        }
+
+        let relation = RelationData::new(record.rnode.db_oid, record.rnode.table_oid);
+        let buffer = self.read_buffer(&relation, record.block_num)?;
+
+        // The page already has the change of the record.
+        if self.get_page(buffer).page_get_lsn() >= lsn {
+            self.release_buffer(buffer);
+            return Ok(());
+        }
+
+        self.mark_buffer_dirty(buffer);
+
+        {
+            let page = self.get_page_mut(buffer);
+
+            match record.xl_info {
+                XLOG_HEAP_INSERT => {
+                    page.add_entry_overwrite(record.data.as_ptr() as *const libc::c_void, record.data.len() as u16, record.offnum).unwrap();
+                },
+                XLOG_BTREE_INSERT => {
+                    page.add_entry_at(record.data.as_ptr() as *const libc::c_void, record.data.len() as u16, record.offnum).unwrap();
+                },
+                XLOG_HEAP_DELETE => {
+                    heap_tuple_set_deleted(page, record.offnum, LittleEndian::read_u32(&record.data));
+                },
+                XLOG_FPI => {
+                    page.restore_image(&record.data);
+                },
+                _ => panic!("Unknown WAL record ({:?})", record.xl_info),
+            }
+
+            page.page_set_lsn(lsn);
         }
+
+        self.release_buffer(buffer);
+        Ok(())
+    }
+
+    // `XLogInsert` in pg with a registered buffer.
+    //
+    // Log the change of the page and set the LSN of the record to the page.
+    // If the page is changed first time after the last checkpoint, the full
+    // page image is logged instead to recover from torn page writes
+    // (`full_page_writes` in pg).
+    //
+    // The buffer is marked as dirty here because all changes of pages are
+    // logged (`MarkBufferDirty` is called before `XLogInsert` in pg).
+    pub fn xlog_insert(&mut self, buffer: Buffer, xl_info: u8, offnum: OffsetNumber, data: Vec<u8>) -> Result<(), StorageError> {
+        let buf = unwrap_buffer_id(buffer);
+        self.mark_buffer_dirty(buffer);
+        let record = {
+            let tag = &self.buffer_descriptors[buf].tag;
+            let page = &self.pages[buf];
+
+            if xl_info == XLOG_FPI || page.page_get_lsn() <= self.xlog.redo_ptr() {
+                XLogRecord::new(XLOG_FPI, tag.rnode.clone(), tag.block_num, 0, page.get_image())
+            } else {
+                XLogRecord::new(xl_info, tag.rnode.clone(), tag.block_num, offnum, data)
+            }
+        };
+
+        let lsn = self.xlog.insert(&record)?;
+        self.pages[buf].page_set_lsn(lsn);
+        Ok(())
+    }
+
+    // End of WAL inserted so far.
+    pub fn xlog_insert_ptr(&self) -> XLogRecPtr {
+        self.xlog.insert_ptr()
+    }
+
+    // WAL before this pointer is on disk.
+    pub fn xlog_flushed_ptr(&self) -> XLogRecPtr {
+        self.xlog.flushed_ptr()
+    }
+
+    // `XLogFlush` in pg.
+    pub fn xlog_flush(&mut self, lsn: XLogRecPtr) -> Result<(), StorageError> {
+        self.xlog.flush(lsn)
+    }
+
+    // `log_newpage_buffer` in pg.
+    //
+    // Log the full page image, e.g. when the page is reorganized.
+    pub fn log_newpage(&mut self, buffer: Buffer) -> Result<(), StorageError> {
+        self.xlog_insert(buffer, XLOG_FPI, 0, Vec::new())
     }
 
     // `heap_insert` function in pg.
-    pub fn heap_insert(&mut self, relation: &RelationData, tuple: &TupleTableSlot) {
-        let buffer = self.relation_get_buffer_for_tuple(relation, tuple.len());
-        self.relation_put_heap_tuple(buffer, tuple);
+    //
+    // Returns tid of the inserted tuple, which is used to insert index tuples.
+    pub fn heap_insert(&mut self, relation: &RelationData, tuple: &TupleTableSlot, xid: TransactionId) -> Result<ItemPointerData, Error> {
+        let mut tuple = tuple.clone();
+        tuple.heap_tuple.t_data.set_t_xmin(xid);
+        tuple.heap_tuple.t_data.set_t_xmax(InvalidTransactionId);
+
+        let buffer = self.relation_get_buffer_for_tuple(relation, tuple.len())?;
+        let tid = self.relation_put_heap_tuple(buffer, &tuple);
+        let offnum = tid.item_pointer_get_offset_number();
+        let data = self.get_page(buffer).get_entry(offnum).unwrap();
+        self.xlog_insert(buffer, XLOG_HEAP_INSERT, offnum, data)?;
+        self.record_free_space(buffer);
+        self.release_buffer(buffer);
+        Ok(tid)
     }
 
-    // `heap_delete` in pg.
-    pub fn heap_delete(&mut self, relation: &RelationData, tid: &ItemPointerData) {
+    // `heap_fetch` in pg.
+    //
+    // Fetch a tuple by tid, e.g. for index scan. Returns None if the tuple
+    // is not visible to the snapshot.
+    pub fn heap_fetch(&mut self, relation: &RelationData, tid: &ItemPointerData, snapshot: &Snapshot) -> Result<Option<HeapTupleData>, Error> {
         let block = ::tuple::item_pointer_get_block_number(tid);
-        let buffer = self.read_buffer(relation, block);
-        let page = self.get_page(buffer);
+        let buffer = self.read_buffer(relation, block)?;
         let lineoff = tid.item_pointer_get_offset_number();
 
-        let lp = page.get_item_ref(lineoff);
-        let len = lp.lp_len();
+        let tuple = {
+            let page = self.get_page(buffer);
 
-        // TODO: Is this cast correct?
-        let mut tuple_data = HeapTupleData::new_with_full_len(len as u32);
+            if lineoff >= page.page_get_max_offset_number() || !page.get_item_ref(lineoff).is_normal() {
+                Ok(None)
+            } else {
+                let len = page.get_item_ref(lineoff).lp_len();
+                let mut tuple_data = HeapTupleData::new_with_full_len(len as u32);
+                tuple_data.load(page.get_entry_pointer(lineoff).unwrap(), len as u32, tid.clone());
+
+                heap_tuple_satisfies_visibility(&tuple_data.t_data, snapshot)
+                    .map(|visible| if visible { Some(tuple_data) } else { None })
+            }
+        };
+
+        self.release_buffer(buffer);
+        Ok(tuple?)
+    }
+
+    // `heap_delete` in pg.
+    //
+    // Set `xid` to xmax of the tuple. The tuple is still visible to other
+    // transactions until the transaction is committed.
+    pub fn heap_delete(&mut self, relation: &RelationData, tid: &ItemPointerData, xid: TransactionId) -> Result<(), StorageError> {
+        let block = ::tuple::item_pointer_get_block_number(tid);
+        let buffer = self.read_buffer(relation, block)?;
+        let lineoff = tid.item_pointer_get_offset_number();
 
         debug!("Deleting record on (block: {}, lineoff: {})", block, lineoff);
 
-        tuple_data.load_without_len(page.get_entry_pointer(lineoff).unwrap(), tid.clone());
-        tuple_data.t_data.set_heap_keys_updated();
-        tuple_data.write_data(page.get_entry_pointer(lineoff).unwrap() as *mut libc::c_void);
+        heap_tuple_set_deleted(self.get_page_mut(buffer), lineoff, xid);
+
+        let mut data = vec![0; 4];
+        LittleEndian::write_u32(&mut data, xid);
+        self.xlog_insert(buffer, XLOG_HEAP_DELETE, lineoff, data)?;
+        // Space of the deleted tuple is not reusable until VACUUM, but this
+        // corrects the map if it is stale.
+        self.record_free_space(buffer);
+        self.release_buffer(buffer);
+        Ok(())
+    }
+
+    // `heap_update` in pg.
+    //
+    // Mark the old tuple as deleted and insert the new version of it.
+    // Returns tid of the new version.
+    pub fn heap_update(&mut self, relation: &RelationData, otid: &ItemPointerData, newtup: &TupleTableSlot, xid: TransactionId) -> Result<ItemPointerData, Error> {
+        self.heap_delete(relation, otid, xid)?;
+        self.heap_insert(relation, newtup, xid)
     }
 
     // `RelationPutHeapTuple` in pg.
-    fn relation_put_heap_tuple(&mut self, buffer :Buffer, tuple: &TupleTableSlot) {
-        let page = self.get_page_mut(buffer);
-        page.add_tuple_slot_entry(tuple).unwrap();
+    fn relation_put_heap_tuple(&mut self, buffer :Buffer, tuple: &TupleTableSlot) -> ItemPointerData {
+        let block_num = self.buffer_get_block_number(buffer);
+        let offnum = self.get_page_mut(buffer).add_tuple_slot_entry(tuple).unwrap();
+
+        let mut tid = ItemPointerData::new();
+        ::tuple::item_pointer_set(&mut tid, block_num, offnum);
+        tid
     }
 
     // `RelationGetBufferForTuple` function in pg.
-    fn relation_get_buffer_for_tuple(&mut self, relation: &RelationData, len: u32) -> Buffer {
+    //
+    // Returns the pinned buffer which has enough space for the tuple.
+    // Try the last target block, then blocks found by the free space map,
+    // and extend the relation if there is no such block.
+    fn relation_get_buffer_for_tuple(&mut self, relation: &RelationData, len: u32) -> Result<Buffer, Error> {
         if (len as usize) > MAX_HEAP_TUPLE_SIZE {
-            panic!("row is too big: size {}, maximum size {}", len, MAX_HEAP_TUPLE_SIZE);
+            return Err(Error::ProgramLimitExceeded(format!("row is too big: size {}, maximum size {}", len, MAX_HEAP_TUPLE_SIZE)));
         }
 
-        let mut target_block = InvalidBlockNumber;
+        let mut target_block = self.smgr.relation_smgropen(relation).borrow().smgr_targblock;
 
-        {
-            let mut rd_smgr = self.smgr.relation_smgropen(relation).borrow_mut();
-            target_block = rd_smgr.smgr_targblock;
+        if target_block == InvalidBlockNumber {
+            target_block = self.fsm.get_page_with_free_space(&relation.smgr_rnode, len as usize);
 
+            // If the map knows nothing, try the last page before extending
+            // the relation.
             if target_block == InvalidBlockNumber {
-                let nblocks = rd_smgr.mdnblocks();
+                let nblocks = self.relation_get_number_of_blocks(relation)?;
 
                 if nblocks > 0 {
                     target_block = nblocks - 1;
-                } else {
-                    target_block = 0;
                 }
             }
         }
 
-        // When we implement VACUUM and FSM, we should check there is space
-        // in each page in loop. But now we do not have such fetures, so
-        // we can simply get new page if there is no space in current page.
-        //
-        // loop {
-        {
-            let buffer = self.read_buffer(relation, target_block);
+        while target_block != InvalidBlockNumber {
+            let buffer = self.read_buffer(relation, target_block)?;
             let page_free_space = self.get_page_free_space(buffer);
-            let mut rd_smgr = self.smgr.relation_smgropen(&relation).borrow_mut();
+
             if (len as usize) <= page_free_space {
-                rd_smgr.smgr_targblock = target_block;
-                return buffer;
+                self.smgr.relation_smgropen(relation).borrow_mut().smgr_targblock = target_block;
+                return Ok(buffer);
             }
 
-            // TODO: release buffer.
+            self.release_buffer(buffer);
+            target_block = self.fsm.record_and_get_page_with_free_space(&relation.smgr_rnode, target_block, page_free_space, len as usize);
         }
 
         // `buffer = ReadBufferBI(relation, P_NEW, bistate);` call in pg.
-        let buffer = self.read_buffer_new_page(relation);
+        let buffer = self.read_buffer_new_page(relation)?;
         self.get_page_mut(buffer).page_init(DEFAULT_BLOCK_SIZE);
+        self.mark_buffer_dirty(buffer);
         let block_num = self.buffer_get_block_number(buffer);
         let mut rd_smgr = self.smgr.relation_smgropen(&relation).borrow_mut();
         rd_smgr.smgr_targblock = block_num;
 
-        return buffer
+        Ok(buffer)
+    }
+
+    // `RecordPageWithFreeSpace` in pg with the free space of the page.
+    pub fn record_free_space(&mut self, buffer: Buffer) {
+        let buf = unwrap_buffer_id(buffer);
+        let space_avail = self.pages[buf].page_get_free_space();
+        let tag = &self.buffer_descriptors[buf].tag;
+        self.fsm.record_page_with_free_space(&tag.rnode, tag.block_num, space_avail);
     }
 
     // `BufferGetBlockNumber` in pg.
-    fn buffer_get_block_number(&self, buffer :Buffer) -> BlockNumber {
+    pub fn buffer_get_block_number(&self, buffer :Buffer) -> BlockNumber {
         let buf = unwrap_buffer_id(buffer);
         let tag = &self.buffer_descriptors[buf].tag;
         tag.block_num
     }
 
     // `RelationGetNumberOfBlocks` in pg.
-    pub fn relation_get_number_of_blocks(&mut self, relation: &RelationData) -> BlockNumber {
+    pub fn relation_get_number_of_blocks(&mut self, relation: &RelationData) -> Result<BlockNumber, StorageError> {
         let mut rd_smgr = self.smgr.relation_smgropen(relation).borrow_mut();
         rd_smgr.mdnblocks()
     }
@@ -190,11 +492,11 @@ impl BufferManager {
     // `blockNum == P_NEW` case of `ReadBuffer_common` in pg.
     //
     // This method create new page.
-    fn read_buffer_new_page(&mut self, relation: &RelationData) -> Buffer {
+    pub fn read_buffer_new_page(&mut self, relation: &RelationData) -> Result<Buffer, StorageError> {
         let tag = {
             let mut rd_smgr = self.smgr.relation_smgropen(relation).borrow_mut();
             // Get latest block number.
-            let block_num = rd_smgr.mdnblocks();
+            let block_num = rd_smgr.mdnblocks()?;
 
             BufferTag {
                 rnode: rd_smgr.smgr_rnode.clone(),
@@ -207,10 +509,12 @@ impl BufferManager {
 
 
     // `ReadBuffer` function in pg.
+    // The returned buffer is pinned, so the caller should call
+    // `release_buffer` when it finishes to use the buffer.
     // This should recieve Relation instead of RelFileNode because we should
     // determine which block should be loaded, but the block info is stored in
     // Relation (SMgrRelationData).
-    pub fn read_buffer(&mut self, relation: &RelationData, block_num: BlockNumber) -> Buffer {
+    pub fn read_buffer(&mut self, relation: &RelationData, block_num: BlockNumber) -> Result<Buffer, StorageError> {
         let tag = {
             let rd_smgr = self.smgr.relation_smgropen(relation).borrow();
             BufferTag {
@@ -223,74 +527,170 @@ impl BufferManager {
     }
 
     // ReadBuffer_common in pg
-    fn read_buffer_common(&mut self, relation: &RelationData, tag: BufferTag, new_page: bool) -> Buffer {
+    fn read_buffer_common(&mut self, relation: &RelationData, tag: BufferTag, new_page: bool) -> Result<Buffer, StorageError> {
+        let hit = self.buffer_hash.get(&tag).cloned();
+
+        if let Some(buffer) = hit {
+            self.pin_buffer(buffer);
+            return Ok(buffer);
+        }
+
+        let buf = self.buffer_alloc()?;
+
+        debug!("page is loaded (buf_id: {}, tag {:?})", buf, tag);
+
+        {
+            let mut rd_smgr = self.smgr.relation_smgropen(relation).borrow_mut();
+            let page = &mut self.pages[buf];
+
+            if new_page {
+                page.fill_with_zero(DEFAULT_BLOCK_SIZE as usize);
+                rd_smgr.mdextend(tag.block_num, page.header_pointer())?;
+            } else {
+                // Reading a block beyond the end of file reads nothing,
+                // so the page is initialized as an empty page.
+                page.page_init(DEFAULT_BLOCK_SIZE);
+                rd_smgr.mdread(tag.block_num, page.header_pointer())?;
+            }
+        }
+
+        let buffer = Buffer::Buffer(buf);
         {
-            let opt = self.buffer_hash.get(&tag);
+            let descriptor = &mut self.buffer_descriptors[buf];
+            descriptor.tag = tag.clone();
+            descriptor.refcount = 1;
+            descriptor.usage_count = 1;
+            descriptor.dirty = false;
+            descriptor.valid = true;
+        }
+        self.buffer_hash.insert(tag, buffer);
 
-            if opt.is_some() {
-                return opt.unwrap().clone();
+        Ok(buffer)
+    }
+
+    // `BufferAlloc` in pg.
+    //
+    // Find a victim buffer and evict the page in it. A dirty page is
+    // written back before eviction.
+    fn buffer_alloc(&mut self) -> Result<usize, StorageError> {
+        let buf = self.strategy_get_buffer()?;
+
+        if self.buffer_descriptors[buf].valid {
+            if self.buffer_descriptors[buf].dirty {
+                self.flush_buffer_with_index(buf)?;
             }
+
+            let descriptor = &mut self.buffer_descriptors[buf];
+            self.buffer_hash.remove(&descriptor.tag);
+            descriptor.valid = false;
         }
 
-        let buffer = Buffer::Buffer(self.pages.len());
-        let descriptor = BufferDesc {
-            tag: tag.clone(),
-            buf_id: buffer,
-            locked: false,
-            dirty: false,
-            valid: true,
-        };
+        Ok(buf)
+    }
+
+    // `StrategyGetBuffer` in pg.
+    //
+    // Run clock sweep to find unpinned buffer whose usage_count is zero.
+    // The usage_count of unpinned buffers are decremented while the clock
+    // hand passes.
+    fn strategy_get_buffer(&mut self) -> Result<usize, StorageError> {
+        let n_buffers = self.buffer_descriptors.len();
+        let mut trycounter = n_buffers;
+
+        loop {
+            let buf = self.next_victim_buffer;
+            self.next_victim_buffer = (buf + 1) % n_buffers;
+            let descriptor = &mut self.buffer_descriptors[buf];
+
+            if descriptor.refcount == 0 {
+                if descriptor.usage_count == 0 {
+                    return Ok(buf);
+                }
 
-        debug!("page is pushed (len: {}, tag {:?})", self.pages.len(), tag);
+                descriptor.usage_count -= 1;
+                trycounter = n_buffers;
+            } else {
+                trycounter -= 1;
 
-        let mut rd_smgr = self.smgr.relation_smgropen(relation).borrow_mut();
+                if trycounter == 0 {
+                    return Err(StorageError::NoUnpinnedBuffers(n_buffers));
+                }
+            }
+        }
+    }
 
-        let page = if new_page {
-            let mut page = Page::new(DEFAULT_BLOCK_SIZE);
-            page.fill_with_zero(DEFAULT_BLOCK_SIZE as usize);
-            rd_smgr.mdextend(tag.block_num, page.header_pointer());
-            page
-        } else {
-            let page = Page::new(DEFAULT_BLOCK_SIZE);
-            rd_smgr.mdread(tag.block_num, page.header_pointer());
-            page
-        };
+    // `PinBuffer` in pg.
+    fn pin_buffer(&mut self, buffer: Buffer) {
+        let descriptor = &mut self.buffer_descriptors[unwrap_buffer_id(buffer)];
+        descriptor.refcount += 1;
 
-        self.pages.push(page);
-        self.buffer_descriptors.push(descriptor);
-        self.buffer_hash.entry(tag).or_insert_with(|| {
-            buffer
-        });
+        if descriptor.usage_count < BM_MAX_USAGE_COUNT {
+            descriptor.usage_count += 1;
+        }
+    }
 
-        ensure_pages_length!(self);
+    // `ReleaseBuffer` in pg.
+    //
+    // Unpin the buffer. Unpinned buffer may be evicted, so the caller should
+    // not use it after release.
+    pub fn release_buffer(&mut self, buffer: Buffer) {
+        let descriptor = &mut self.buffer_descriptors[unwrap_buffer_id(buffer)];
 
-        buffer
+        if descriptor.refcount == 0 {
+            panic!("Buffer {:?} is not pinned", buffer);
+        }
+
+        descriptor.refcount -= 1;
     }
 
-    // `FlushBuffer` in pg.
-    fn flush_buffer(&mut self, buffer_id: Buffer) {
-        let buf = unwrap_buffer_id(buffer_id);
-        self.flush_buffer_with_index(buf);
+    // `ResourceOwnerRelease` in pg.
+    //
+    // Unpin all buffers. A statement which fails in the middle of a scan
+    // leaves pins, so this is called when the statement ends.
+    pub fn release_all_buffers(&mut self) {
+        for descriptor in self.buffer_descriptors.iter_mut() {
+            descriptor.refcount = 0;
+        }
     }
 
-    fn flush_buffer_with_index(&mut self, i: usize) {
+    // `MarkBufferDirty` in pg.
+    pub fn mark_buffer_dirty(&mut self, buffer: Buffer) {
+        let buf = unwrap_buffer_id(buffer);
+        self.buffer_descriptors[buf].dirty = true;
+    }
+
+    fn flush_buffer_with_index(&mut self, i: usize) -> Result<(), StorageError> {
         debug!("Page {} is flushed", i);
 
-        let page = &self.pages[i];
-        let descriptor = &self.buffer_descriptors[i];
-        let rnode = &descriptor.tag.rnode;
-        let block_num = descriptor.tag.block_num;
+        // WAL should be written before data pages.
+        let lsn = self.pages[i].page_get_lsn();
+        self.xlog.flush(lsn)?;
+
+        {
+            let page = &self.pages[i];
+            let descriptor = &self.buffer_descriptors[i];
+            let rnode = &descriptor.tag.rnode;
+            let block_num = descriptor.tag.block_num;
+
+            let relation_data = self.smgr.smgropen(&rnode);
+            relation_data.borrow_mut().mdwrite(block_num, page.header_pointer())?;
+        }
 
-        let relation_data = self.smgr.smgropen(&rnode);
-        relation_data.borrow_mut().mdwrite(block_num, page.header_pointer());
+        self.buffer_descriptors[i].dirty = false;
+        Ok(())
     }
 
-    fn flush_buffers(&mut self) {
+    // Write all dirty pages (See `BufferSync` in pg).
+    fn flush_buffers(&mut self) -> Result<(), StorageError> {
         let len = self.buffer_descriptors.len();
 
         for i in 0..len {
-            self.flush_buffer_with_index(i);
+            if self.buffer_descriptors[i].valid && self.buffer_descriptors[i].dirty {
+                self.flush_buffer_with_index(i)?;
+            }
         }
+
+        Ok(())
     }
 
     fn get_page_free_space(&self, buffer_id: Buffer) -> usize {
@@ -313,3 +713,159 @@ impl BufferManager {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::mem;
+    use tempfile::tempdir;
+    use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+    use config::N_BUFFERS;
+    use tqual::get_snapshot_data;
+    use transam::create_clog_file;
+    use ty::Integer;
+
+    fn setup() -> (tempfile::TempDir, Rc<Config>, RelationData) {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.table_dir_path(10000, 10001)).unwrap();
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+        create_clog_file(&config).unwrap();
+        (dir, config, RelationData::new(10000, 10001))
+    }
+
+    #[test]
+    fn test_eviction_writes_dirty_page() {
+        let (_dir, config, relation) = setup();
+        let mut bufmrg = BufferManager::new(2, config.clone()).unwrap();
+
+        for _ in 0..3 {
+            let buffer = bufmrg.read_buffer_new_page(&relation).unwrap();
+            bufmrg.get_page_mut(buffer).page_init(DEFAULT_BLOCK_SIZE);
+            bufmrg.mark_buffer_dirty(buffer);
+            bufmrg.release_buffer(buffer);
+        }
+
+        let buffer = bufmrg.read_buffer(&relation, 0).unwrap();
+        bufmrg.get_page_mut(buffer).add_vec_entry(&vec![1, 2, 3]).unwrap();
+        bufmrg.mark_buffer_dirty(buffer);
+        bufmrg.release_buffer(buffer);
+
+        // Evict block 0 by reading other blocks.
+        for block in 1..3 {
+            let buffer = bufmrg.read_buffer(&relation, block).unwrap();
+            bufmrg.release_buffer(buffer);
+        }
+        assert!(bufmrg.buffer_hash.len() <= 2);
+        assert!(bufmrg.buffer_hash.keys().all(|tag| tag.block_num != 0));
+
+        let buffer = bufmrg.read_buffer(&relation, 0).unwrap();
+        assert_eq!(bufmrg.get_page(buffer).get_entry(0).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_pinned_buffer_is_not_evicted() {
+        let (_dir, config, relation) = setup();
+        let mut bufmrg = BufferManager::new(2, config.clone()).unwrap();
+        let pinned = bufmrg.read_buffer_new_page(&relation).unwrap();
+
+        for _ in 0..5 {
+            let buffer = bufmrg.read_buffer_new_page(&relation).unwrap();
+            bufmrg.release_buffer(buffer);
+        }
+
+        assert_eq!(bufmrg.buffer_get_block_number(pinned), 0);
+    }
+
+    #[test]
+    fn test_all_buffers_pinned() {
+        let (_dir, config, relation) = setup();
+        let mut bufmrg = BufferManager::new(2, config.clone()).unwrap();
+
+        for _ in 0..2 {
+            bufmrg.read_buffer_new_page(&relation).unwrap();
+        }
+
+        match bufmrg.read_buffer_new_page(&relation) {
+            Err(StorageError::NoUnpinnedBuffers(n)) => assert_eq!(n, 2),
+            _ => panic!("read_buffer_new_page should fail"),
+        }
+    }
+
+    #[test]
+    fn test_recovery_from_wal() {
+        let (_dir, config, relation) = setup();
+        let attrs = vec![MiniAttributeRecord::new("id".to_string(), 10000, 10001, TypeLabel::Integer, 4)];
+        let mut slot = TupleTableSlot::new(attrs);
+        let transam = TransactionManager::new(config.clone());
+        let xid = transam.get_new_transaction_id().unwrap();
+
+        {
+            let mut bufmrg = BufferManager::new(N_BUFFERS, config.clone()).unwrap();
+            slot.set_column(0, &Integer { elem: 1 });
+            bufmrg.heap_insert(&relation, &slot, xid).unwrap();
+        }
+
+        // Crash without writing pages.
+        let mut bufmrg = BufferManager::new(N_BUFFERS, config.clone()).unwrap();
+        slot.set_column(0, &Integer { elem: 2 });
+        let tid = bufmrg.heap_insert(&relation, &slot, xid).unwrap();
+        bufmrg.heap_delete(&relation, &tid, xid).unwrap();
+        slot.set_column(0, &Integer { elem: 3 });
+        bufmrg.heap_insert(&relation, &slot, xid).unwrap();
+        bufmrg.xlog.flush(bufmrg.xlog.insert_ptr()).unwrap();
+        mem::forget(bufmrg);
+        transam.transaction_id_commit(xid).unwrap();
+
+        let mut bufmrg = BufferManager::new(N_BUFFERS, config.clone()).unwrap();
+        let snapshot = get_snapshot_data(InvalidTransactionId, &transam).unwrap();
+        let buffer = bufmrg.read_buffer(&relation, 0).unwrap();
+        assert_eq!(bufmrg.get_page(buffer).entry_count(), 3);
+        assert!(bufmrg.heap_fetch(&relation, &tid, &snapshot).unwrap().is_none());
+
+        let mut tid3 = ItemPointerData::new();
+        ::tuple::item_pointer_set(&mut tid3, 0, 2);
+        let tuple = bufmrg.heap_fetch(&relation, &tid3, &snapshot).unwrap().unwrap();
+        slot.load_data(tuple.data_ptr(), tuple.len(), tid3);
+        assert_eq!(slot.get_column(0).as_string(), "3".to_string());
+    }
+
+    #[test]
+    fn test_heap_visibility() {
+        let (_dir, config, relation) = setup();
+        let attrs = vec![MiniAttributeRecord::new("id".to_string(), 10000, 10001, TypeLabel::Integer, 4)];
+        let slot = TupleTableSlot::new(attrs);
+        let transam = TransactionManager::new(config.clone());
+        let mut bufmrg = BufferManager::new(N_BUFFERS, config.clone()).unwrap();
+
+        let xid1 = transam.get_new_transaction_id().unwrap();
+        let xid2 = transam.get_new_transaction_id().unwrap();
+        let xid3 = transam.get_new_transaction_id().unwrap();
+        let committed = bufmrg.heap_insert(&relation, &slot, xid1).unwrap();
+        let aborted = bufmrg.heap_insert(&relation, &slot, xid2).unwrap();
+        let in_progress = bufmrg.heap_insert(&relation, &slot, xid3).unwrap();
+        transam.transaction_id_commit(xid1).unwrap();
+        transam.transaction_id_abort(xid2).unwrap();
+
+        let snapshot = get_snapshot_data(InvalidTransactionId, &transam).unwrap();
+        assert!(bufmrg.heap_fetch(&relation, &committed, &snapshot).unwrap().is_some());
+        assert!(bufmrg.heap_fetch(&relation, &aborted, &snapshot).unwrap().is_none());
+        assert!(bufmrg.heap_fetch(&relation, &in_progress, &snapshot).unwrap().is_none());
+
+        // Own changes are visible to the transaction itself.
+        let snapshot = get_snapshot_data(xid3, &transam).unwrap();
+        assert!(bufmrg.heap_fetch(&relation, &in_progress, &snapshot).unwrap().is_some());
+
+        // Delete by aborted transaction is ignored.
+        bufmrg.heap_delete(&relation, &committed, xid2).unwrap();
+        let snapshot = get_snapshot_data(InvalidTransactionId, &transam).unwrap();
+        assert!(bufmrg.heap_fetch(&relation, &committed, &snapshot).unwrap().is_some());
+
+        // Delete by in-progress transaction is visible only to itself.
+        bufmrg.heap_delete(&relation, &committed, xid3).unwrap();
+        assert!(bufmrg.heap_fetch(&relation, &committed, &snapshot).unwrap().is_some());
+        let snapshot = get_snapshot_data(xid3, &transam).unwrap();
+        assert!(bufmrg.heap_fetch(&relation, &committed, &snapshot).unwrap().is_none());
+    }
+}
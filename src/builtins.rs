@@ -0,0 +1,196 @@
+// Built-in functions and operators (`utils/adt` and `fmgrtab.c` in pg).
+//
+// Scalar functions are registered in `BUILTIN_FUNCTIONS` and looked up by
+// name. `ExprEvaluator` calls them with evaluated arguments.
+
+use catalog::mini_attribute::TypeLabel;
+use error::Error;
+use ty::{Datum, int4in};
+
+// `FmgrBuiltin` in pg.
+pub struct BuiltinFunction {
+    pub name: &'static str,
+    // The minimum and maximum number of arguments.
+    pub nargs: (usize, usize),
+    // Return type. None means the type of the arguments (`anyelement` in pg).
+    pub rettype: Option<TypeLabel>,
+    // `proisstrict` in pg. A strict function returns null without being
+    // called if any argument is null.
+    pub strict: bool,
+    pub func: fn(Vec<Datum>) -> Result<Datum, Error>,
+}
+
+static BUILTIN_FUNCTIONS: [BuiltinFunction; 6] = [
+    BuiltinFunction { name: "abs", nargs: (1, 1), rettype: Some(TypeLabel::Integer), strict: true, func: int4abs },
+    BuiltinFunction { name: "length", nargs: (1, 1), rettype: Some(TypeLabel::Integer), strict: true, func: textlen },
+    BuiltinFunction { name: "lower", nargs: (1, 1), rettype: Some(TypeLabel::Text), strict: true, func: lower },
+    BuiltinFunction { name: "upper", nargs: (1, 1), rettype: Some(TypeLabel::Text), strict: true, func: upper },
+    BuiltinFunction { name: "coalesce", nargs: (1, usize::MAX), rettype: None, strict: false, func: coalesce },
+    BuiltinFunction { name: "substr", nargs: (2, 3), rettype: Some(TypeLabel::Text), strict: true, func: text_substr },
+];
+
+// `LookupFuncName` in pg. Function names are case insensitive.
+pub fn lookup_function(name: &str, nargs: usize) -> Result<&'static BuiltinFunction, Error> {
+    let name = name.to_lowercase();
+
+    BUILTIN_FUNCTIONS.iter()
+        .find(|f| f.name == name && f.nargs.0 <= nargs && nargs <= f.nargs.1)
+        .ok_or_else(|| Error::UndefinedFunction(format!("Function {} with {} arguments does not exist.", name, nargs)))
+}
+
+fn out_of_range() -> Error {
+    Error::NumericValueOutOfRange("Integer out of range.".to_string())
+}
+
+fn division_by_zero() -> Error {
+    Error::DivisionByZero("Division by zero.".to_string())
+}
+
+pub fn int4pl(a: i32, b: i32) -> Result<i32, Error> {
+    a.checked_add(b).ok_or_else(out_of_range)
+}
+
+pub fn int4mi(a: i32, b: i32) -> Result<i32, Error> {
+    a.checked_sub(b).ok_or_else(out_of_range)
+}
+
+pub fn int4mul(a: i32, b: i32) -> Result<i32, Error> {
+    a.checked_mul(b).ok_or_else(out_of_range)
+}
+
+pub fn int4div(a: i32, b: i32) -> Result<i32, Error> {
+    if b == 0 {
+        return Err(division_by_zero());
+    }
+
+    a.checked_div(b).ok_or_else(out_of_range)
+}
+
+// The result has the sign of `a` like C.
+pub fn int4mod(a: i32, b: i32) -> Result<i32, Error> {
+    if b == 0 {
+        return Err(division_by_zero());
+    }
+
+    // i32::MIN % -1 overflows in Rust, but the result is 0.
+    if b == -1 {
+        return Ok(0);
+    }
+
+    Ok(a % b)
+}
+
+pub fn int4um(a: i32) -> Result<i32, Error> {
+    a.checked_neg().ok_or_else(out_of_range)
+}
+
+// Arguments of unknown type (string literals) are converted like pg.
+fn int_arg(func: &str, v: &Datum) -> Result<i32, Error> {
+    match v {
+        Datum::Integer(i) => Ok(*i),
+        Datum::Text(s) => int4in(s),
+        _ => Err(Error::UndefinedFunction(format!("Function {} does not accept the argument.", func))),
+    }
+}
+
+fn text_arg<'a>(func: &str, v: &'a Datum) -> Result<&'a str, Error> {
+    match v {
+        Datum::Text(s) => Ok(s),
+        _ => Err(Error::UndefinedFunction(format!("Function {} does not accept the argument.", func))),
+    }
+}
+
+fn int4abs(args: Vec<Datum>) -> Result<Datum, Error> {
+    let i = int_arg("abs", &args[0])?;
+    i.checked_abs().map(Datum::Integer).ok_or_else(out_of_range)
+}
+
+// The number of characters, not bytes.
+fn textlen(args: Vec<Datum>) -> Result<Datum, Error> {
+    let s = text_arg("length", &args[0])?;
+    Ok(Datum::Integer(s.chars().count() as i32))
+}
+
+fn lower(args: Vec<Datum>) -> Result<Datum, Error> {
+    Ok(Datum::Text(text_arg("lower", &args[0])?.to_lowercase()))
+}
+
+fn upper(args: Vec<Datum>) -> Result<Datum, Error> {
+    Ok(Datum::Text(text_arg("upper", &args[0])?.to_uppercase()))
+}
+
+// Returns the first non-null argument. pg evaluates `CoalesceExpr` lazily,
+// but all arguments are evaluated here.
+fn coalesce(args: Vec<Datum>) -> Result<Datum, Error> {
+    Ok(args.into_iter().find(|v| !v.is_null()).unwrap_or(Datum::Null))
+}
+
+// `text_substr` in pg. `start` is 1-origin and can be less than 1, e.g.
+// substr('abc', 0, 2) is 'a'.
+fn text_substr(args: Vec<Datum>) -> Result<Datum, Error> {
+    let s = text_arg("substr", &args[0])?;
+    let start = int_arg("substr", &args[1])? as i64;
+    let chars = s.chars().skip((start.max(1) - 1) as usize);
+
+    match args.get(2) {
+        Some(v) => {
+            let count = int_arg("substr", v)? as i64;
+
+            if count < 0 {
+                return Err(Error::SubstringError("Negative substring length not allowed.".to_string()));
+            }
+
+            // Characters before the first one are also counted.
+            let take = (start + count - start.max(1)).max(0) as usize;
+            Ok(Datum::Text(chars.take(take).collect()))
+        },
+        None => Ok(Datum::Text(chars.collect())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: Vec<Datum>) -> Result<Datum, Error> {
+        (lookup_function(name, args.len())?.func)(args)
+    }
+
+    fn string(s: &str) -> Datum {
+        Datum::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_int4_operators() {
+        assert_eq!(int4pl(1, 2).unwrap(), 3);
+        assert_eq!(int4pl(i32::MAX, 1).err().unwrap().code(), "22003");
+        assert_eq!(int4mi(i32::MIN, 1).err().unwrap().code(), "22003");
+        assert_eq!(int4mul(65536, 65536).err().unwrap().code(), "22003");
+        assert_eq!(int4div(7, -2).unwrap(), -3);
+        assert_eq!(int4div(1, 0).err().unwrap().code(), "22012");
+        assert_eq!(int4div(i32::MIN, -1).err().unwrap().code(), "22003");
+        assert_eq!(int4mod(-7, 2).unwrap(), -1);
+        assert_eq!(int4mod(i32::MIN, -1).unwrap(), 0);
+        assert_eq!(int4mod(1, 0).err().unwrap().code(), "22012");
+        assert_eq!(int4um(i32::MIN).err().unwrap().code(), "22003");
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(call("abs", vec![Datum::Integer(-3)]).unwrap(), Datum::Integer(3));
+        assert_eq!(call("ABS", vec![string("-3")]).unwrap(), Datum::Integer(3));
+        assert_eq!(call("abs", vec![Datum::Integer(i32::MIN)]).err().unwrap().code(), "22003");
+        assert_eq!(call("length", vec![string("日本語")]).unwrap(), Datum::Integer(3));
+        assert_eq!(call("length", vec![Datum::Integer(1)]).err().unwrap().code(), "42883");
+        assert_eq!(call("lower", vec![string("AbC")]).unwrap(), string("abc"));
+        assert_eq!(call("upper", vec![string("AbC")]).unwrap(), string("ABC"));
+        assert_eq!(call("coalesce", vec![Datum::Null, Datum::Integer(1), Datum::Integer(2)]).unwrap(), Datum::Integer(1));
+        assert_eq!(call("coalesce", vec![Datum::Null]).unwrap(), Datum::Null);
+        assert_eq!(call("substr", vec![string("abcde"), Datum::Integer(2)]).unwrap(), string("bcde"));
+        assert_eq!(call("substr", vec![string("abcde"), Datum::Integer(2), Datum::Integer(2)]).unwrap(), string("bc"));
+        assert_eq!(call("substr", vec![string("abcde"), Datum::Integer(0), Datum::Integer(2)]).unwrap(), string("a"));
+        assert_eq!(call("substr", vec![string("abcde"), Datum::Integer(1), Datum::Integer(-1)]).err().unwrap().code(), "22011");
+        assert_eq!(lookup_function("substr", 1).err().unwrap().code(), "42883");
+        assert_eq!(lookup_function("nofunc", 1).err().unwrap().code(), "42883");
+    }
+}
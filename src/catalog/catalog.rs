@@ -46,6 +46,14 @@ impl<T: Record> RecordManeger<T> {
         self.records.push(Box::new(record))
     }
 
+    // Remove all records which `f` returns true, and returns the number of
+    // removed records.
+    pub fn remove_records<F>(&mut self, f: F) -> usize where F: Fn(&T) -> bool {
+        let len = self.records.len();
+        self.records.retain(|r| !f(r.as_ref()));
+        len - self.records.len()
+    }
+
     pub fn save(&self, config: &Config) -> io::Result<()> {
         self.save_to_file(config.system_catalog_file_path(&self.name))
     }
@@ -84,6 +92,19 @@ mod tests {
         assert_eq!(db.records[1].name, "bar".to_string());
     }
 
+    #[test]
+    fn test_remove_records() {
+        let mut db: RecordManeger<MiniDatabaseRecord> = RecordManeger::new("mini_database".to_string());
+        db.add_record(MiniDatabaseRecord::new(10001, "baz".to_string()));
+        db.add_record(MiniDatabaseRecord::new(10002, "fooo".to_string()));
+        db.add_record(MiniDatabaseRecord::new(10003, "baz".to_string()));
+
+        assert_eq!(db.remove_records(|r| r.name == "baz"), 2);
+        assert_eq!(db.records.len(), 1);
+        assert_eq!(db.records[0].oid, 10002);
+        assert_eq!(db.remove_records(|r| r.name == "baz"), 0);
+    }
+
     #[test]
     fn test_save_to_file() {
         let tmpfile = NamedTempFile::new().unwrap();
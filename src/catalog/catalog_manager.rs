@@ -1,15 +1,19 @@
 use std::rc::Rc;
 
 use config::{Config};
+use error::Error;
 use catalog::catalog::RecordManeger;
 use catalog::mini_database::MiniDatabaseRecord;
 use catalog::mini_class::MiniClassRecord;
 use catalog::mini_attribute::MiniAttributeRecord;
+use catalog::mini_index::MiniIndexRecord;
+use oid_manager::Oid;
 
 pub struct CatalogManager {
     pub database_rm: RecordManeger<MiniDatabaseRecord>,
     pub class_rm: RecordManeger<MiniClassRecord>,
     pub attribute_rm: RecordManeger<MiniAttributeRecord>,
+    pub index_rm: RecordManeger<MiniIndexRecord>,
 }
 
 impl CatalogManager {
@@ -18,6 +22,28 @@ impl CatalogManager {
             database_rm: RecordManeger::mini_database_rm(&config.clone()),
             class_rm: RecordManeger::mini_class_rm(&config.clone()),
             attribute_rm: RecordManeger::mini_attribute_rm(&config.clone()),
+            index_rm: RecordManeger::mini_index_rm(&config.clone()),
+        }
+    }
+
+    // `RangeVarGetRelid` in pg.
+    //
+    // Returns oids of the database and the table.
+    pub fn get_relation_oids(&self, dbname: &str, table_name: &str) -> Result<(Oid, Oid), Error> {
+        let db_oid = self.database_rm.find_mini_database_oid(dbname)
+                         .ok_or(Error::UndefinedDatabase(format!("{} database does not exist.", dbname)))?;
+        let table_oid = self.class_rm.find_mini_class_oid(db_oid, table_name)
+                            .ok_or(Error::UndefinedTable(format!("{} table does not exist under the {} database.", table_name, dbname)))?;
+
+        Ok((db_oid, table_oid))
+    }
+
+    // `transformColumnRef` in pg.
+    pub fn check_column_exists(&self, db_oid: Oid, table_oid: Oid, table_name: &str, column: &str) -> Result<(), Error> {
+        if self.attribute_rm.attributes(db_oid, table_oid).iter().any(|attr| attr.name == column) {
+            Ok(())
+        } else {
+            Err(Error::UndefinedColumn(format!("{} column does not exist in the {} table.", column, table_name)))
         }
     }
 }
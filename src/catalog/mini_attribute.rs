@@ -10,24 +10,35 @@ use oid_manager::Oid;
 pub enum TypeLabel {
     // Signed 4 bytes integer
     Integer,
+    // Variable length string
+    Text,
+    // Variable length string with limit (the limit is stored as `len`)
+    Varchar,
 }
 
 fn ty_to_u32(ty: &TypeLabel) -> u32 {
     match ty {
         Integer => 1,
+        Text => 2,
+        Varchar => 3,
     }
 }
 
 fn u32_to_ty(i: u32) -> TypeLabel {
     match i {
         1 => Integer,
+        2 => Text,
+        3 => Varchar,
         _ => panic!("Unknown type {}", i)
     }
 }
 
-pub fn ty_byte_len(ty: &TypeLabel) -> u16 {
+// Returns None for variable length types (`attlen = -1` in pg).
+pub fn ty_byte_len(ty: &TypeLabel) -> Option<u16> {
     match ty {
-        Integer => 4,
+        Integer => Some(4),
+        Text => None,
+        Varchar => None,
     }
 }
 
@@ -42,7 +53,8 @@ pub struct MiniAttributeRecord {
     // oid of class this attribute belongs to
     pub class_oid: Oid,
     pub ty: TypeLabel,
-    // Byte length of value
+    // Byte length of value.
+    // For variable length types, this is max length of value (0 means no limit).
     pub len: usize,
 }
 
@@ -90,8 +102,9 @@ impl MiniAttributeRecord {
         }
     }
 
-    fn byte_len(&self) -> u16 {
-        ty_byte_len(&self.ty)
+    // Variable length attribute is stored with length header, see `ty::VARHDRSZ`.
+    pub fn is_varlena(&self) -> bool {
+        ty_byte_len(&self.ty).is_none()
     }
 }
 
@@ -116,6 +129,14 @@ impl RecordManeger<MiniAttributeRecord> {
             .map(|e| *e.clone())
             .collect()
     }
+
+    pub fn remove_attributes(&mut self, db_oid: Oid, table_oid: Oid) -> usize {
+        self.remove_records(|e| e.db_oid == db_oid && e.class_oid == table_oid)
+    }
+
+    pub fn remove_database_attributes(&mut self, db_oid: Oid) -> usize {
+        self.remove_records(|e| e.db_oid == db_oid)
+    }
 }
 
 
@@ -136,6 +157,12 @@ mod tests {
 
         let result2 = MiniAttributeRecord::build_from_line("table1".to_string());
         assert_eq!(result2.is_err(), true);
+
+        let result3 = MiniAttributeRecord::build_from_line("name,10001,10002,3,20".to_string());
+        let ok3 = result3.ok().unwrap();
+        assert_eq!(ok3.ty, Varchar);
+        assert_eq!(ok3.len, 20);
+        assert_eq!(ok3.is_varlena(), true);
     }
 
     #[test]
@@ -10,10 +10,10 @@ use oid_manager::Oid;
 #[derive(Debug)]
 pub struct MiniClassRecord {
     // oid of table
-    oid: Oid,
+    pub oid: Oid,
     // name of table
-    name: String,
-    db_oid: Oid
+    pub name: String,
+    pub db_oid: Oid
 }
 
 impl Record for MiniClassRecord {
@@ -64,6 +64,14 @@ impl RecordManeger<MiniClassRecord> {
         self.find_mini_class(db_oid, name).map(|c| c.oid)
     }
 
+    pub fn remove_mini_class(&mut self, db_oid: Oid, name: &str) -> usize {
+        self.remove_records(|e| e.name == name && e.db_oid == db_oid)
+    }
+
+    pub fn remove_mini_classes(&mut self, db_oid: Oid) -> usize {
+        self.remove_records(|e| e.db_oid == db_oid)
+    }
+
     fn find_mini_class(&self, db_oid: Oid, name: &str) -> Option<&MiniClassRecord> {
         self.records.iter().find(|e| e.name == name && e.db_oid == db_oid).map(|b| b.as_ref())
     }
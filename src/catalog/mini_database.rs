@@ -57,6 +57,10 @@ impl RecordManeger<MiniDatabaseRecord> {
         self.find_mini_database(name).map(|d| d.oid)
     }
 
+    pub fn remove_mini_database(&mut self, name: &str) -> usize {
+        self.remove_records(|e| e.name == name)
+    }
+
     fn find_mini_database(&self, name: &str) -> Option<&MiniDatabaseRecord> {
         self.records.iter().find(|e| e.name == name).map(|b| b.as_ref())
     }
@@ -0,0 +1,122 @@
+// This is for indexes of tables. Index relation itself is stored
+// as a file same as tables.
+
+use std::io::{self, Error, ErrorKind, Write};
+
+use config::Config;
+use catalog::catalog::{Record, RecordManeger};
+use oid_manager::Oid;
+
+#[derive(Debug, Clone)]
+pub struct MiniIndexRecord {
+    // oid of index
+    pub oid: Oid,
+    // name of index
+    pub name: String,
+    pub db_oid: Oid,
+    // oid of the table this index is for
+    pub class_oid: Oid,
+    // name of indexed column
+    pub column: String,
+}
+
+impl Record for MiniIndexRecord {
+    fn build_from_line(line: String) -> io::Result<Box<MiniIndexRecord>> {
+        let c: Vec<&str> = line.split(",").collect();
+
+        if c.len() != 5 {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Line ({}) is invalid.", line)
+            ));
+        }
+
+        let r = MiniIndexRecord {
+            oid: c[0].to_string().parse::<u32>().unwrap(),
+            name: c[1].to_string(),
+            db_oid: c[2].to_string().parse::<u32>().unwrap(),
+            class_oid: c[3].to_string().parse::<u32>().unwrap(),
+            column: c[4].to_string(),
+        };
+        Ok(Box::new(r))
+    }
+
+    fn save_to_file(&self, w: &mut Write) -> io::Result<usize> {
+        w.write(format!(
+            "{},{},{},{},{}",
+            self.oid,
+            self.name,
+            self.db_oid,
+            self.class_oid,
+            self.column
+        ).as_bytes())
+    }
+}
+
+impl MiniIndexRecord {
+    pub fn new(oid: Oid, name: String, db_oid: Oid, class_oid: Oid, column: String) -> MiniIndexRecord {
+        MiniIndexRecord {
+            oid: oid,
+            name: name,
+            db_oid: db_oid,
+            class_oid: class_oid,
+            column: column
+        }
+    }
+}
+
+impl RecordManeger<MiniIndexRecord> {
+    pub fn mini_index_rm(config: &Config) -> RecordManeger<MiniIndexRecord> {
+        RecordManeger::build_from_config("mini_index".to_string(), config).unwrap()
+    }
+
+    pub fn find_mini_index(&self, db_oid: Oid, name: &str) -> Option<&MiniIndexRecord> {
+        self.records.iter().find(|e| e.name == name && e.db_oid == db_oid).map(|b| b.as_ref())
+    }
+
+    // Indexes of the table. `RelationGetIndexList` in pg.
+    pub fn indexes(&self, db_oid: Oid, table_oid: Oid) -> Vec<&MiniIndexRecord> {
+        self.records
+            .iter()
+            .filter(|e| e.db_oid == db_oid && e.class_oid == table_oid)
+            .map(|e| e.as_ref())
+            .collect()
+    }
+
+    pub fn remove_indexes(&mut self, db_oid: Oid, table_oid: Oid) -> usize {
+        self.remove_records(|e| e.db_oid == db_oid && e.class_oid == table_oid)
+    }
+
+    pub fn remove_database_indexes(&mut self, db_oid: Oid) -> usize {
+        self.remove_records(|e| e.db_oid == db_oid)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_build_from_line() {
+        let result1 = MiniIndexRecord::build_from_line("10004,idx1,10010,10011,id".to_string());
+        assert_eq!(result1.is_ok(), true);
+        let ok1 = result1.ok().unwrap();
+        assert_eq!(ok1.oid, 10004);
+        assert_eq!(ok1.name, "idx1".to_string());
+        assert_eq!(ok1.db_oid, 10010);
+        assert_eq!(ok1.class_oid, 10011);
+        assert_eq!(ok1.column, "id".to_string());
+
+        let result2 = MiniIndexRecord::build_from_line("10004,idx1".to_string());
+        assert_eq!(result2.is_err(), true);
+    }
+
+    #[test]
+    fn test_record_save_to_file() {
+        let record = MiniIndexRecord::new(10005, "idx1".to_string(), 10006, 10007, "id".to_string());
+        let mut v = Vec::new();
+        record.save_to_file(&mut v).unwrap();
+        assert_eq!(v, b"10005,idx1,10006,10007,id");
+    }
+}
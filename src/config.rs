@@ -2,23 +2,39 @@ use std::path::{Path, PathBuf};
 
 use oid_manager::{Oid, oid_to_string};
 
+#[derive(Clone)]
 pub struct Config {
     root_dir_name: String,
     block_size: u16,
+    work_mem: usize,
 }
 
 pub const DEFAULT_BLOCK_SIZE: u16 = 1024 * 8;
 // NBuffers in pg.
 pub const N_BUFFERS: usize = 10;
+// `RELSEG_SIZE` in pg. Number of blocks in a segment file (1 GB).
+pub const RELSEG_SIZE: u32 = 1024 * 1024 * 1024 / DEFAULT_BLOCK_SIZE as u32;
+// `work_mem` in pg (kB). Memory used by a sort before writing tuples to
+// temporary files.
+pub const DEFAULT_WORK_MEM: usize = 4 * 1024;
 
 impl Config {
     pub fn new(root_dir_name: String) -> Config {
         Config {
             root_dir_name: root_dir_name,
             block_size: DEFAULT_BLOCK_SIZE,
+            work_mem: DEFAULT_WORK_MEM,
         }
     }
 
+    pub fn work_mem(&self) -> usize {
+        self.work_mem
+    }
+
+    pub fn set_work_mem(&mut self, work_mem: usize) {
+        self.work_mem = work_mem;
+    }
+
     // [Catalogs]
     // root directory / "global" / table name /
     // Under the "table name"
@@ -27,11 +43,29 @@ impl Config {
     // [User defined tables]
     // root directory / "base" / database oid / table oid /
     // Under the "table name"
-    // * "data": table file
+    // * "data", "data.1", ...: segments of table file
+    // * "fsm": free space map
+    //
+    // [WAL]
+    // root directory / "wal" / segment number (hex)
+    // root directory / "global" / "control": redo pointer of the last checkpoint
+    //
+    // [Transactions]
+    // root directory / "global" / "clog": status of each transaction
+    //
+    // [Temporary files]
+    // root directory / "pgsql_tmp" / "pgsql_tmp" pid "." number: sorted runs
+    //
+    // [Lock file]
+    // root directory / "postmaster.pid": pid of the process using the directory
     pub fn root_dir_path(&self) -> PathBuf {
         Path::new(&self.root_dir_name).to_path_buf()
     }
 
+    pub fn lock_file_path(&self) -> PathBuf {
+        self.root_dir_path().join("postmaster.pid")
+    }
+
     pub fn base_dir_path(&self) -> PathBuf {
         self.root_dir_path().join("base")
     }
@@ -44,6 +78,26 @@ impl Config {
         self.global_dir_path().join("oid")
     }
 
+    pub fn control_file_path(&self) -> PathBuf {
+        self.global_dir_path().join("control")
+    }
+
+    pub fn clog_file_path(&self) -> PathBuf {
+        self.global_dir_path().join("clog")
+    }
+
+    pub fn temp_dir_path(&self) -> PathBuf {
+        self.root_dir_path().join("pgsql_tmp")
+    }
+
+    pub fn wal_dir_path(&self) -> PathBuf {
+        self.root_dir_path().join("wal")
+    }
+
+    pub fn wal_segment_file_path(&self, segno: u64) -> PathBuf {
+        self.wal_dir_path().join(format!("{:016X}", segno))
+    }
+
     pub fn system_catalog_dir_path<P: AsRef<Path>>(&self, tablename: P) -> PathBuf {
         self.global_dir_path().join(tablename)
     }
@@ -63,6 +117,19 @@ impl Config {
     pub fn data_file_path(&self, db_oid: Oid, table_oid: Oid) -> PathBuf {
         self.table_dir_path(db_oid, table_oid).join("data")
     }
+
+    // `data` for the first segment, `data.N` for others.
+    pub fn data_segment_file_path(&self, db_oid: Oid, table_oid: Oid, segno: u32) -> PathBuf {
+        if segno == 0 {
+            self.data_file_path(db_oid, table_oid)
+        } else {
+            self.table_dir_path(db_oid, table_oid).join(format!("data.{}", segno))
+        }
+    }
+
+    pub fn fsm_file_path(&self, db_oid: Oid, table_oid: Oid) -> PathBuf {
+        self.table_dir_path(db_oid, table_oid).join("fsm")
+    }
 }
 
 #[cfg(test)]
@@ -97,6 +164,42 @@ mod tests {
         assert_eq!(config.oid_file_path(), PathBuf::from("/mydb/global/oid"));
     }
 
+    #[test]
+    fn test_control_file_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.control_file_path(), PathBuf::from("/mydb/global/control"));
+    }
+
+    #[test]
+    fn test_clog_file_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.clog_file_path(), PathBuf::from("/mydb/global/clog"));
+    }
+
+    #[test]
+    fn test_temp_dir_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.temp_dir_path(), PathBuf::from("/mydb/pgsql_tmp"));
+    }
+
+    #[test]
+    fn test_lock_file_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.lock_file_path(), PathBuf::from("/mydb/postmaster.pid"));
+    }
+
+    #[test]
+    fn test_wal_segment_file_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.wal_dir_path(), PathBuf::from("/mydb/wal"));
+        assert_eq!(config.wal_segment_file_path(26), PathBuf::from("/mydb/wal/000000000000001A"));
+    }
+
     #[test]
     fn test_system_catalog_dir_path() {
         let config = Config::new("/mydb".to_string());
@@ -130,4 +233,19 @@ mod tests {
 
         assert_eq!(config.data_file_path(10001, 10002), PathBuf::from("/mydb/base/10001/10002/data"));
     }
+
+    #[test]
+    fn test_data_segment_file_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.data_segment_file_path(10001, 10002, 0), PathBuf::from("/mydb/base/10001/10002/data"));
+        assert_eq!(config.data_segment_file_path(10001, 10002, 2), PathBuf::from("/mydb/base/10001/10002/data.2"));
+    }
+
+    #[test]
+    fn test_fsm_file_path() {
+        let config = Config::new("/mydb".to_string());
+
+        assert_eq!(config.fsm_file_path(10001, 10002), PathBuf::from("/mydb/base/10001/10002/fsm"));
+    }
 }
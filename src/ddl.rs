@@ -1,13 +1,22 @@
 use std::fs;
-use std::io::{self, Error, ErrorKind};
+use std::io::{self, ErrorKind};
 use std::rc::Rc;
 use std::sync::RwLock;
 
-use config::Config;
+use ast::ColumnDef;
+use config::{Config};
+use error::Error;
 use catalog::catalog::RecordManeger;
 use catalog::mini_class::MiniClassRecord;
 use catalog::mini_database::MiniDatabaseRecord;
 use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+use catalog::mini_index::MiniIndexRecord;
+use buffer_manager::{BufferManager, RelFileNode};
+use storage_manager::RelationManager;
+use tqual::Snapshot;
+use executor::exec_indexing::IndexInfo;
+use executor::node_seqscan::ScanState;
+use executor::plan_node::PlanNode;
 use oid_manager::{OidManager, Oid};
 
 pub struct CreateDatabaseCommand {
@@ -20,6 +29,19 @@ pub struct CreateTableCommand {
     oid_manager: RwLock<OidManager>,
 }
 
+pub struct CreateIndexCommand {
+    config: Rc<Config>,
+    oid_manager: RwLock<OidManager>,
+}
+
+pub struct DropDatabaseCommand {
+    config: Rc<Config>,
+}
+
+pub struct DropTableCommand {
+    config: Rc<Config>,
+}
+
 impl CreateDatabaseCommand {
     pub fn new(config: Rc<Config>, oid_manager: RwLock<OidManager>) -> CreateDatabaseCommand {
         CreateDatabaseCommand {
@@ -28,22 +50,33 @@ impl CreateDatabaseCommand {
         }
     }
 
-    pub fn execute(&self, dbname: &str) -> io::Result<()> {
-        let oid = self.oid_manager.write().unwrap().get_new_oid();
+    pub fn execute(&self, dbname: &str) -> Result<(), Error> {
         self.check_base_dir()?;
+        self.check_database_not_exist(dbname)?;
+        let oid = self.oid_manager.write().unwrap().get_new_oid();
         self.create_database_dir(oid)?;
-        self.add_record(dbname, oid);
+        self.add_record(dbname, oid)?;
         Ok(())
     }
 
-    fn check_base_dir(&self) -> io::Result<()> {
+    fn check_base_dir(&self) -> Result<(), Error> {
         if self.config.base_dir_path().exists() {
             Ok(())
         } else {
-            Err(Error::new(
-                ErrorKind::Other,
+            Err(Error::Io(io::Error::new(
+                ErrorKind::NotFound,
                 format!("Base dir ({}) does not exist.", self.config.base_dir_path().display())
-            ))
+            )))
+        }
+    }
+
+    fn check_database_not_exist(&self, dbname: &str) -> Result<(), Error> {
+        let db: RecordManeger<MiniDatabaseRecord> = RecordManeger::mini_database_rm(&self.config);
+
+        if db.find_mini_database_oid(dbname).is_some() {
+            Err(Error::DuplicateDatabase(format!("{} database already exists.", dbname)))
+        } else {
+            Ok(())
         }
     }
 
@@ -51,11 +84,11 @@ impl CreateDatabaseCommand {
         fs::create_dir(self.config.database_dir_path(db_oid))
     }
 
-    fn add_record(&self, dbname: &str, db_oid: Oid) {
+    fn add_record(&self, dbname: &str, db_oid: Oid) -> io::Result<()> {
         let mut db: RecordManeger<MiniDatabaseRecord> = RecordManeger::mini_database_rm(&self.config);
         let record = MiniDatabaseRecord::new(db_oid, dbname.to_string());
         db.add_record(record);
-        db.save(&self.config);
+        db.save(&self.config)
     }
 }
 
@@ -67,28 +100,60 @@ impl CreateTableCommand {
         }
     }
 
-    pub fn execute(&self, dbname: &str, tablename: &str) -> io::Result<()> {
+    pub fn execute(&self, dbname: &str, tablename: &str, columns: &Vec<ColumnDef>) -> Result<(), Error> {
         let db: RecordManeger<MiniDatabaseRecord> = RecordManeger::mini_database_rm(&self.config);
-        let db_oid = db.find_mini_database_oid(dbname)
-                       .expect(&format!("{} database should be defined.", dbname));
-        let table_oid = self.oid_manager.write().unwrap().get_new_oid();
+        let db_oid = match db.find_mini_database_oid(dbname) {
+            Some(oid) => oid,
+            None => return Err(Error::UndefinedDatabase(format!("{} database should be defined.", dbname)))
+        };
 
         self.check_base_dir()?;
+        self.check_table_not_exist(db_oid, dbname, tablename)?;
+        self.check_columns(columns)?;
+
+        let table_oid = self.oid_manager.write().unwrap().get_new_oid();
         self.create_table_dir(db_oid, table_oid)?;
-        self.add_record_to_mini_class(db_oid, tablename, table_oid);
-        self.add_record_to_mini_attribute("id", db_oid, table_oid, TypeLabel::Integer, 4);
-        self.add_record_to_mini_attribute("age", db_oid, table_oid, TypeLabel::Integer, 4);
+        self.add_record_to_mini_class(db_oid, tablename, table_oid)?;
+
+        for column in columns.iter() {
+            self.add_record_to_mini_attribute(&column.name, db_oid, table_oid, column.ty.clone(), column.len)?;
+        }
+
         Ok(())
     }
 
-    fn check_base_dir(&self) -> io::Result<()> {
+    fn check_table_not_exist(&self, db_oid: Oid, dbname: &str, tablename: &str) -> Result<(), Error> {
+        let class: RecordManeger<MiniClassRecord> = RecordManeger::mini_class_rm(&self.config);
+
+        if class.find_mini_class_oid(db_oid, tablename).is_some() {
+            Err(Error::DuplicateTable(format!("{} table already exists under the {} database.", tablename, dbname)))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_columns(&self, columns: &Vec<ColumnDef>) -> Result<(), Error> {
+        if columns.is_empty() {
+            return Err(Error::InvalidTableDefinition("Table should have at least one column.".to_string()));
+        }
+
+        for (i, column) in columns.iter().enumerate() {
+            if columns.iter().skip(i + 1).any(|c| c.name == column.name) {
+                return Err(Error::DuplicateColumn(format!("Column ({}) is specified more than once.", column.name)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_base_dir(&self) -> Result<(), Error> {
         if self.config.base_dir_path().exists() {
             Ok(())
         } else {
-            Err(Error::new(
-                ErrorKind::Other,
+            Err(Error::Io(io::Error::new(
+                ErrorKind::NotFound,
                 format!("Base dir ({}) does not exist.", self.config.base_dir_path().display())
-            ))
+            )))
         }
     }
 
@@ -96,14 +161,14 @@ impl CreateTableCommand {
         fs::create_dir(self.config.table_dir_path(db_oid, table_oid))
     }
 
-    fn add_record_to_mini_class(&self, db_oid: Oid, tablename: &str, oid: Oid) {
+    fn add_record_to_mini_class(&self, db_oid: Oid, tablename: &str, oid: Oid) -> io::Result<()> {
         let mut db: RecordManeger<MiniClassRecord> = RecordManeger::mini_class_rm(&self.config);
         let record = MiniClassRecord::new(oid, tablename.to_string(), db_oid);
         db.add_record(record);
-        db.save(&self.config);
+        db.save(&self.config)
     }
 
-    fn add_record_to_mini_attribute(&self, name: &str, db_oid: Oid, table_oid: Oid, ty: TypeLabel, len: usize) {
+    fn add_record_to_mini_attribute(&self, name: &str, db_oid: Oid, table_oid: Oid, ty: TypeLabel, len: usize) -> io::Result<()> {
         let mut db: RecordManeger<MiniAttributeRecord> = RecordManeger::mini_attribute_rm(&self.config);
         let record = MiniAttributeRecord::new(
             name.to_string(),
@@ -113,6 +178,215 @@ impl CreateTableCommand {
             len,
         );
         db.add_record(record);
-        db.save(&self.config);
+        db.save(&self.config)
+    }
+}
+
+impl CreateIndexCommand {
+    pub fn new(config: Rc<Config>, oid_manager: RwLock<OidManager>) -> CreateIndexCommand {
+        CreateIndexCommand {
+            config: config,
+            oid_manager: oid_manager,
+        }
+    }
+
+    // `DefineIndex` in pg.
+    pub fn execute(&self, dbname: &str, tablename: &str, indexname: &str, column: &str, snapshot: &Snapshot, bm: &RwLock<BufferManager>) -> Result<(), Error> {
+        let db: RecordManeger<MiniDatabaseRecord> = RecordManeger::mini_database_rm(&self.config);
+        let class: RecordManeger<MiniClassRecord> = RecordManeger::mini_class_rm(&self.config);
+        let attribute: RecordManeger<MiniAttributeRecord> = RecordManeger::mini_attribute_rm(&self.config);
+        let mut index: RecordManeger<MiniIndexRecord> = RecordManeger::mini_index_rm(&self.config);
+
+        let db_oid = match db.find_mini_database_oid(dbname) {
+            Some(oid) => oid,
+            None => return Err(Error::UndefinedDatabase(format!("{} database should be defined.", dbname)))
+        };
+        let table_oid = match class.find_mini_class_oid(db_oid, tablename) {
+            Some(oid) => oid,
+            None => return Err(Error::UndefinedTable(format!("{} table does not exist under the {} database.", tablename, dbname)))
+        };
+
+        if !attribute.attributes(db_oid, table_oid).iter().any(|attr| attr.name == column) {
+            return Err(Error::UndefinedColumn(format!("{} column does not exist in the {} table.", column, tablename)));
+        }
+
+        // Indexes and tables share the namespace like pg_class in pg.
+        if index.find_mini_index(db_oid, indexname).is_some() || class.find_mini_class_oid(db_oid, indexname).is_some() {
+            return Err(Error::DuplicateTable(format!("{} relation already exists under the {} database.", indexname, dbname)));
+        }
+
+        let index_oid = self.oid_manager.write().unwrap().get_new_oid();
+        let record = MiniIndexRecord::new(index_oid, indexname.to_string(), db_oid, table_oid, column.to_string());
+        fs::create_dir(self.config.table_dir_path(db_oid, index_oid))?;
+
+        if let Err(e) = self.index_build(&record, &attribute, snapshot, bm) {
+            fs::remove_dir_all(self.config.table_dir_path(db_oid, index_oid))?;
+            return Err(e);
+        }
+
+        index.add_record(record);
+        index.save(&self.config)?;
+        Ok(())
+    }
+
+    // `index_build` in pg.
+    //
+    // Create an empty index and insert all existing tuples of the table.
+    // CREATE INDEX can not run inside a transaction block, so only tuples
+    // of committed transactions are inserted.
+    fn index_build(&self, record: &MiniIndexRecord, attribute: &RecordManeger<MiniAttributeRecord>, snapshot: &Snapshot, bm: &RwLock<BufferManager>) -> Result<(), Error> {
+        let index_info = IndexInfo::new(record, attribute);
+        let mut rmgr = RelationManager::new(self.config.clone());
+        let relation = rmgr.get_relation(record.db_oid, record.class_oid);
+        let qual = None;
+
+        index_info.btree().init(&mut bm.write().unwrap())?;
+
+        let mut scan = ScanState::new(relation, attribute, bm, &qual, snapshot);
+
+        loop {
+            match scan.exec()? {
+                Some(slot) => {
+                    index_info.insert(&mut bm.write().unwrap(), slot, slot.tid())?;
+                },
+                None => break
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DropDatabaseCommand {
+    pub fn new(config: Rc<Config>) -> DropDatabaseCommand {
+        DropDatabaseCommand {
+            config: config,
+        }
+    }
+
+    // `dropdb` in pg.
+    //
+    // If `missing_ok` is true, it is not an error that the database does not exist.
+    pub fn execute(&self, dbname: &str, missing_ok: bool, bm: &RwLock<BufferManager>) -> Result<(), Error> {
+        let mut db: RecordManeger<MiniDatabaseRecord> = RecordManeger::mini_database_rm(&self.config);
+        let db_oid = match db.find_mini_database_oid(dbname) {
+            Some(oid) => oid,
+            None => {
+                if missing_ok {
+                    warn!("{} database does not exist, skipping", dbname);
+                    return Ok(());
+                }
+
+                return Err(Error::UndefinedDatabase(format!("{} database does not exist.", dbname)));
+            }
+        };
+
+        // Pages of the database should not be written after its files are
+        // removed, and WAL records of them should not be replayed.
+        {
+            let mut bufmrg = bm.write().unwrap();
+            bufmrg.drop_database_buffers(db_oid);
+            bufmrg.create_checkpoint()?;
+        }
+
+        self.remove_database_dir(db_oid)?;
+
+        let mut class: RecordManeger<MiniClassRecord> = RecordManeger::mini_class_rm(&self.config);
+        class.remove_mini_classes(db_oid);
+        class.save(&self.config)?;
+
+        let mut attribute: RecordManeger<MiniAttributeRecord> = RecordManeger::mini_attribute_rm(&self.config);
+        attribute.remove_database_attributes(db_oid);
+        attribute.save(&self.config)?;
+
+        let mut index: RecordManeger<MiniIndexRecord> = RecordManeger::mini_index_rm(&self.config);
+        index.remove_database_indexes(db_oid);
+        index.save(&self.config)?;
+
+        db.remove_mini_database(dbname);
+        db.save(&self.config)?;
+        Ok(())
+    }
+
+    fn remove_database_dir(&self, db_oid: Oid) -> io::Result<()> {
+        let path = self.config.database_dir_path(db_oid);
+
+        if path.exists() {
+            fs::remove_dir_all(path)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl DropTableCommand {
+    pub fn new(config: Rc<Config>) -> DropTableCommand {
+        DropTableCommand {
+            config: config,
+        }
+    }
+
+    // `RemoveRelations` in pg.
+    //
+    // If `missing_ok` is true, it is not an error that the table does not exist.
+    pub fn execute(&self, dbname: &str, tablename: &str, missing_ok: bool, bm: &RwLock<BufferManager>) -> Result<(), Error> {
+        let db: RecordManeger<MiniDatabaseRecord> = RecordManeger::mini_database_rm(&self.config);
+        let mut class: RecordManeger<MiniClassRecord> = RecordManeger::mini_class_rm(&self.config);
+        let oids = db.find_mini_database_oid(dbname).and_then(|db_oid| {
+            class.find_mini_class_oid(db_oid, tablename).map(|table_oid| (db_oid, table_oid))
+        });
+        let (db_oid, table_oid) = match oids {
+            Some(oids) => oids,
+            None => {
+                if missing_ok {
+                    warn!("{}.{} table does not exist, skipping", dbname, tablename);
+                    return Ok(());
+                }
+
+                return Err(Error::UndefinedTable(format!("{} table does not exist under the {} database.", tablename, dbname)));
+            }
+        };
+
+        // Indexes of the table are dropped together.
+        let mut index: RecordManeger<MiniIndexRecord> = RecordManeger::mini_index_rm(&self.config);
+        let mut oids = vec![table_oid];
+        oids.extend(index.indexes(db_oid, table_oid).iter().map(|i| i.oid));
+
+        // Same as DROP DATABASE, discard pages of the relations before
+        // their files are removed.
+        {
+            let mut bufmrg = bm.write().unwrap();
+
+            for oid in oids.iter() {
+                bufmrg.drop_rel_file_node_buffers(&RelFileNode { table_oid: *oid, db_oid: db_oid });
+            }
+
+            bufmrg.create_checkpoint()?;
+        }
+
+        for oid in oids.iter() {
+            self.remove_table_dir(db_oid, *oid)?;
+        }
+
+        index.remove_indexes(db_oid, table_oid);
+        index.save(&self.config)?;
+
+        let mut attribute: RecordManeger<MiniAttributeRecord> = RecordManeger::mini_attribute_rm(&self.config);
+        attribute.remove_attributes(db_oid, table_oid);
+        attribute.save(&self.config)?;
+
+        class.remove_mini_class(db_oid, tablename);
+        class.save(&self.config)?;
+        Ok(())
+    }
+
+    fn remove_table_dir(&self, db_oid: Oid, table_oid: Oid) -> io::Result<()> {
+        let path = self.config.table_dir_path(db_oid, table_oid);
+
+        if path.exists() {
+            fs::remove_dir_all(path)
+        } else {
+            Ok(())
+        }
     }
 }
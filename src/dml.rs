@@ -3,16 +3,27 @@ use std::sync::RwLock;
 
 use config::{Config};
 use tuple::{TupleTableSlot, KeyValue};
+use ty::Integer;
 use buffer_manager::{BufferManager};
+use builtins::lookup_function;
 use storage_manager::{RelationManager};
 use executor::node_agg::{CountState};
 use executor::node_delete::{DeleteState};
+use executor::exec_expr::ProjectionInfo;
+use executor::exec_indexing::{exec_open_indices};
 use executor::node_insert::{InsertState};
-use executor::node_seqscan::{ScanState};
 use executor::node_sort::{SortState};
+use executor::node_update::{UpdateState};
 use executor::plan_node::PlanNode;
+use executor::tuptable::{ColumnDesc, Dest, Portal, QueryResult, Row};
 use catalog::catalog_manager::CatalogManager;
-use ast::Expr;
+use catalog::mini_attribute::TypeLabel;
+use error::Error;
+use oid_manager::Oid;
+use planner::create_scan_plan;
+use tqual::Snapshot;
+use transam::TransactionId;
+use ast::{Expr, ResTarget, SortBy};
 
 pub struct InsertIntoCommand {
     config: Rc<Config>,
@@ -30,6 +41,49 @@ pub struct DeleteCommand {
     config: Rc<Config>,
 }
 
+pub struct UpdateCommand {
+    config: Rc<Config>,
+}
+
+// Check that columns referred by the expression exist in the table and
+// functions called by it exist, so that the executor does not fail in the
+// middle of the scan.
+fn check_column_refs(cmgr: &CatalogManager, db_oid: Oid, table_oid: Oid, table_name: &str, expr: &Option<Box<Expr>>) -> Result<(), Error> {
+    match expr {
+        Some(e) => check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e),
+        None => Ok(()),
+    }
+}
+
+fn check_expr_column_refs(cmgr: &CatalogManager, db_oid: Oid, table_oid: Oid, table_name: &str, expr: &Expr) -> Result<(), Error> {
+    match expr {
+        Expr::ColumnRef(col_name) => cmgr.check_column_exists(db_oid, table_oid, table_name, col_name),
+        Expr::OpEq(e1, e2) | Expr::OpNe(e1, e2) |
+        Expr::OpLt(e1, e2) | Expr::OpLe(e1, e2) | Expr::OpGt(e1, e2) | Expr::OpGe(e1, e2) |
+        Expr::And(e1, e2) | Expr::Or(e1, e2) |
+        Expr::OpAdd(e1, e2) | Expr::OpSub(e1, e2) | Expr::OpMul(e1, e2) | Expr::OpDiv(e1, e2) | Expr::OpMod(e1, e2) => {
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e1)?;
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e2)
+        },
+        Expr::Between(e, lower, upper) => {
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e)?;
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, lower)?;
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, upper)
+        },
+        Expr::In(e, list) => {
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e)?;
+            list.iter().map(|e| check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e)).collect()
+        },
+        Expr::FuncCall(name, args) => {
+            lookup_function(name, args.len())?;
+            args.iter().map(|e| check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e)).collect()
+        },
+        Expr::Not(e) | Expr::UMinus(e) | Expr::IsNull(e) | Expr::IsNotNull(e) => check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e),
+        Expr::All | Expr::Count => Err(Error::FeatureNotSupported(format!("{:?} is not supported in expressions.", expr))),
+        Expr::Bool(_) | Expr::Number(_) | Expr::String(_) | Expr::Null => Ok(()),
+    }
+}
+
 impl InsertIntoCommand {
     pub fn new(config: Rc<Config>) -> InsertIntoCommand {
         InsertIntoCommand {
@@ -37,22 +91,20 @@ impl InsertIntoCommand {
         }
     }
 
-    pub fn execute(&self, dbname: &str, table_name: &str, key_values: Vec<KeyValue>, cmgr: &CatalogManager) -> Result<(), String> {
-        let db_oid = cmgr.database_rm.find_mini_database_oid(dbname)
-                       .expect(&format!("{} database should be defined.", dbname));
-        let table_oid = cmgr.class_rm.find_mini_class_oid(db_oid, table_name)
-                             .expect(&format!("{} table should be defined under the {} database. ", table_name, dbname));
+    pub fn execute(&self, dbname: &str, table_name: &str, key_values: Vec<KeyValue>, cmgr: &CatalogManager, xid: TransactionId, bm: &RwLock<BufferManager>) -> Result<QueryResult, Error> {
+        let (db_oid, table_oid) = cmgr.get_relation_oids(dbname, table_name)?;
         let rm = &cmgr.attribute_rm;
         let mut rmgr = RelationManager::new(self.config.clone());
-        let bm = RwLock::new(BufferManager::new(1, self.config.clone()));
         let relation = rmgr.get_relation(db_oid, table_oid);
         let mut slot = TupleTableSlot::new(rm.attributes_clone(db_oid, table_oid));
         slot.update_tuple(key_values)?;
 
-        let mut insert = InsertState::new(relation, &slot, &bm);
-        insert.exec();
+        let indices = exec_open_indices(cmgr, db_oid, table_oid);
 
-        Ok(())
+        let mut insert = InsertState::new(relation, &slot, &indices, xid, bm);
+        insert.exec()?;
+
+        Ok(QueryResult::command("INSERT", 1))
     }
 }
 
@@ -63,57 +115,46 @@ impl SelectFromCommand {
         }
     }
 
-    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, qual: &Option<Box<Expr>>, sort: &Option<String>) -> Result<(), String> {
-        let db_oid = cmgr.database_rm.find_mini_database_oid(dbname)
-                       .expect(&format!("{} database should be defined.", dbname));
-        let table_oid = cmgr.class_rm.find_mini_class_oid(db_oid, table_name)
-                             .expect(&format!("{} table should be defined under the {} database. ", table_name, dbname));
+    // Rows are fetched by `dest` while the plan is open.
+    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, target_list: &Vec<ResTarget>, qual: &Option<Box<Expr>>, sort: &Vec<SortBy>, snapshot: &Snapshot, bm: &RwLock<BufferManager>, dest: &mut Dest) -> Result<QueryResult, Error> {
+        let (db_oid, table_oid) = cmgr.get_relation_oids(dbname, table_name)?;
+        check_column_refs(cmgr, db_oid, table_oid, table_name, qual)?;
+
+        for target in target_list.iter() {
+            match target.val.as_ref() {
+                Expr::All => {},
+                e => check_expr_column_refs(cmgr, db_oid, table_oid, table_name, e)?,
+            }
+        }
+
+        for sort_key in sort.iter() {
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, &sort_key.node)?;
+        }
+
         let rm = &cmgr.attribute_rm;
         let mut rmgr = RelationManager::new(self.config.clone());
         let relation = rmgr.get_relation(db_oid, table_oid);
-        let bm = RwLock::new(BufferManager::new(1, self.config.clone()));
-
-        match sort {
-            Some(col_name) => {
-                let mut scan = ScanState::new(relation, &rm, &bm, qual);
-                let mut sort_state = SortState::new(&mut scan, col_name.clone());
-
-                loop {
-                    let opt = sort_state.exec();
-
-                    match opt {
-                        Some(slot) => {
-                            for i in 0..(slot.attrs_count()) {
-                                let ty = slot.get_column(i);
-                                print!("{:?} ", ty.as_string());
-                            }
-                            print!("\n");
-                        },
-                        None => break
-                    }
-                }
-            },
-            None => {
-                let mut scan = ScanState::new(relation, &rm, &bm, qual);
-
-                loop {
-                    let opt = scan.exec();
-
-                    match opt {
-                        Some(slot) => {
-                            for i in 0..(slot.attrs_count()) {
-                                let ty = slot.get_column(i);
-                                print!("{:?} ", ty.as_string());
-                            }
-                            print!("\n");
-                        },
-                        None => break
-                    }
-                }
+        let indices = exec_open_indices(cmgr, db_oid, table_oid);
+        let projection = ProjectionInfo::new(target_list, &rm.attributes(db_oid, table_oid))?;
+        let mut scan = create_scan_plan(relation, &rm, &indices, bm, qual, snapshot);
+        let mut sort_state;
+        let plan: &mut PlanNode = if sort.is_empty() {
+            &mut *scan
+        } else {
+            sort_state = SortState::new(&mut *scan, sort, self.config.clone());
+            &mut sort_state
+        };
+
+        let mut next_row = || -> Result<Option<Row>, Error> {
+            match plan.exec()? {
+                Some(slot) => Ok(Some(projection.exec_project(slot)?)),
+                None => Ok(None),
             }
-        }
+        };
+        let mut portal = Portal::new(projection.columns(), &mut next_row);
+        dest(&mut portal)?;
 
-        Ok(())
+        Ok(QueryResult::new("SELECT", portal.columns().to_vec(), portal.processed()))
     }
 }
 
@@ -124,22 +165,24 @@ impl CountCommand {
         }
     }
 
-    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, qual: &Option<Box<Expr>>) -> Result<(), String> {
-        let db_oid = cmgr.database_rm.find_mini_database_oid(dbname)
-                       .expect(&format!("{} database should be defined.", dbname));
-        let table_oid = cmgr.class_rm.find_mini_class_oid(db_oid, table_name)
-                             .expect(&format!("{} table should be defined under the {} database. ", table_name, dbname));
+    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, col_name: &str, qual: &Option<Box<Expr>>, snapshot: &Snapshot, bm: &RwLock<BufferManager>, dest: &mut Dest) -> Result<QueryResult, Error> {
+        let (db_oid, table_oid) = cmgr.get_relation_oids(dbname, table_name)?;
+        check_column_refs(cmgr, db_oid, table_oid, table_name, qual)?;
         let rm = &cmgr.attribute_rm;
         let mut rmgr = RelationManager::new(self.config.clone());
         let relation = rmgr.get_relation(db_oid, table_oid);
-        let bm = RwLock::new(BufferManager::new(1, self.config.clone()));
-        let mut scan = ScanState::new(relation, &rm, &bm, qual);
-        let mut count = CountState::new(&mut scan);
+        let indices = exec_open_indices(cmgr, db_oid, table_oid);
+        let mut scan = create_scan_plan(relation, &rm, &indices, bm, qual, snapshot);
+        let mut count = CountState::new(&mut *scan);
 
-        count.exec();
-        println!("Count: {}", count.result);
+        count.exec()?;
 
-        Ok(())
+        let mut row: Option<Row> = Some(vec![Box::new(Integer { elem: count.result as i32 })]);
+        let mut next_row = || Ok(row.take());
+        let mut portal = Portal::new(vec![ColumnDesc::new(col_name.to_string(), TypeLabel::Integer)], &mut next_row);
+        dest(&mut portal)?;
+
+        Ok(QueryResult::new("SELECT", portal.columns().to_vec(), portal.processed()))
     }
 }
 
@@ -150,21 +193,48 @@ impl DeleteCommand {
         }
     }
 
-    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, qual: &Option<Box<Expr>>) -> Result<(), String> {
-        let db_oid = cmgr.database_rm.find_mini_database_oid(dbname)
-                       .expect(&format!("{} database should be defined.", dbname));
-        let table_oid = cmgr.class_rm.find_mini_class_oid(db_oid, table_name)
-                             .expect(&format!("{} table should be defined under the {} database. ", table_name, dbname));
+    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, qual: &Option<Box<Expr>>, snapshot: &Snapshot, bm: &RwLock<BufferManager>) -> Result<QueryResult, Error> {
+        let (db_oid, table_oid) = cmgr.get_relation_oids(dbname, table_name)?;
+        check_column_refs(cmgr, db_oid, table_oid, table_name, qual)?;
         let rm = &cmgr.attribute_rm;
         let mut rmgr = RelationManager::new(self.config.clone());
         let relation = rmgr.get_relation(db_oid, table_oid);
-        let bm = RwLock::new(BufferManager::new(1, self.config.clone()));
-        let mut scan = ScanState::new(relation, &rm, &bm, qual);
-        let mut delete = DeleteState::new(relation, &mut scan, &bm);
+        let indices = exec_open_indices(cmgr, db_oid, table_oid);
+        let mut scan = create_scan_plan(relation, &rm, &indices, bm, qual, snapshot);
+        let mut delete = DeleteState::new(relation, &mut *scan, snapshot.xid, bm);
+
+        delete.exec()?;
+
+        Ok(QueryResult::command("DELETE", delete.count))
+    }
+}
+
+impl UpdateCommand {
+    pub fn new(config: Rc<Config>) -> UpdateCommand {
+        UpdateCommand {
+            config: config,
+        }
+    }
+
+    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, target_list: &Vec<(String, Box<Expr>)>, qual: &Option<Box<Expr>>, snapshot: &Snapshot, bm: &RwLock<BufferManager>) -> Result<QueryResult, Error> {
+        let (db_oid, table_oid) = cmgr.get_relation_oids(dbname, table_name)?;
+        let rm = &cmgr.attribute_rm;
+
+        for (col_name, expr) in target_list.iter() {
+            cmgr.check_column_exists(db_oid, table_oid, table_name, col_name)?;
+            check_expr_column_refs(cmgr, db_oid, table_oid, table_name, expr)?;
+        }
+
+        check_column_refs(cmgr, db_oid, table_oid, table_name, qual)?;
+
+        let mut rmgr = RelationManager::new(self.config.clone());
+        let relation = rmgr.get_relation(db_oid, table_oid);
+        let indices = exec_open_indices(cmgr, db_oid, table_oid);
+        let mut scan = create_scan_plan(relation, &rm, &indices, bm, qual, snapshot);
+        let mut update = UpdateState::new(relation, &mut *scan, target_list, &indices, snapshot.xid, bm);
 
-        delete.exec();
-        println!("Deleted records: {}", delete.count);
+        update.exec()?;
 
-        Ok(())
+        Ok(QueryResult::command("UPDATE", update.count))
     }
 }
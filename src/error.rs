@@ -0,0 +1,153 @@
+// Errors reported to clients (`ereport(ERROR, ...)` in pg).
+//
+// Each variant has a SQLSTATE code (`errcodes.txt` in pg), so that callers
+// can react to the kind of error without parsing the message.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use storage_manager::StorageError;
+
+#[derive(Debug)]
+pub enum Error {
+    // 42601 syntax_error
+    SyntaxError(String),
+    // 3D000 invalid_catalog_name
+    UndefinedDatabase(String),
+    // 42P01 undefined_table
+    UndefinedTable(String),
+    // 42703 undefined_column
+    UndefinedColumn(String),
+    // 42P04 duplicate_database
+    DuplicateDatabase(String),
+    // 42P07 duplicate_table (tables and indexes)
+    DuplicateTable(String),
+    // 42701 duplicate_column
+    DuplicateColumn(String),
+    // 42P16 invalid_table_definition
+    InvalidTableDefinition(String),
+    // 42804 datatype_mismatch
+    DatatypeMismatch(String),
+    // 42883 undefined_function (including operators)
+    UndefinedFunction(String),
+    // 22P02 invalid_text_representation
+    InvalidTextRepresentation(String),
+    // 22001 string_data_right_truncation
+    StringDataRightTruncation(String),
+    // 22003 numeric_value_out_of_range
+    NumericValueOutOfRange(String),
+    // 22012 division_by_zero
+    DivisionByZero(String),
+    // 22011 substring_error
+    SubstringError(String),
+    // 0A000 feature_not_supported
+    FeatureNotSupported(String),
+    // 54000 program_limit_exceeded
+    ProgramLimitExceeded(String),
+    // 25001 active_sql_transaction
+    ActiveSqlTransaction(String),
+    // 25P02 in_failed_sql_transaction
+    InFailedSqlTransaction(String),
+    // 58030 io_error
+    Io(io::Error),
+    // XX000 internal_error
+    Internal(String),
+}
+
+impl Error {
+    // SQLSTATE of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::SyntaxError(_) => "42601",
+            Error::UndefinedDatabase(_) => "3D000",
+            Error::UndefinedTable(_) => "42P01",
+            Error::UndefinedColumn(_) => "42703",
+            Error::DuplicateDatabase(_) => "42P04",
+            Error::DuplicateTable(_) => "42P07",
+            Error::DuplicateColumn(_) => "42701",
+            Error::InvalidTableDefinition(_) => "42P16",
+            Error::DatatypeMismatch(_) => "42804",
+            Error::UndefinedFunction(_) => "42883",
+            Error::InvalidTextRepresentation(_) => "22P02",
+            Error::StringDataRightTruncation(_) => "22001",
+            Error::NumericValueOutOfRange(_) => "22003",
+            Error::DivisionByZero(_) => "22012",
+            Error::SubstringError(_) => "22011",
+            Error::FeatureNotSupported(_) => "0A000",
+            Error::ProgramLimitExceeded(_) => "54000",
+            Error::ActiveSqlTransaction(_) => "25001",
+            Error::InFailedSqlTransaction(_) => "25P02",
+            Error::Io(_) => "58030",
+            Error::Internal(_) => "XX000",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SyntaxError(msg) |
+            Error::UndefinedDatabase(msg) |
+            Error::UndefinedTable(msg) |
+            Error::UndefinedColumn(msg) |
+            Error::DuplicateDatabase(msg) |
+            Error::DuplicateTable(msg) |
+            Error::DuplicateColumn(msg) |
+            Error::InvalidTableDefinition(msg) |
+            Error::DatatypeMismatch(msg) |
+            Error::UndefinedFunction(msg) |
+            Error::InvalidTextRepresentation(msg) |
+            Error::StringDataRightTruncation(msg) |
+            Error::NumericValueOutOfRange(msg) |
+            Error::DivisionByZero(msg) |
+            Error::SubstringError(msg) |
+            Error::FeatureNotSupported(msg) |
+            Error::ProgramLimitExceeded(msg) |
+            Error::ActiveSqlTransaction(msg) |
+            Error::InFailedSqlTransaction(msg) |
+            Error::Internal(msg) => write!(f, "{}", msg),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(e: StorageError) -> Error {
+        match e {
+            StorageError::Io(_, ref cause) => Error::Io(io::Error::new(cause.kind(), e.to_string())),
+            StorageError::NoUnpinnedBuffers(_) => Error::Internal(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        assert_eq!(Error::SyntaxError("".to_string()).code(), "42601");
+        assert_eq!(Error::UndefinedTable("".to_string()).code(), "42P01");
+        assert_eq!(Error::from(io::Error::new(io::ErrorKind::NotFound, "not found")).code(), "58030");
+
+        let e = Error::from(StorageError::NoUnpinnedBuffers(2));
+        assert_eq!(e.code(), "XX000");
+        assert_eq!(e.to_string(), "no unpinned buffers available (N_BUFFERS: 2)");
+    }
+}
@@ -0,0 +1,119 @@
+// Projection of target list (`ExecBuildProjectionInfo` and `ExecProject` in
+// `execExpr.c` in pg).
+//
+// The projection builds a row of the result from a slot returned by the scan
+// (or sort) node. It is applied after sort, so that ORDER BY can refer to
+// columns which are not in the target list.
+
+use ast::{Expr, ResTarget};
+use builtins::lookup_function;
+use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+use error::Error;
+use executor::node_seqscan::ExprEvaluator;
+use executor::tuptable::ColumnDesc;
+use tuple::TupleTableSlot;
+use ty::TypeValue;
+
+enum ProjectionTarget<'a> {
+    // Column of the slot. pg also projects simple Vars without evaluating
+    // expressions.
+    Column(usize),
+    Expr(&'a Expr),
+}
+
+pub struct ProjectionInfo<'a> {
+    targets: Vec<ProjectionTarget<'a>>,
+    columns: Vec<ColumnDesc>,
+}
+
+impl<'a> ProjectionInfo<'a> {
+    // `ExecBuildProjectionInfo` in pg.
+    //
+    // `attrs` are attributes of the scanned table.
+    pub fn new(target_list: &'a Vec<ResTarget>, attrs: &[&MiniAttributeRecord]) -> Result<ProjectionInfo<'a>, Error> {
+        let mut targets = Vec::new();
+        let mut columns = Vec::new();
+
+        for target in target_list.iter() {
+            match target.val.as_ref() {
+                // `ExpandColumnRefStar` in pg.
+                Expr::All => {
+                    for (i, attr) in attrs.iter().enumerate() {
+                        targets.push(ProjectionTarget::Column(i));
+                        columns.push(ColumnDesc::new(attr.name.clone(), attr.ty.clone()));
+                    }
+                },
+                Expr::ColumnRef(col_name) => {
+                    let i = attrs.iter().position(|attr| &attr.name == col_name).unwrap();
+                    targets.push(ProjectionTarget::Column(i));
+                    columns.push(ColumnDesc::new(figure_colname(target), attrs[i].ty.clone()));
+                },
+                expr => {
+                    targets.push(ProjectionTarget::Expr(expr));
+                    columns.push(ColumnDesc::new(figure_colname(target), expr_type(expr, attrs)?));
+                },
+            }
+        }
+
+        Ok(ProjectionInfo {
+            targets: targets,
+            columns: columns,
+        })
+    }
+
+    // Output columns of the projection.
+    pub fn columns(&self) -> Vec<ColumnDesc> {
+        self.columns.clone()
+    }
+
+    // `ExecProject` in pg.
+    pub fn exec_project(&self, slot: &TupleTableSlot) -> Result<Vec<Box<TypeValue>>, Error> {
+        self.targets.iter().map(|target| {
+            match target {
+                ProjectionTarget::Column(i) => Ok(slot.get_column(*i)),
+                ProjectionTarget::Expr(expr) => {
+                    Ok(ExprEvaluator::new(slot, expr).eval_value()?.into_type_value())
+                },
+            }
+        }).collect()
+    }
+}
+
+// `FigureColname` in pg. Columns without a name are labeled "?column?".
+fn figure_colname(target: &ResTarget) -> String {
+    if let Some(name) = &target.name {
+        return name.clone();
+    }
+
+    match target.val.as_ref() {
+        Expr::ColumnRef(col_name) => col_name.clone(),
+        Expr::FuncCall(name, _) => name.to_lowercase(),
+        Expr::Count => "count".to_string(),
+        _ => "?column?".to_string(),
+    }
+}
+
+// `exprType` in pg. Unknown type literals, e.g. 'abc' and NULL, are resolved
+// as text.
+fn expr_type(expr: &Expr, attrs: &[&MiniAttributeRecord]) -> Result<TypeLabel, Error> {
+    match expr {
+        Expr::Number(_) | Expr::UMinus(_) |
+        Expr::OpAdd(_, _) | Expr::OpSub(_, _) | Expr::OpMul(_, _) | Expr::OpDiv(_, _) | Expr::OpMod(_, _) => Ok(TypeLabel::Integer),
+        Expr::String(_) | Expr::Null => Ok(TypeLabel::Text),
+        Expr::ColumnRef(col_name) => Ok(attrs.iter().find(|attr| &attr.name == col_name).unwrap().ty.clone()),
+        Expr::FuncCall(name, args) => {
+            match lookup_function(name, args.len())?.rettype {
+                Some(ref ty) => Ok(ty.clone()),
+                // Polymorphic functions return the type of the first argument
+                // which is not NULL.
+                None => {
+                    match args.iter().find(|arg| match arg.as_ref() { Expr::Null => false, _ => true }) {
+                        Some(arg) => expr_type(arg, attrs),
+                        None => Ok(TypeLabel::Text),
+                    }
+                },
+            }
+        },
+        _ => Err(Error::FeatureNotSupported(format!("{:?} is not supported as target of SELECT.", expr))),
+    }
+}
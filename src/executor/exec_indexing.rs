@@ -0,0 +1,85 @@
+// `execIndexing.c` in pg.
+//
+// Index maintenance of tables. When a tuple is inserted into a table,
+// index tuples for it are inserted into all indexes of the table.
+//
+// Deleting a tuple does not remove its index tuples. Index scan checks
+// the heap tuple and skips deleted ones, and they remain until VACUUM.
+use catalog::catalog::RecordManeger;
+use catalog::mini_attribute::MiniAttributeRecord;
+use catalog::mini_index::MiniIndexRecord;
+use catalog::catalog_manager::CatalogManager;
+use btree::BTree;
+use buffer_manager::BufferManager;
+use error::Error;
+use storage_manager::RelationData;
+use tuple::{TupleTableSlot, ItemPointerData};
+use oid_manager::Oid;
+
+// `IndexInfo` in pg with the index relation.
+pub struct IndexInfo {
+    pub name: String,
+    pub relation: RelationData,
+    // attribute of the indexed column
+    pub attr: MiniAttributeRecord,
+    // position of the indexed column in tuples of the table
+    pub attr_index: usize,
+}
+
+impl IndexInfo {
+    pub fn new(index: &MiniIndexRecord, rm: &RecordManeger<MiniAttributeRecord>) -> IndexInfo {
+        let attrs = rm.attributes(index.db_oid, index.class_oid);
+        let attr_index = attrs.iter().position(|attr| attr.name == index.column)
+                             .expect(&format!("{} column of {} index should be defined.", index.column, index.name));
+
+        IndexInfo {
+            name: index.name.clone(),
+            relation: RelationData::new(index.db_oid, index.oid),
+            attr: attrs[attr_index].clone(),
+            attr_index: attr_index,
+        }
+    }
+
+    pub fn btree<'a>(&'a self) -> BTree<'a> {
+        BTree::new(&self.relation, &self.attr.ty)
+    }
+
+    // `FormIndexDatum` in pg.
+    //
+    // Returns key bytes of the tuple, or None if the column is null
+    // because null is not stored in indexes.
+    pub fn form_index_datum(&self, slot: &TupleTableSlot) -> Option<Vec<u8>> {
+        if slot.is_null(self.attr_index) {
+            return None;
+        }
+
+        let mut key = Vec::new();
+        slot.get_column(self.attr_index).write_bytes(&mut key).unwrap();
+        Some(key)
+    }
+
+    // `index_insert` in pg.
+    pub fn insert(&self, bufmrg: &mut BufferManager, slot: &TupleTableSlot, tid: &ItemPointerData) -> Result<(), Error> {
+        match self.form_index_datum(slot) {
+            Some(key) => self.btree().insert(bufmrg, &key, tid),
+            None => Ok(()),
+        }
+    }
+}
+
+// `ExecOpenIndices` in pg.
+pub fn exec_open_indices(cmgr: &CatalogManager, db_oid: Oid, table_oid: Oid) -> Vec<IndexInfo> {
+    cmgr.index_rm.indexes(db_oid, table_oid)
+        .iter()
+        .map(|index| IndexInfo::new(index, &cmgr.attribute_rm))
+        .collect()
+}
+
+// `ExecInsertIndexTuples` in pg.
+pub fn exec_insert_index_tuples(indices: &Vec<IndexInfo>, bufmrg: &mut BufferManager, slot: &TupleTableSlot, tid: &ItemPointerData) -> Result<(), Error> {
+    for index in indices.iter() {
+        index.insert(bufmrg, slot, tid)?;
+    }
+
+    Ok(())
+}
@@ -1,6 +1,11 @@
 pub mod plan_node;
+pub mod exec_expr;
+pub mod exec_indexing;
 pub mod node_agg;
 pub mod node_delete;
+pub mod node_indexscan;
 pub mod node_insert;
 pub mod node_seqscan;
 pub mod node_sort;
+pub mod node_update;
+pub mod tuptable;
@@ -1,3 +1,4 @@
+use error::Error;
 use tuple::{TupleTableSlot};
 use executor::plan_node::PlanNode;
 
@@ -17,9 +18,9 @@ impl<'a> CountState<'a> {
 
 impl<'a> PlanNode for CountState<'a> {
     // See: `ExecAgg` in pg.
-    fn exec(&mut self) -> Option<&TupleTableSlot> {
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
         loop {
-            let opt = self.lefttree.exec();
+            let opt = self.lefttree.exec()?;
 
             match opt {
                 Some(_slot) => {
@@ -29,6 +30,6 @@ impl<'a> PlanNode for CountState<'a> {
             }
         }
 
-        None
+        Ok(None)
     }
 }
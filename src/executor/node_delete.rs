@@ -2,9 +2,11 @@
 use std::cell::RefCell;
 use std::sync::RwLock;
 
+use error::Error;
 use tuple::{TupleTableSlot};
 use buffer_manager::{BufferManager};
 use storage_manager::{RelationData};
+use transam::TransactionId;
 use executor::plan_node::PlanNode;
 
 pub struct DeleteState<'a> {
@@ -12,6 +14,8 @@ pub struct DeleteState<'a> {
     currentRelation: &'a RefCell<RelationData>,
     lefttree: &'a mut PlanNode,
     pub count: u64,
+    // transaction which deletes tuples
+    xid: TransactionId,
     bufmrg: &'a RwLock<BufferManager>,
 }
 
@@ -19,12 +23,14 @@ impl<'a> DeleteState<'a> {
     pub fn new(
         relation: &'a RefCell<RelationData>,
         lefttree: &'a mut PlanNode,
+        xid: TransactionId,
         bufmrg: &'a RwLock<BufferManager>,
     ) -> DeleteState<'a> {
         DeleteState {
             currentRelation: relation,
             lefttree: lefttree,
             count: 0,
+            xid: xid,
             bufmrg: bufmrg,
         }
     }
@@ -32,21 +38,21 @@ impl<'a> DeleteState<'a> {
 
 impl<'a> PlanNode for DeleteState<'a> {
     // `ExecDelete` in pg.
-    fn exec(&mut self) -> Option<&TupleTableSlot> {
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
         loop {
-            let opt = self.lefttree.exec();
+            let opt = self.lefttree.exec()?;
 
             match opt {
                 Some(slot) => {
                     let relation = self.currentRelation.borrow();
                     let tid = slot.tid();
-                    self.bufmrg.write().unwrap().heap_delete(&relation, tid);
+                    self.bufmrg.write().unwrap().heap_delete(&relation, tid, self.xid)?;
                     self.count = self.count + 1;
                 },
                 None => break
             }
         }
 
-        None
+        Ok(None)
     }
 }
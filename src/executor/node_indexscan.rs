@@ -0,0 +1,121 @@
+#![allow(non_snake_case)]
+use std::cell::RefCell;
+use std::sync::RwLock;
+
+use catalog::catalog::RecordManeger;
+use catalog::mini_attribute::MiniAttributeRecord;
+use btree::ScanKeyData;
+use buffer_manager::BufferManager;
+use error::Error;
+use tuple::{TupleTableSlot, ItemPointerData};
+use storage_manager::RelationData;
+use tqual::Snapshot;
+use executor::exec_indexing::IndexInfo;
+use executor::node_seqscan::ExprEvaluator;
+use executor::plan_node::PlanNode;
+use ast::Expr;
+
+// `IndexScanState` in pg.
+pub struct IndexScanState<'a> {
+    // relation being scanned
+    ss_currentRelation: &'a RefCell<RelationData>,
+    // index used to find tuples
+    iss_Index: &'a IndexInfo,
+    iss_ScanKeys: Vec<ScanKeyData>,
+    // tids returned by the index, None until the first exec
+    iss_Tids: Option<Vec<ItemPointerData>>,
+    // position of the next tid
+    iss_Current: usize,
+    // pointer to slot in tuple table holding scan tuple
+    ss_ScanTupleSlot: Box<TupleTableSlot>,
+    // The field of PlanState in pg. Index conditions are also rechecked by this.
+    qual: &'a Option<Box<Expr>>,
+    // The field of EState in pg.
+    snapshot: &'a Snapshot<'a>,
+    bufmrg: &'a RwLock<BufferManager>,
+}
+
+impl<'a> IndexScanState<'a> {
+    // `ExecInitIndexScan` in pg.
+    pub fn new(
+        relation: &'a RefCell<RelationData>,
+        rm: &RecordManeger<MiniAttributeRecord>,
+        index: &'a IndexInfo,
+        scan_keys: Vec<ScanKeyData>,
+        bufmrg: &'a RwLock<BufferManager>,
+        qual: &'a Option<Box<Expr>>,
+        snapshot: &'a Snapshot<'a>
+    ) -> IndexScanState<'a> {
+        let rnode = &relation.borrow().smgr_rnode;
+        let attrs = rm.attributes_clone(rnode.db_oid, rnode.table_oid);
+
+        IndexScanState {
+            ss_currentRelation: relation,
+            iss_Index: index,
+            iss_ScanKeys: scan_keys,
+            iss_Tids: None,
+            iss_Current: 0,
+            ss_ScanTupleSlot: Box::new(TupleTableSlot::new(attrs)),
+            qual: qual,
+            snapshot: snapshot,
+            bufmrg: bufmrg,
+        }
+    }
+
+    // `IndexNext` in pg.
+    //
+    // Fetch the next heap tuple pointed by the index into the slot.
+    // Returns false if there is no more tuple.
+    fn index_next(&mut self) -> Result<bool, Error> {
+        if self.iss_Tids.is_none() {
+            let tids = self.iss_Index.btree().scan(&mut self.bufmrg.write().unwrap(), &self.iss_ScanKeys)?;
+            debug!("Index {} returns {} tids", self.iss_Index.name, tids.len());
+            self.iss_Tids = Some(tids);
+        }
+
+        let tids = self.iss_Tids.as_ref().unwrap();
+
+        while self.iss_Current < tids.len() {
+            let tid = &tids[self.iss_Current];
+            self.iss_Current = self.iss_Current + 1;
+
+            let relation = self.ss_currentRelation.borrow();
+
+            // Skip invisible tuple
+            if let Some(tuple) = self.bufmrg.write().unwrap().heap_fetch(&relation, tid, self.snapshot)? {
+                self.ss_ScanTupleSlot.load_data(tuple.data_ptr(), tuple.len(), tid.clone());
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    // ExecQual in pg.
+    fn exec_qual(&self) -> Result<bool, Error> {
+        if self.qual.is_none() {
+            // Always condition is met
+            return Ok(true);
+        }
+
+        let evaluator = ExprEvaluator::new(self.ss_ScanTupleSlot.as_ref(), self.qual.as_ref().unwrap().as_ref());
+        evaluator.eval()
+    }
+}
+
+impl<'a> PlanNode for IndexScanState<'a> {
+    // `ExecIndexScan` in pg.
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
+        loop {
+            if !self.index_next()? {
+                return Ok(None);
+            }
+
+            if self.exec_qual()? {
+                return Ok(Some(self.ss_ScanTupleSlot.as_ref()));
+            }
+
+            // next tuple
+        }
+    }
+}
@@ -2,14 +2,21 @@
 use std::cell::RefCell;
 use std::sync::RwLock;
 
+use error::Error;
 use tuple::{TupleTableSlot};
 use buffer_manager::{BufferManager};
 use storage_manager::{RelationData};
+use transam::TransactionId;
+use executor::exec_indexing::{IndexInfo, exec_insert_index_tuples};
 use executor::plan_node::{PlanNode};
 
 pub struct InsertState<'a> {
     slot: &'a TupleTableSlot,
     ss_currentRelation: &'a RefCell<RelationData>,
+    // indexes of the relation
+    indices: &'a Vec<IndexInfo>,
+    // transaction which inserts the tuple
+    xid: TransactionId,
     bufmrg: &'a RwLock<BufferManager>,
 }
 
@@ -17,11 +24,15 @@ impl<'a> InsertState<'a> {
     pub fn new(
         relation: &'a RefCell<RelationData>,
         slot: &'a TupleTableSlot,
+        indices: &'a Vec<IndexInfo>,
+        xid: TransactionId,
         bufmrg: &'a RwLock<BufferManager>
     ) -> InsertState<'a> {
         InsertState {
             ss_currentRelation: relation,
             slot: slot,
+            indices: indices,
+            xid: xid,
             bufmrg: bufmrg,
         }
     }
@@ -29,8 +40,10 @@ impl<'a> InsertState<'a> {
 
 impl<'a> PlanNode for InsertState<'a> {
     // `ExecInsert` in pg.
-    fn exec(&mut self) -> Option<&TupleTableSlot> {
-        self.bufmrg.write().unwrap().heap_insert(&mut self.ss_currentRelation.borrow_mut(), self.slot);
-        Some(self.slot)
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
+        let mut bufmrg = self.bufmrg.write().unwrap();
+        let tid = bufmrg.heap_insert(&mut self.ss_currentRelation.borrow_mut(), self.slot, self.xid)?;
+        exec_insert_index_tuples(self.indices, &mut bufmrg, self.slot, &tid)?;
+        Ok(Some(self.slot))
     }
 }
@@ -1,17 +1,22 @@
 #![allow(non_snake_case)]
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::sync::RwLock;
 
 use catalog::catalog::RecordManeger;
 use catalog::mini_attribute::MiniAttributeRecord;
 use buffer_manager::{Buffer, BlockNumber, BufferManager, InvalidBlockNumber};
+use error::Error;
 use tuple::{TupleTableSlot, HeapTupleData, ItemPointerData};
 use off::{FirstOffsetNumber};
-use storage_manager::{RelationData};
+use storage_manager::RelationData;
+use tqual::{Snapshot, heap_tuple_satisfies_visibility};
 use executor::plan_node::{PlanNode};
 use ast::Expr;
+use ty::{Datum, int4in};
+use builtins::{lookup_function, int4pl, int4mi, int4mul, int4div, int4mod, int4um};
 
-struct ExprEvaluator<'a> {
+pub struct ExprEvaluator<'a> {
     currentTuple: &'a TupleTableSlot,
     expr: &'a Expr,
 }
@@ -30,6 +35,8 @@ pub struct ScanState<'a> {
     ss_ScanTupleSlot: Box<TupleTableSlot>,
     // The field of PlanState in pg.
     qual: &'a Option<Box<Expr>>,
+    // The field of EState in pg.
+    snapshot: &'a Snapshot<'a>,
     bufmrg: &'a RwLock<BufferManager>,
 }
 
@@ -57,13 +64,8 @@ struct HeapScanDescData<'a> {
     rs_cbuf: Buffer,
 }
 
-enum Value {
-    Bool(bool),
-    String(String),
-}
-
 impl<'a> ExprEvaluator<'a> {
-    fn new(
+    pub fn new(
         currentTuple: &'a TupleTableSlot,
         expr: &'a Expr
     ) -> ExprEvaluator<'a> {
@@ -73,30 +75,136 @@ impl<'a> ExprEvaluator<'a> {
         }
     }
 
-    fn eval(&self) -> bool {
-        match *self.eval_rec(self.expr) {
-            Value::Bool(b) => b,
-            Value::String(s) => panic!("Value::String is not supported as result. ({:?})", s),
-        }
+    pub fn eval(&self) -> Result<bool, Error> {
+        // Null is treated as false like WHERE clause in SQL.
+        Ok(self.eval_bool(self.expr, "WHERE")?.unwrap_or(false))
     }
 
-    fn eval_rec(&self, expr: &Expr) -> Box<Value> {
+    // Returns the result of expression as it is, e.g. for SET clause of UPDATE.
+    pub fn eval_value(&self) -> Result<Datum, Error> {
+        self.eval_rec(self.expr)
+    }
+
+    fn eval_rec(&self, expr: &Expr) -> Result<Datum, Error> {
         match expr {
             Expr::Bool(b) => {
-                Box::new(Value::Bool(b.clone()))
+                Ok(Datum::Bool(b.clone()))
             },
             Expr::Number(n) => {
-                Box::new(Value::String(n.to_string()))
+                Ok(Datum::Integer(*n))
+            },
+            Expr::String(s) => {
+                Ok(Datum::Text(s.clone()))
+            },
+            Expr::Null => {
+                Ok(Datum::Null)
+            },
+            Expr::OpEq(e1, e2) => self.eval_comparison(e1, e2, |o| o == Ordering::Equal),
+            Expr::OpNe(e1, e2) => self.eval_comparison(e1, e2, |o| o != Ordering::Equal),
+            Expr::OpLt(e1, e2) => self.eval_comparison(e1, e2, |o| o == Ordering::Less),
+            Expr::OpLe(e1, e2) => self.eval_comparison(e1, e2, |o| o != Ordering::Greater),
+            Expr::OpGt(e1, e2) => self.eval_comparison(e1, e2, |o| o == Ordering::Greater),
+            Expr::OpGe(e1, e2) => self.eval_comparison(e1, e2, |o| o != Ordering::Less),
+            // `ExecEvalAnd` and `ExecEvalOr` in pg. Null means unknown,
+            // e.g. "false AND null" is false but "true AND null" is null.
+            Expr::And(e1, e2) => {
+                let b1 = self.eval_bool(e1, "AND")?;
+
+                if b1 == Some(false) {
+                    return Ok(Datum::Bool(false));
+                }
+
+                match (b1, self.eval_bool(e2, "AND")?) {
+                    (_, Some(false)) => Ok(Datum::Bool(false)),
+                    (Some(true), Some(true)) => Ok(Datum::Bool(true)),
+                    _ => Ok(Datum::Null),
+                }
+            },
+            Expr::Or(e1, e2) => {
+                let b1 = self.eval_bool(e1, "OR")?;
+
+                if b1 == Some(true) {
+                    return Ok(Datum::Bool(true));
+                }
+
+                match (b1, self.eval_bool(e2, "OR")?) {
+                    (_, Some(true)) => Ok(Datum::Bool(true)),
+                    (Some(false), Some(false)) => Ok(Datum::Bool(false)),
+                    _ => Ok(Datum::Null),
+                }
+            },
+            Expr::Not(e) => {
+                match self.eval_bool(e, "NOT")? {
+                    Some(b) => Ok(Datum::Bool(!b)),
+                    None => Ok(Datum::Null),
+                }
+            },
+            // "expr BETWEEN lower AND upper" is "expr >= lower AND expr <= upper".
+            Expr::Between(e, lower, upper) => {
+                let v = self.eval_rec(e)?;
+                let b1 = v.compare(&self.eval_rec(lower)?)?.map(|o| o != Ordering::Less);
+                let b2 = v.compare(&self.eval_rec(upper)?)?.map(|o| o != Ordering::Greater);
+
+                match (b1, b2) {
+                    (Some(false), _) | (_, Some(false)) => Ok(Datum::Bool(false)),
+                    (Some(true), Some(true)) => Ok(Datum::Bool(true)),
+                    _ => Ok(Datum::Null),
+                }
+            },
+            // "expr IN (e1, e2, ...)" is "expr = e1 OR expr = e2 OR ...".
+            Expr::In(e, list) => {
+                let v = self.eval_rec(e)?;
+                let mut has_null = false;
+
+                for e in list.iter() {
+                    match v.equal(&self.eval_rec(e)?)? {
+                        Some(true) => return Ok(Datum::Bool(true)),
+                        Some(false) => {},
+                        None => has_null = true,
+                    }
+                }
+
+                if has_null {
+                    Ok(Datum::Null)
+                } else {
+                    Ok(Datum::Bool(false))
+                }
+            },
+            Expr::OpAdd(e1, e2) => self.eval_arithmetic(e1, e2, int4pl),
+            Expr::OpSub(e1, e2) => self.eval_arithmetic(e1, e2, int4mi),
+            Expr::OpMul(e1, e2) => self.eval_arithmetic(e1, e2, int4mul),
+            Expr::OpDiv(e1, e2) => self.eval_arithmetic(e1, e2, int4div),
+            Expr::OpMod(e1, e2) => self.eval_arithmetic(e1, e2, int4mod),
+            Expr::UMinus(e) => {
+                match self.eval_integer(e)? {
+                    Some(i) => Ok(Datum::Integer(int4um(i)?)),
+                    None => Ok(Datum::Null),
+                }
+            },
+            // `ExecEvalFunc` in pg.
+            Expr::FuncCall(name, args) => {
+                let func = lookup_function(name, args.len())?;
+                let mut values = Vec::new();
+
+                for arg in args.iter() {
+                    values.push(self.eval_rec(arg)?);
+                }
+
+                if func.strict && values.iter().any(|v| v.is_null()) {
+                    return Ok(Datum::Null);
+                }
+
+                (func.func)(values)
             },
-            Expr::OpEq(e1, e2) => {
-                let v1 = self.eval_rec(e1);
-                let v2 = self.eval_rec(e2);
-                Box::new(Value::Bool(self.op_eq(v1.as_ref(), v2.as_ref())))
+            Expr::IsNull(e) => {
+                Ok(Datum::Bool(self.eval_rec(e)?.is_null()))
+            },
+            Expr::IsNotNull(e) => {
+                Ok(Datum::Bool(!self.eval_rec(e)?.is_null()))
             },
             Expr::ColumnRef(col_name) => {
                 let i = self.currentTuple.get_index_from_name(col_name);
-                let c = self.currentTuple.get_column(i);
-                Box::new(Value::String(c.as_string()))
+                Ok(self.currentTuple.get_datum(i))
             },
             Expr::All => {
                 panic!("Unknown expr ({:?})", self.expr);
@@ -107,11 +215,46 @@ impl<'a> ExprEvaluator<'a> {
         }
     }
 
-    fn op_eq(&self, v1: &Value, v2: &Value) -> bool {
-        match (v1, v2) {
-            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
-            (Value::String(s1), Value::String(s2)) => s1 == s2,
-            _ => false
+    // Evaluate an argument of boolean operators. None means null.
+    fn eval_bool(&self, expr: &Expr, op: &str) -> Result<Option<bool>, Error> {
+        match self.eval_rec(expr)? {
+            Datum::Bool(b) => Ok(Some(b)),
+            Datum::Null => Ok(None),
+            _ => Err(Error::DatatypeMismatch(format!("Argument of {} must be type boolean. ({:?})", op, expr))),
+        }
+    }
+
+    // Evaluate an argument of arithmetic operators. None means null.
+    fn eval_integer(&self, expr: &Expr) -> Result<Option<i32>, Error> {
+        match self.eval_rec(expr)? {
+            Datum::Integer(i) => Ok(Some(i)),
+            Datum::Text(ref s) => Ok(Some(int4in(s)?)),
+            Datum::Null => Ok(None),
+            Datum::Bool(_) => Err(Error::UndefinedFunction(format!("Operator does not exist for boolean. ({:?})", expr))),
+        }
+    }
+
+    fn eval_arithmetic<F>(&self, e1: &Expr, e2: &Expr, f: F) -> Result<Datum, Error>
+        where F: Fn(i32, i32) -> Result<i32, Error>
+    {
+        let i1 = self.eval_integer(e1)?;
+        let i2 = self.eval_integer(e2)?;
+
+        match (i1, i2) {
+            (Some(i1), Some(i2)) => Ok(Datum::Integer(f(i1, i2)?)),
+            _ => Ok(Datum::Null),
+        }
+    }
+
+    fn eval_comparison<F>(&self, e1: &Expr, e2: &Expr, f: F) -> Result<Datum, Error>
+        where F: Fn(Ordering) -> bool
+    {
+        let v1 = self.eval_rec(e1)?;
+        let v2 = self.eval_rec(e2)?;
+
+        match v1.compare(&v2)? {
+            Some(o) => Ok(Datum::Bool(f(o))),
+            None => Ok(Datum::Null),
         }
     }
 }
@@ -122,18 +265,17 @@ impl<'a> ScanState<'a> {
         relation: &'a RefCell<RelationData>,
         rm: &RecordManeger<MiniAttributeRecord>,
         bufmrg: &'a RwLock<BufferManager>,
-        qual: &'a Option<Box<Expr>>
+        qual: &'a Option<Box<Expr>>,
+        snapshot: &'a Snapshot<'a>
     ) -> ScanState<'a> {
         let rnode = &relation.borrow().smgr_rnode;
         let attrs = rm.attributes_clone(rnode.db_oid, rnode.table_oid);
-        let attrs_len = attrs.iter().fold(0, |acc, attr| acc + attr.len) as u32;
-        let mut tuple = HeapTupleData::new(attrs_len);
+        let slot = TupleTableSlot::new(attrs);
+        let mut tuple = HeapTupleData::new_with_full_len(slot.len());
         ::tuple::item_pointer_set_invalid(&mut tuple.t_self);
-        let rs_nblocks = bufmrg.write().unwrap().relation_get_number_of_blocks(&relation.borrow());
-
         let scan_desc = HeapScanDescData {
             rs_rd: relation,
-            rs_nblocks: rs_nblocks,
+            rs_nblocks: InvalidBlockNumber,
             rs_startblock: 0,
             rs_numblocks: InvalidBlockNumber,
             rs_inited: false,
@@ -143,7 +285,6 @@ impl<'a> ScanState<'a> {
             rs_cbuf: Buffer::InvalidBuffer,
         };
         let plan_state = PlanState {};
-        let slot = TupleTableSlot::new(attrs);
 
         ScanState {
             ps: plan_state,
@@ -151,34 +292,55 @@ impl<'a> ScanState<'a> {
             ss_currentScanDesc: scan_desc,
             ss_ScanTupleSlot: Box::new(slot),
             qual: qual,
+            snapshot: snapshot,
             bufmrg: bufmrg,
         }
     }
 
     // SeqNext in pg.
-    fn seq_next(&mut self) {
-        self.heap_getnext();
+    fn seq_next(&mut self) -> Result<(), Error> {
+        self.heap_getnext()?;
 
         if !self.ss_currentScanDesc.rs_finished {
             let tuple = &self.ss_currentScanDesc.rs_ctup;
-            self.ss_ScanTupleSlot.load_data_without_len(tuple.data_ptr(), tuple.t_self.clone());
+            self.ss_ScanTupleSlot.load_data(tuple.data_ptr(), tuple.len(), tuple.t_self.clone());
         }
+
+        Ok(())
     }
 
     // heap_getnext in pg.
     //
     // Get next tuple
-    fn heap_getnext(&mut self) {
-        self.heapgettup();
+    fn heap_getnext(&mut self) -> Result<(), Error> {
+        self.heapgettup()
     }
 
     // heapgettup in pg.
-    fn heapgettup(&mut self) {
+    //
+    // The current buffer is pinned while the scan is on the page.
+    fn heapgettup(&mut self) -> Result<(), Error> {
         let scan_desc = &mut self.ss_currentScanDesc;
 
+        if scan_desc.rs_finished {
+            return Ok(())
+        }
+
+        // `initscan` in pg gets the number of blocks, but we get it at the
+        // first call so that `new` does not fail.
+        if !scan_desc.rs_inited {
+            scan_desc.rs_nblocks = self.bufmrg.write().unwrap().relation_get_number_of_blocks(&scan_desc.rs_rd.borrow())?;
+        }
+
+        // Empty relation
+        if scan_desc.rs_nblocks == 0 {
+            scan_desc.rs_finished = true;
+            return Ok(())
+        }
+
         let mut lineoff = if !scan_desc.rs_inited {
             let page = scan_desc.rs_startblock;
-            let buf = self.bufmrg.write().unwrap().read_buffer(&scan_desc.rs_rd.borrow(), page);
+            let buf = self.bufmrg.write().unwrap().read_buffer(&scan_desc.rs_rd.borrow(), page)?;
             scan_desc.rs_cbuf = buf;
             scan_desc.rs_cblock = page;
             scan_desc.rs_inited = true;
@@ -197,31 +359,38 @@ impl<'a> ScanState<'a> {
 
                 let mrg = self.bufmrg.write().unwrap();
                 let dp = mrg.get_page(scan_desc.rs_cbuf);
-                let mut t_self = ItemPointerData::new();
-                ::tuple::item_pointer_set(&mut t_self, scan_desc.rs_cblock, lineoff);
-                debug!("lp_len {}", dp.get_item_ref(lineoff).lp_len());
-                scan_desc.rs_ctup.load_without_len(dp.get_entry_pointer(lineoff).unwrap(), t_self);
-
-                // Skip deleted record
-
-                if scan_desc.rs_ctup.t_data.heap_keys_updated_p() {
-                    debug!("Skip deleted tuple {}", lineoff);
+                let lpp = dp.get_item_ref(lineoff);
+
+                // Skip dead or unused line pointers and invisible records
+                let valid = lpp.is_normal() && {
+                    let mut t_self = ItemPointerData::new();
+                    ::tuple::item_pointer_set(&mut t_self, scan_desc.rs_cblock, lineoff);
+                    let lp_len = lpp.lp_len();
+                    debug!("lp_len {}", lp_len);
+                    scan_desc.rs_ctup.load(dp.get_entry_pointer(lineoff).unwrap(), lp_len as u32, t_self);
+                    heap_tuple_satisfies_visibility(&scan_desc.rs_ctup.t_data, self.snapshot)?
+                };
+
+                if !valid {
+                    debug!("Skip invisible tuple {}", lineoff);
                     lineoff = lineoff + 1;
                     linesleft = linesleft - 1;
                     // next
                 } else {
                     debug!("Return tuple {}", lineoff);
-                    return
+                    return Ok(())
                 }
             }
 
             // if we get here, it means we've exhausted the items on this page and
             // it's time to move to the next.
 
-            // If this is a last page, all works are finished (we do not support segments).
+            // If this is a last page, all works are finished.
             if scan_desc.rs_cblock + 1 >= scan_desc.rs_nblocks {
+                self.bufmrg.write().unwrap().release_buffer(scan_desc.rs_cbuf);
+                scan_desc.rs_cbuf = Buffer::InvalidBuffer;
                 scan_desc.rs_finished = true;
-                return
+                return Ok(())
             }
 
             // In pg, heapgetpage update `rs_cbuf`, `rs_cblock`
@@ -229,9 +398,10 @@ impl<'a> ScanState<'a> {
             {
                 let page = scan_desc.rs_cblock + 1;
                 scan_desc.rs_cblock = page;
-                let buf = self.bufmrg.write().unwrap().read_buffer(&scan_desc.rs_rd.borrow(), page);
+                let mut mrg = self.bufmrg.write().unwrap();
+                mrg.release_buffer(scan_desc.rs_cbuf);
+                let buf = mrg.read_buffer(&scan_desc.rs_rd.borrow(), page)?;
                 scan_desc.rs_cbuf = buf;
-                let mrg = self.bufmrg.write().unwrap();
                 let dp = mrg.get_page(scan_desc.rs_cbuf);
                 lineoff = FirstOffsetNumber;
                 let lines = dp.page_get_max_offset_number();
@@ -246,10 +416,10 @@ impl<'a> ScanState<'a> {
     //
     // If we need to current tuple to check qual,
     // use `self.ss_ScanTupleSlot.as_ref()`.
-    fn exec_qual(&self) -> bool {
+    fn exec_qual(&self) -> Result<bool, Error> {
         if self.qual.is_none() {
             // Always condition is met
-            return true;
+            return Ok(true);
         }
 
         let evaluator = ExprEvaluator::new(self.ss_ScanTupleSlot.as_ref(), self.qual.as_ref().unwrap().as_ref());
@@ -259,16 +429,16 @@ impl<'a> ScanState<'a> {
 
 impl<'a> PlanNode for ScanState<'a> {
     // ExecScan in pg.
-    fn exec(&mut self) -> Option<&TupleTableSlot> {
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
         loop {
-            self.seq_next();
+            self.seq_next()?;
 
             if self.ss_currentScanDesc.rs_finished {
-                return None;
+                return Ok(None);
             }
 
-            if self.exec_qual() {
-                return Some(self.ss_ScanTupleSlot.as_ref());
+            if self.exec_qual()? {
+                return Ok(Some(self.ss_ScanTupleSlot.as_ref()));
             }
 
             // next tuple
@@ -1,56 +1,39 @@
 #![allow(non_snake_case)]
+use std::rc::Rc;
+
+use ast::SortBy;
+use config::Config;
+use error::Error;
 use tuple::{TupleTableSlot};
+use tuplesort::Tuplesortstate;
 use executor::plan_node::PlanNode;
 
 pub struct SortState<'a> {
     lefttree: &'a mut PlanNode,
     sort_Done: bool,
-    memtuples: Vec<Box<TupleTableSlot>>,
-    current: usize, // array index (points current tuple index)
-    // Most simple version of comparetup
-    target_col_name: String,
+    tuplesortstate: Tuplesortstate<'a>,
 }
 
 impl<'a> SortState<'a> {
-    pub fn new(lefttree: &'a mut PlanNode, target_col_name: String) -> SortState {
+    pub fn new(lefttree: &'a mut PlanNode, sort_keys: &'a Vec<SortBy>, config: Rc<Config>) -> SortState<'a> {
         SortState {
             lefttree: lefttree,
             sort_Done: false,
-            memtuples: vec![],
-            current: 0,
-            target_col_name: target_col_name,
+            tuplesortstate: Tuplesortstate::new(config, sort_keys),
         }
     }
-
-    // fn tuplesort_puttupleslot(&mut self, slot: Box<TupleTableSlot>) {
-    //     self.memtuples.push(slot);
-    // }
-
-    fn tuplesort_performsort(&mut self) {
-        let col_name = self.target_col_name.clone();
-
-        self.memtuples.sort_by(|a, b| {
-           let i_a = a.get_index_from_name(&col_name);
-           let i_b = b.get_index_from_name(&col_name);
-           let col_a = a.get_column(i_a);
-           let col_b = b.get_column(i_b);
-
-           col_a.as_string().cmp(&col_b.as_string())
-        })
-    }
 }
 
 impl<'a> PlanNode for SortState<'a> {
     // ExecSort in pg.
-    fn exec(&mut self) -> Option<&TupleTableSlot> {
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
         if !self.sort_Done {
             loop {
-                let slot = self.lefttree.exec();
+                let slot = self.lefttree.exec()?;
 
                 match slot {
                     Some(s) => {
-                        // tuplesort_puttupleslot
-                        self.memtuples.push(Box::new((*s).clone()));
+                        self.tuplesortstate.tuplesort_puttupleslot(s)?;
                     },
                     None => {
                         break;
@@ -58,16 +41,10 @@ impl<'a> PlanNode for SortState<'a> {
                 }
             }
 
-            self.tuplesort_performsort();
+            self.tuplesortstate.tuplesort_performsort()?;
             self.sort_Done = true;
         }
 
-        if self.current < self.memtuples.len() {
-            let slot = &self.memtuples[self.current];
-            self.current = self.current + 1;
-            return Some(slot.as_ref());
-        }
-
-        return None;
+        self.tuplesortstate.tuplesort_gettupleslot()
     }
 }
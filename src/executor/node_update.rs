@@ -0,0 +1,96 @@
+#![allow(non_snake_case)]
+use std::cell::RefCell;
+use std::sync::RwLock;
+
+use error::Error;
+use tuple::{TupleTableSlot, ItemPointerData};
+use buffer_manager::{BufferManager};
+use storage_manager::{RelationData};
+use transam::TransactionId;
+use executor::exec_indexing::{IndexInfo, exec_insert_index_tuples};
+use executor::node_seqscan::ExprEvaluator;
+use executor::plan_node::PlanNode;
+use ty::build_type_value_from_datum;
+use ast::Expr;
+
+pub struct UpdateState<'a> {
+    // TODO: Extract this currentRelation to EState
+    currentRelation: &'a RefCell<RelationData>,
+    lefttree: &'a mut PlanNode,
+    // Pairs of column name and expression of SET clause.
+    targetList: &'a Vec<(String, Box<Expr>)>,
+    // indexes of the relation
+    indices: &'a Vec<IndexInfo>,
+    pub count: u64,
+    // transaction which updates tuples
+    xid: TransactionId,
+    bufmrg: &'a RwLock<BufferManager>,
+}
+
+impl<'a> UpdateState<'a> {
+    pub fn new(
+        relation: &'a RefCell<RelationData>,
+        lefttree: &'a mut PlanNode,
+        targetList: &'a Vec<(String, Box<Expr>)>,
+        indices: &'a Vec<IndexInfo>,
+        xid: TransactionId,
+        bufmrg: &'a RwLock<BufferManager>,
+    ) -> UpdateState<'a> {
+        UpdateState {
+            currentRelation: relation,
+            lefttree: lefttree,
+            targetList: targetList,
+            indices: indices,
+            count: 0,
+            xid: xid,
+            bufmrg: bufmrg,
+        }
+    }
+
+    // Build new version of the tuple. All expressions of SET clause are
+    // evaluated with the old tuple.
+    fn build_new_tuple(targetList: &Vec<(String, Box<Expr>)>, old_slot: &TupleTableSlot) -> Result<Box<TupleTableSlot>, Error> {
+        let mut new_slot = Box::new(old_slot.clone());
+
+        for (col_name, expr) in targetList.iter() {
+            let i = old_slot.get_index_from_name(col_name);
+            let evaluator = ExprEvaluator::new(old_slot, expr);
+            let ty = build_type_value_from_datum(old_slot.get_attribute(i), evaluator.eval_value()?)?;
+            new_slot.set_column(i, ty.as_ref());
+        }
+
+        Ok(new_slot)
+    }
+}
+
+impl<'a> PlanNode for UpdateState<'a> {
+    // `ExecUpdate` in pg.
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
+        // We do not have command id (cmin/cmax) to hide new versions of tuples
+        // from the scan, so collect all target tuples before updating them.
+        let mut tuples: Vec<(ItemPointerData, Box<TupleTableSlot>)> = Vec::new();
+        let targetList = self.targetList;
+
+        loop {
+            let opt = self.lefttree.exec()?;
+
+            match opt {
+                Some(slot) => {
+                    tuples.push((slot.tid().clone(), UpdateState::build_new_tuple(targetList, slot)?));
+                },
+                None => break
+            }
+        }
+
+        for (tid, new_slot) in tuples.iter() {
+            let relation = self.currentRelation.borrow();
+            let mut bufmrg = self.bufmrg.write().unwrap();
+            let new_tid = bufmrg.heap_update(&relation, tid, new_slot, self.xid)?;
+            // New version of the tuple is pointed by new index tuples.
+            exec_insert_index_tuples(self.indices, &mut bufmrg, new_slot, &new_tid)?;
+            self.count = self.count + 1;
+        }
+
+        Ok(None)
+    }
+}
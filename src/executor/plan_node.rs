@@ -1,6 +1,10 @@
+use error::Error;
 use tuple::{TupleTableSlot};
 
 // typedef struct Plan in pg.
+//
+// `exec` returns the next tuple, or None if there is no more tuple.
+// Errors (e.g. I/O errors of the storage) abort the statement.
 pub trait PlanNode {
-    fn exec(&mut self) -> Option<&TupleTableSlot>;
+    fn exec(&mut self) -> Result<Option<&TupleTableSlot>, Error>;
 }
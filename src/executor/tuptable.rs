@@ -0,0 +1,119 @@
+// Result of a statement (`SPI_processed` in pg) and the portal which
+// returns rows of SELECT.
+//
+// Rows are not stored in the result. They are fetched from the portal one
+// at a time while the statement is running (`PortalRunSelect` in pg), so
+// a large result, e.g. a sort spilled to disk, is not read into memory.
+
+use catalog::mini_attribute::TypeLabel;
+use error::Error;
+use ty::TypeValue;
+
+// Output column (an item of RowDescription message in pg).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDesc {
+    pub name: String,
+    pub ty: TypeLabel,
+}
+
+pub struct QueryResult {
+    // Command tag, e.g. "SELECT", "INSERT" and "CREATE TABLE"
+    pub tag: String,
+    // Output columns. Empty if the statement does not return rows.
+    pub columns: Vec<ColumnDesc>,
+    // The number of rows fetched, inserted, deleted or updated.
+    pub processed: u64,
+}
+
+// Values of a row are ordered by the output columns.
+pub type Row = Vec<Box<TypeValue>>;
+
+// `PortalData` in pg, only for statements which return rows.
+pub struct Portal<'a> {
+    columns: Vec<ColumnDesc>,
+    // Runs the plan until the next row is returned.
+    next_row: &'a mut FnMut() -> Result<Option<Row>, Error>,
+    processed: u64,
+}
+
+// Receives the portal of a statement which returns rows, and fetches rows
+// from it (`DestReceiver` in pg). An error of `Portal::fetch` should be
+// returned, so that the statement is aborted.
+pub type Dest<'d> = FnMut(&mut Portal) -> Result<(), Error> + 'd;
+
+impl ColumnDesc {
+    pub fn new(name: String, ty: TypeLabel) -> ColumnDesc {
+        ColumnDesc {
+            name: name,
+            ty: ty,
+        }
+    }
+}
+
+impl QueryResult {
+    // Result of a statement which returns rows.
+    pub fn new(tag: &str, columns: Vec<ColumnDesc>, processed: u64) -> QueryResult {
+        QueryResult {
+            tag: tag.to_string(),
+            columns: columns,
+            processed: processed,
+        }
+    }
+
+    // Result of a statement which does not return rows.
+    pub fn command(tag: &str, processed: u64) -> QueryResult {
+        QueryResult {
+            tag: tag.to_string(),
+            columns: Vec::new(),
+            processed: processed,
+        }
+    }
+
+    // `BuildQueryCompletionString` in pg, e.g. "INSERT 0 1" and "DELETE 2".
+    pub fn completion_tag(&self) -> String {
+        match self.tag.as_str() {
+            "INSERT" => format!("INSERT 0 {}", self.processed),
+            "SELECT" | "UPDATE" | "DELETE" => format!("{} {}", self.tag, self.processed),
+            _ => self.tag.clone(),
+        }
+    }
+}
+
+impl<'a> Portal<'a> {
+    pub fn new(columns: Vec<ColumnDesc>, next_row: &'a mut FnMut() -> Result<Option<Row>, Error>) -> Portal<'a> {
+        Portal {
+            columns: columns,
+            next_row: next_row,
+            processed: 0,
+        }
+    }
+
+    // Output columns (`PortalGetTupleDesc` in pg).
+    pub fn columns(&self) -> &[ColumnDesc] {
+        &self.columns
+    }
+
+    // The number of rows fetched so far.
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    // `PortalRunFetch` in pg. Returns the next row, or None after the last
+    // one. Rows which are not fetched when the receiver returns are not
+    // computed, like a cursor closed in the middle.
+    pub fn fetch(&mut self) -> Result<Option<Row>, Error> {
+        let row = (self.next_row)()?;
+
+        if row.is_some() {
+            self.processed += 1;
+        }
+
+        Ok(row)
+    }
+
+    // Fetch and discard all rows (`DestNone` in pg).
+    pub fn discard(&mut self) -> Result<(), Error> {
+        while self.fetch()?.is_some() {}
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+// Free space map (`freespace.c` in pg).
+//
+// The free space map of a relation is stored in "fsm" file next to the
+// "data" file. It has one byte for each block, which is the amount of free
+// space of the block divided by FSM_CAT_STEP (category). pg stores the map
+// as a tree of pages, but we keep a flat array because it is small enough.
+//
+// The map is approximate. It is not WAL-logged and written only when
+// buffers are flushed, so it may be stale after a crash. Callers should
+// check the free space of the page found by the map, and record the actual
+// value if it is wrong.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::rc::Rc;
+
+use buffer_manager::{BlockNumber, InvalidBlockNumber, RelFileNode};
+use config::{Config, DEFAULT_BLOCK_SIZE};
+use storage_manager::StorageError;
+
+// `FSM_CATEGORIES` and `FSM_CAT_STEP` in pg.
+const FSM_CATEGORIES: usize = 256;
+const FSM_CAT_STEP: usize = DEFAULT_BLOCK_SIZE as usize / FSM_CATEGORIES;
+
+// `fsm_space_avail_to_cat` in pg.
+//
+// Round down, so that the page has at least the space of the category.
+fn fsm_space_avail_to_cat(avail: usize) -> u8 {
+    let cat = avail / FSM_CAT_STEP;
+
+    if cat >= FSM_CATEGORIES {
+        (FSM_CATEGORIES - 1) as u8
+    } else {
+        cat as u8
+    }
+}
+
+// `fsm_space_needed_to_cat` in pg.
+//
+// Round up, so that any page of the category has enough space except
+// for the largest category.
+fn fsm_space_needed_to_cat(needed: usize) -> u8 {
+    let cat = (needed + FSM_CAT_STEP - 1) / FSM_CAT_STEP;
+
+    if cat >= FSM_CATEGORIES {
+        (FSM_CATEGORIES - 1) as u8
+    } else {
+        cat as u8
+    }
+}
+
+pub struct FreeSpaceMap {
+    config: Rc<Config>,
+    // Categories of blocks. Maps are loaded when they are used first time.
+    maps: HashMap<RelFileNode, Vec<u8>>,
+    // Relations whose maps are changed after loaded
+    dirty: HashSet<RelFileNode>,
+}
+
+impl FreeSpaceMap {
+    pub fn new(config: Rc<Config>) -> FreeSpaceMap {
+        FreeSpaceMap {
+            config: config,
+            maps: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    // `GetPageWithFreeSpace` in pg.
+    //
+    // Returns the first block which may have `space_needed` bytes, or
+    // InvalidBlockNumber if there is no such block.
+    pub fn get_page_with_free_space(&mut self, rnode: &RelFileNode, space_needed: usize) -> BlockNumber {
+        let min_cat = fsm_space_needed_to_cat(space_needed);
+
+        self.get_map(rnode).iter()
+            .position(|cat| *cat >= min_cat)
+            .map(|blkno| blkno as BlockNumber)
+            .unwrap_or(InvalidBlockNumber)
+    }
+
+    // `RecordPageWithFreeSpace` in pg.
+    pub fn record_page_with_free_space(&mut self, rnode: &RelFileNode, blkno: BlockNumber, space_avail: usize) {
+        let cat = fsm_space_avail_to_cat(space_avail);
+
+        {
+            let map = self.get_map(rnode);
+
+            if map.len() <= blkno as usize {
+                // Blocks which are not recorded yet are treated as full.
+                map.resize(blkno as usize + 1, 0);
+            } else if map[blkno as usize] == cat {
+                return;
+            }
+
+            map[blkno as usize] = cat;
+        }
+
+        self.dirty.insert(rnode.clone());
+    }
+
+    // `RecordAndGetPageWithFreeSpace` in pg.
+    //
+    // Used when `old_page` turns out not to have enough space.
+    pub fn record_and_get_page_with_free_space(&mut self, rnode: &RelFileNode, old_page: BlockNumber, old_space_avail: usize, space_needed: usize) -> BlockNumber {
+        self.record_page_with_free_space(rnode, old_page, old_space_avail);
+        self.get_page_with_free_space(rnode, space_needed)
+    }
+
+    // Discard maps of relations which match `f`, e.g. dropped relations.
+    pub fn forget<F>(&mut self, f: F) where F: Fn(&RelFileNode) -> bool {
+        self.maps.retain(|rnode, _| !f(rnode));
+        self.dirty.retain(|rnode| !f(rnode));
+    }
+
+    // Write changed maps to files. Maps which fail to be written stay dirty.
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        let dirty: Vec<RelFileNode> = self.dirty.iter().cloned().collect();
+
+        for rnode in dirty {
+            let path = self.config.fsm_file_path(rnode.db_oid, rnode.table_oid);
+
+            // Skip relations which have been dropped.
+            if self.config.table_dir_path(rnode.db_oid, rnode.table_oid).exists() {
+                fs::write(&path, &self.maps[&rnode]).map_err(|e| StorageError::Io(format!("write file {:?}", path), e))?;
+            }
+
+            self.dirty.remove(&rnode);
+        }
+
+        Ok(())
+    }
+
+    fn get_map(&mut self, rnode: &RelFileNode) -> &mut Vec<u8> {
+        let config = &self.config;
+
+        self.maps.entry(rnode.clone()).or_insert_with(|| {
+            let path = config.fsm_file_path(rnode.db_oid, rnode.table_oid);
+
+            match fs::read(&path) {
+                Ok(map) => map,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+                Err(e) => panic!("Failed to read free space map {:?}. '{}'", path, e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_categories() {
+        assert_eq!(fsm_space_avail_to_cat(0), 0);
+        assert_eq!(fsm_space_avail_to_cat(FSM_CAT_STEP - 1), 0);
+        assert_eq!(fsm_space_avail_to_cat(FSM_CAT_STEP), 1);
+        assert_eq!(fsm_space_avail_to_cat(DEFAULT_BLOCK_SIZE as usize), 255);
+        assert_eq!(fsm_space_needed_to_cat(1), 1);
+        assert_eq!(fsm_space_needed_to_cat(FSM_CAT_STEP), 1);
+        assert_eq!(fsm_space_needed_to_cat(FSM_CAT_STEP + 1), 2);
+    }
+
+    #[test]
+    fn test_free_space_map() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.table_dir_path(10000, 10001)).unwrap();
+        let rnode = RelFileNode { table_oid: 10001, db_oid: 10000 };
+
+        let mut fsm = FreeSpaceMap::new(config.clone());
+        assert_eq!(fsm.get_page_with_free_space(&rnode, 100), InvalidBlockNumber);
+
+        fsm.record_page_with_free_space(&rnode, 2, 1000);
+        fsm.record_page_with_free_space(&rnode, 4, 4000);
+        assert_eq!(fsm.get_page_with_free_space(&rnode, 100), 2);
+        assert_eq!(fsm.get_page_with_free_space(&rnode, 2000), 4);
+        assert_eq!(fsm.record_and_get_page_with_free_space(&rnode, 4, 10, 2000), InvalidBlockNumber);
+        fsm.flush().unwrap();
+
+        // Read the map from the file.
+        let mut fsm = FreeSpaceMap::new(config.clone());
+        assert_eq!(fsm.get_page_with_free_space(&rnode, 100), 2);
+        assert_eq!(fsm.get_page_with_free_space(&rnode, 1000), InvalidBlockNumber);
+    }
+}
@@ -3,6 +3,8 @@ use std::rc::Rc;
 
 use config::Config;
 use oid_manager::create_oid_file;
+use transam::create_clog_file;
+use xlog::create_control_file;
 
 pub struct InitCommand {
     config: Rc<Config>,
@@ -17,12 +19,17 @@ impl InitCommand {
         self.create_base_dir()?;
         self.create_global_dir()?;
         create_oid_file(&self.config)?;
+        create_control_file(&self.config)?;
+        create_clog_file(&self.config)?;
+        fs::create_dir_all(self.config.wal_dir_path())?;
 
         self.create_system_catalog_dir_and_file("mini_database")?;
         // For tables
         self.create_system_catalog_dir_and_file("mini_class")?;
         // For columns
-        self.create_system_catalog_dir_and_file("mini_attribute")
+        self.create_system_catalog_dir_and_file("mini_attribute")?;
+        // For indexes
+        self.create_system_catalog_dir_and_file("mini_index")
     }
 
     fn create_base_dir(&self) -> std::io::Result<()> {
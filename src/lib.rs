@@ -1,10 +1,10 @@
 extern crate libc;
 extern crate tempfile;
 extern crate byteorder;
-extern crate errno;
 #[macro_use]
 extern crate log;
 extern crate simple_logger;
+extern crate rustyline;
 
 #[macro_use]
 extern crate lalrpop_util;
@@ -18,17 +18,35 @@ pub mod catalog {
     pub mod mini_attribute;
     pub mod mini_class;
     pub mod mini_database;
+    pub mod mini_index;
 }
 
+pub mod btree;
+pub mod builtins;
 pub mod buffer_manager;
 pub mod oid_manager;
 pub mod storage_manager;
 pub mod ty;
 pub mod ddl;
 pub mod dml;
+pub mod error;
+pub mod freespace;
 pub mod init;
+pub mod miscinit;
 pub mod page;
+pub mod planner;
+pub mod postmaster;
+pub mod pqformat;
+pub mod tqual;
+pub mod tuplesort;
+pub mod transam;
 pub mod tuple;
+pub mod vacuum;
+pub mod xact;
+pub mod xlog;
 pub mod executor;
 pub mod off;
 pub mod spi;
+pub mod shell;
+
+pub use error::Error;
@@ -1,20 +1,53 @@
 extern crate minidb;
 extern crate clap;
 
+use std::fs;
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::RwLock;
 
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, AppSettings, SubCommand};
 
+use minidb::ast::ColumnDef;
 use minidb::oid_manager::OidManager;
 use minidb::config::{Config};
 use minidb::ddl::{CreateDatabaseCommand, CreateTableCommand};
 use minidb::init::{InitCommand};
 use minidb::spi::{Executor};
-use minidb::catalog::catalog_manager::CatalogManager;
+use minidb::postmaster::Postmaster;
+use minidb::shell::Shell;
+use minidb::error::Error;
+use minidb::executor::tuptable::{Portal, QueryResult};
+use minidb::catalog::mini_attribute::TypeLabel;
+
+// Rows are printed while they are fetched.
+fn print_rows(portal: &mut Portal) -> Result<(), Error> {
+    while let Some(row) = portal.fetch()? {
+        for value in row.iter() {
+            if value.is_null() {
+                print!("NULL ");
+            } else {
+                print!("{:?} ", value.as_string());
+            }
+        }
+        print!("\n");
+    }
+
+    Ok(())
+}
+
+fn print_result(result: &QueryResult) {
+    match result.tag.as_str() {
+        "DELETE" => println!("Deleted records: {}", result.processed),
+        "UPDATE" => println!("Updated records: {}", result.processed),
+        "VACUUM" => println!("Removed dead records: {}", result.processed),
+        _ => {},
+    }
+}
 
 fn main() {
     let matches = App::new("minidb")
+                          .setting(AppSettings::SubcommandRequiredElseHelp)
                           .arg(Arg::with_name("base_dir")
                                .long("base_dir")
                                .required(true)
@@ -25,6 +58,12 @@ fn main() {
                                .required(false)
                                .default_value("warn")
                                .takes_value(true))
+                          .arg(Arg::with_name("work_mem")
+                               .long("work_mem")
+                               .required(false)
+                               .value_name("KB")
+                               .default_value("4096")
+                               .takes_value(true))
                           .subcommand(
                               SubCommand::with_name("init"))
                           .subcommand(
@@ -44,6 +83,32 @@ fn main() {
                               SubCommand::with_name("execute")
                                   .arg(Arg::with_name("query")
                                        .required(true)
+                                       .multiple(true)
+                                       .takes_value(true)))
+                          .subcommand(
+                              SubCommand::with_name("run_file")
+                                  .arg(Arg::with_name("file")
+                                       .required(true)
+                                       .takes_value(true))
+                                  .arg(Arg::with_name("continue_on_error")
+                                       .long("continue_on_error")
+                                       .required(false)
+                                       .takes_value(false)))
+                          .subcommand(
+                              SubCommand::with_name("shell"))
+                          .subcommand(
+                              SubCommand::with_name("serve")
+                                  .arg(Arg::with_name("host")
+                                       .long("host")
+                                       .default_value("127.0.0.1")
+                                       .takes_value(true))
+                                  .arg(Arg::with_name("port")
+                                       .long("port")
+                                       .default_value("5432")
+                                       .takes_value(true))
+                                  .arg(Arg::with_name("unix_socket_directory")
+                                       .long("unix_socket_directory")
+                                       .required(false)
                                        .takes_value(true)))
                           .get_matches();
 
@@ -64,7 +129,17 @@ fn main() {
 
     simple_logger::init_with_level(level).unwrap();
 
-    let config = Rc::new(Config::new(base_dir.to_string()));
+    let work_mem = match matches.value_of("work_mem").unwrap().parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            println!("Error: 'work_mem must be a positive integer (kB)'");
+            ::std::process::exit(1);
+        }
+    };
+
+    let mut config = Config::new(base_dir.to_string());
+    config.set_work_mem(work_mem);
+    let config = Rc::new(config);
 
     match matches.subcommand() {
         ("init", Some(_)) => {
@@ -85,8 +160,8 @@ fn main() {
 
             match create_db.execute(dbname) {
                 Ok(_) => {},
-                Err(msg) => {
-                    println!("Error: '{}'", msg);
+                Err(e) => {
+                    println!("Error: '{}' (SQLSTATE {})", e, e.code());
                     ::std::process::exit(1);
                 }
             }
@@ -96,34 +171,123 @@ fn main() {
             let dbname = sub_m.value_of("dbname").unwrap();
             let tablename = sub_m.value_of("tablename").unwrap();
             let create_table = CreateTableCommand::new(config.clone(), oid_manager);
+            // Use `CREATE TABLE` statement to define other columns.
+            let columns = vec![
+                ColumnDef::new("id".to_string(), TypeLabel::Integer, 4),
+                ColumnDef::new("age".to_string(), TypeLabel::Integer, 4),
+            ];
 
-            match create_table.execute(dbname, tablename) {
+            match create_table.execute(dbname, tablename, &columns) {
                 Ok(_) => {},
-                Err(msg) => {
-                    println!("Error: '{}'", msg);
+                Err(e) => {
+                    println!("Error: '{}' (SQLSTATE {})", e, e.code());
                     ::std::process::exit(1);
                 }
             }
         },
         ("execute", Some(sub_m)) => {
-            let cmrg = CatalogManager::new(config.clone());
-            let query = sub_m.value_of("query").unwrap();
-            let executor = Executor::new(config.clone(), &cmrg);
+            let mut executor = match Executor::new(config.clone()) {
+                Ok(executor) => executor,
+                Err(e) => {
+                    println!("Error: '{}' (SQLSTATE {})", e, e.code());
+                    ::std::process::exit(1);
+                }
+            };
 
-            match executor.execute_query(query) {
-                Ok(_) => {},
-                Err(msg) => {
-                    println!("Error: '{}'", msg);
+            // Queries are executed in the same session, so a transaction
+            // block can contain multiple queries, e.g.
+            // `execute "begin" "insert ..." "insert ..." "commit"`.
+            for query in sub_m.values_of("query").unwrap() {
+                match executor.execute_query_with(query, &mut print_rows) {
+                    Ok(result) => print_result(&result),
+                    Err(e) => {
+                        println!("Error: '{}' (SQLSTATE {})", e, e.code());
+                        // Abort the transaction before exit.
+                        drop(executor);
+                        ::std::process::exit(1);
+                    }
+                }
+            }
+        },
+        ("run_file", Some(sub_m)) => {
+            let file = sub_m.value_of("file").unwrap();
+            let script = match fs::read_to_string(file) {
+                Ok(script) => script,
+                Err(e) => {
+                    println!("Error: 'could not read file \"{}\": {}'", file, e);
+                    ::std::process::exit(1);
+                }
+            };
+            // Nothing is executed if the file has a syntax error.
+            let stmts = match Executor::parse_script(&script) {
+                Ok(stmts) => stmts,
+                Err(e) => {
+                    println!("Error: '{}' (SQLSTATE {})", e, e.code());
+                    ::std::process::exit(1);
+                }
+            };
+            let mut executor = match Executor::new(config.clone()) {
+                Ok(executor) => executor,
+                Err(e) => {
+                    println!("Error: '{}' (SQLSTATE {})", e, e.code());
+                    ::std::process::exit(1);
+                }
+            };
+            let on_error_stop = !sub_m.is_present("continue_on_error");
+            let mut failed = false;
+
+            for stmt in stmts.into_iter() {
+                match executor.portal_run(stmt, &mut print_rows) {
+                    Ok(result) => print_result(&result),
+                    Err(e) => {
+                        println!("Error: '{}' (SQLSTATE {})", e, e.code());
+                        failed = true;
+
+                        if on_error_stop {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if failed {
+                // Abort the transaction before exit.
+                drop(executor);
+                ::std::process::exit(1);
+            }
+        },
+        ("shell", Some(_)) => {
+            let mut shell = match Shell::new(config.clone()) {
+                Ok(shell) => shell,
+                Err(e) => {
+                    println!("Error: '{}' (SQLSTATE {})", e, e.code());
                     ::std::process::exit(1);
                 }
+            };
+
+            if let Err(e) = shell.run() {
+                println!("Error: '{}'", e);
+                ::std::process::exit(1);
             }
         },
-        (command, _) => {
-            println!(
-                "Unknown command '{}' is given.\nSupported commands are 'init', 'create_db' and 'create_table'",
-                command
-            );
-            ::std::process::exit(1);
-        }
+        ("serve", Some(sub_m)) => {
+            let postmaster = Postmaster::new(config.clone());
+            let host = sub_m.value_of("host").unwrap();
+            let port = sub_m.value_of("port").unwrap();
+
+            // Listen on "DIR/.s.PGSQL.PORT" like pg, so that clients can
+            // connect with `psql -h DIR -p PORT`.
+            let result = match sub_m.value_of("unix_socket_directory") {
+                Some(dir) => postmaster.serve_unix(&Path::new(dir).join(format!(".s.PGSQL.{}", port))),
+                None => postmaster.serve_tcp(&format!("{}:{}", host, port)),
+            };
+
+            if let Err(e) = result {
+                println!("Error: '{}'", e);
+                ::std::process::exit(1);
+            }
+        },
+        // clap rejects unknown subcommands and prints help if none is given.
+        _ => unreachable!(),
     }
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+// Lock file of the data directory (`miscinit.c` in pg).
+//
+// A session (`Executor`) which uses the data directory holds an exclusive
+// lock of "root directory / postmaster.pid" until it ends, because two
+// sessions would replay WAL and abort transactions of each other, even in
+// the same process. The file has the pid of the holder. pg checks whether
+// the pid in the file is alive, but we use flock(2), which the kernel
+// releases when the process dies. The file is not removed at exit,
+// otherwise another process could lock the removed file while a third one
+// creates a new file.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process;
+
+use config::Config;
+
+// The lock is released when this is dropped.
+pub struct DataDirLockFile {
+    _file: File,
+}
+
+// `CreateDataDirLockFile` in pg.
+pub fn create_data_dir_lock_file(config: &Config) -> io::Result<DataDirLockFile> {
+    let path = config.lock_file_path();
+    let mut f = OpenOptions::new().read(true).write(true).create(true).open(&path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("could not create lock file {:?}: {}", path, e)))?;
+
+    if unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(io::Error::new(e.kind(), format!("could not lock file {:?}: {}", path, e)));
+        }
+
+        let mut pid = String::new();
+        f.read_to_string(&mut pid)?;
+        let msg = format!("lock file {:?} already exists. Is another process (PID {}) using the data directory?", path, pid.trim());
+        return Err(io::Error::new(e.kind(), msg));
+    }
+
+    f.set_len(0)?;
+    writeln!(f, "{}", process::id())?;
+    f.sync_all()?;
+
+    Ok(DataDirLockFile { _file: f })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_file() {
+        let dir = tempdir().unwrap();
+        let config = Config::new(dir.path().to_str().unwrap().to_string());
+
+        let lock = create_data_dir_lock_file(&config).unwrap();
+        assert_eq!(fs::read_to_string(config.lock_file_path()).unwrap(), format!("{}\n", process::id()));
+        assert!(create_data_dir_lock_file(&config).is_err());
+
+        drop(lock);
+        assert!(create_data_dir_lock_file(&config).is_ok());
+    }
+}
@@ -3,6 +3,7 @@ use std::mem;
 use config::DEFAULT_BLOCK_SIZE;
 use tuple::{TupleTableSlot};
 use off::{OffsetNumber};
+use xlog::XLogRecPtr;
 
 type LocationIndex = u16;
 
@@ -37,6 +38,14 @@ pub struct ItemIdData {
     data: u32
 }
 
+// Values of `lp_flags` (`itemid.h` in pg).
+// unused (should always have lp_len=0)
+pub const LP_UNUSED: u8 = 0;
+// used (should always have lp_len>0)
+pub const LP_NORMAL: u8 = 1;
+// dead, storage is released by `page_repair_fragmentation`
+pub const LP_DEAD: u8 = 3;
+
 const MEM_SIZE_OF_U8: usize = mem::size_of::<u8>();
 const MEM_SIZE_OF_U8_AS_U16: u16 = mem::size_of::<u8>() as u16;
 
@@ -44,12 +53,22 @@ const ITEM_ID_DATA_BYTE_SIZE: usize = mem::size_of::<ItemIdData>();
 
 // `PageHeaderData` in pg.
 pub struct PageHeaderData {
+    // LSN: next byte after last byte of WAL record for last change to this page
+    pd_lsn: XLogRecPtr,
     // offset to start of free space
     pd_lower: LocationIndex,
     // offset to end of free space
     pd_upper: LocationIndex,
+    // offset to start of special space
+    pd_special: LocationIndex,
+    // flag bits, see below
+    pd_flags: u16,
 }
 
+// `PD_HAS_FREE_LINES` in pg.
+// This is a hint that there are unused line pointers in the page.
+const PD_HAS_FREE_LINES: u16 = 0x0001;
+
 // `SizeOfPageHeaderData` in pg.
 const SIZE_OF_PAGE_HEADER_DATA: usize = mem::size_of::<PageHeaderData>();
 
@@ -92,19 +111,40 @@ impl ItemIdData {
     pub fn set_lp_len(&mut self, len: u16) {
         self.data = (self.data & !0x00007fff) | ((len & 0x7fff) as u32);
     }
+
+    // `ItemIdIsUsed` in pg.
+    pub fn is_used(&self) -> bool {
+        self.lp_flags() != LP_UNUSED
+    }
+
+    // `ItemIdIsNormal` in pg.
+    pub fn is_normal(&self) -> bool {
+        self.lp_flags() == LP_NORMAL
+    }
+
+    // `ItemIdIsDead` in pg.
+    pub fn is_dead(&self) -> bool {
+        self.lp_flags() == LP_DEAD
+    }
 }
 
 impl PageHeaderData {
     fn new(block_size: u16) -> PageHeaderData {
         PageHeaderData {
+            pd_lsn: 0,
             pd_lower: SIZE_OF_PAGE_HEADER_DATA as u16,
             pd_upper: block_size,
+            pd_special: block_size,
+            pd_flags: 0,
         }
     }
 
-    fn init(&mut self, block_size: u16) {
+    fn init(&mut self, block_size: u16, special_size: u16) {
+        self.pd_lsn = 0;
         self.pd_lower = SIZE_OF_PAGE_HEADER_DATA as u16;
-        self.pd_upper = block_size;
+        self.pd_upper = block_size - special_size;
+        self.pd_special = block_size - special_size;
+        self.pd_flags = 0;
     }
 
     fn from_bytes(buf: &[u8]) -> PageHeaderData {
@@ -113,8 +153,11 @@ impl PageHeaderData {
         }
 
         PageHeaderData {
-            pd_lower: ((buf[0] as u16) << 8) | buf[1] as u16,
-            pd_upper: ((buf[2] as u16) << 8) | buf[4] as u16,
+            pd_lsn: buf[0..8].iter().fold(0, |lsn, b| (lsn << 8) | *b as u64),
+            pd_lower: ((buf[8] as u16) << 8) | buf[9] as u16,
+            pd_upper: ((buf[10] as u16) << 8) | buf[11] as u16,
+            pd_special: ((buf[12] as u16) << 8) | buf[13] as u16,
+            pd_flags: ((buf[14] as u16) << 8) | buf[15] as u16,
         }
     }
 }
@@ -130,14 +173,55 @@ impl Page {
                 panic!("failed to allocate memory");
             }
 
-            (*header_p).init(block_size);
+            (*header_p).init(block_size, 0);
             Page { header: header_p }
         }
     }
 
     // `PageInit` in pg.
     pub fn page_init(&mut self, block_size: u16) {
-        self.mut_header().init(block_size);
+        self.mut_header().init(block_size, 0);
+    }
+
+    // `PageInit` in pg with `specialSize`.
+    // Special space is reserved at the end of the page, e.g. for index.
+    pub fn page_init_with_special(&mut self, block_size: u16, special_size: u16) {
+        self.mut_header().init(block_size, special_size);
+    }
+
+    // `PageGetSpecialPointer` in pg.
+    pub fn page_get_special_pointer(&self) -> *mut u8 {
+        unsafe {
+            (self.header as *mut u8).add(self.header().pd_special as usize)
+        }
+    }
+
+    // `PageGetLSN` in pg.
+    pub fn page_get_lsn(&self) -> XLogRecPtr {
+        self.header().pd_lsn
+    }
+
+    // `PageSetLSN` in pg.
+    pub fn page_set_lsn(&mut self, lsn: XLogRecPtr) {
+        self.mut_header().pd_lsn = lsn;
+    }
+
+    // Copy of whole page, which is used as full page image of WAL.
+    pub fn get_image(&self) -> Vec<u8> {
+        unsafe {
+            ::std::slice::from_raw_parts(self.header as *const u8, DEFAULT_BLOCK_SIZE as usize).to_vec()
+        }
+    }
+
+    // Overwrite whole page with the image.
+    pub fn restore_image(&mut self, image: &[u8]) {
+        if image.len() != DEFAULT_BLOCK_SIZE as usize {
+            panic!("Length of page image should be {}, but {}.", DEFAULT_BLOCK_SIZE, image.len());
+        }
+
+        unsafe {
+            libc::memcpy(self.header as *mut libc::c_void, image.as_ptr() as *const libc::c_void, image.len());
+        }
     }
 
     pub fn header_pointer(&self) -> *mut libc::c_void {
@@ -178,32 +262,182 @@ impl Page {
         }
     }
 
-    pub fn add_entry(&mut self, src: *const libc::c_void, n: u16) -> Result<(), String> {
+    // `PageAddItem` in pg without `offsetNumber`.
+    //
+    // Reuse an unused line pointer if the page has it, otherwise a new line
+    // pointer is added. Returns the offset number of the entry.
+    pub fn add_entry(&mut self, src: *const libc::c_void, n: u16) -> Result<OffsetNumber, String> {
         debug!("Page add_entry. n: {}", n);
 
-        if self.has_space(n) {
-            self.mut_header().pd_upper -= n;
-            let item = ItemIdData::new_with_lps(self.header().pd_upper, 0, n);
+        let offnum = match self.find_unused_line_pointer() {
+            Some(offnum) => offnum,
+            None => self.entry_count(),
+        };
 
-            unsafe {
-                let item_p: *mut ItemIdData = (self.header as *const u8).add(self.header().pd_lower as usize) as *mut ItemIdData;
-                let tuple_head_p: *mut libc::c_void = (self.header as *const u8).add(self.header().pd_upper as usize) as *mut libc::c_void;
-                *item_p = item;
-                libc::memcpy(tuple_head_p, src, n as usize);
-            }
+        self.add_entry_overwrite(src, n, offnum)
+    }
+
+    // `PageAddItem` in pg with `offsetNumber` and `overwrite`.
+    //
+    // Store an entry at `offnum`, which should be an unused line pointer or
+    // the next of the last line pointer. This is used to redo heap insertion
+    // on the same line pointer.
+    pub fn add_entry_overwrite(&mut self, src: *const libc::c_void, n: u16, offnum: OffsetNumber) -> Result<OffsetNumber, String> {
+        let count = self.entry_count();
+
+        let needed = if offnum == count {
+            n as usize + ITEM_ID_DATA_BYTE_SIZE
+        } else if offnum < count && !self.get_item_ref(offnum).is_used() {
+            n as usize
+        } else {
+            return Err(format!("Line pointer is not reusable. offnum: {}, entry_count: {}", offnum, count));
+        };
 
+        if needed > self.page_get_exact_free_space() {
+            return Err(format!("Does not have enough space for {}", n));
+        }
+
+        if offnum == count {
             self.mut_header().pd_lower += ITEM_ID_DATA_BYTE_SIZE as u16;
-            Ok(())
+        }
+
+        self.put_entry(src, n, offnum);
+        Ok(offnum)
+    }
+
+    // `PageAddItem` in pg with `offsetNumber`.
+    //
+    // Insert an entry at `offnum`, line pointers after `offnum` are shifted.
+    // index is 0-origin.
+    pub fn add_entry_at(&mut self, src: *const libc::c_void, n: u16, offnum: OffsetNumber) -> Result<(), String> {
+        debug!("Page add_entry_at. n: {}, offnum: {}", n, offnum);
+
+        let count = self.entry_count();
+
+        if offnum > count {
+            return Err(format!("Offset over entry_count. offnum: {}, entry_count: {}", offnum, count));
+        }
+
+        if !self.has_space(n) {
+            return Err(format!("Does not have enough space for {}", n));
+        }
+
+        unsafe {
+            let items_p: *mut ItemIdData = (self.header as *mut u8).add(SIZE_OF_PAGE_HEADER_DATA) as *mut ItemIdData;
+            ::std::ptr::copy(items_p.add(offnum as usize), items_p.add(offnum as usize + 1), (count - offnum) as usize);
+        }
+
+        self.mut_header().pd_lower += ITEM_ID_DATA_BYTE_SIZE as u16;
+        self.put_entry(src, n, offnum);
+        Ok(())
+    }
+
+    // Copy the entry into the free space and set the line pointer at
+    // `offnum` to it. The caller should check the space.
+    fn put_entry(&mut self, src: *const libc::c_void, n: u16, offnum: OffsetNumber) {
+        self.mut_header().pd_upper -= n;
+        let item = ItemIdData::new_with_lps(self.header().pd_upper, LP_NORMAL, n);
+
+        unsafe {
+            let tuple_head_p: *mut libc::c_void = (self.header as *const u8).add(self.header().pd_upper as usize) as *mut libc::c_void;
+            *self.get_item_mut(offnum) = item;
+            libc::memcpy(tuple_head_p, src, n as usize);
+        }
+    }
+
+    // `PageGetFreeLinePointer` logic of `PageAddItem` in pg.
+    fn find_unused_line_pointer(&mut self) -> Option<OffsetNumber> {
+        if self.header().pd_flags & PD_HAS_FREE_LINES == 0 {
+            return None;
+        }
+
+        let found = (0..self.entry_count()).find(|i| !self.get_item_ref(*i).is_used());
+
+        if found.is_none() {
+            // The hint was wrong, clear it.
+            self.mut_header().pd_flags &= !PD_HAS_FREE_LINES;
+        }
+
+        found
+    }
+
+    // `ItemIdSetDead` in pg.
+    //
+    // Storage of the entry is released by `page_repair_fragmentation`.
+    pub fn item_id_set_dead(&mut self, index: OffsetNumber) {
+        unsafe {
+            *self.get_item_mut(index) = ItemIdData::new_with_lps(0, LP_DEAD, 0);
+        }
+    }
+
+    // `ItemIdSetUnused` in pg.
+    //
+    // The line pointer can be reused after `page_repair_fragmentation`.
+    pub fn item_id_set_unused(&mut self, index: OffsetNumber) {
+        unsafe {
+            *self.get_item_mut(index) = ItemIdData::new_with_lps(0, LP_UNUSED, 0);
+        }
+    }
+
+    // `PageRepairFragmentation` in pg.
+    //
+    // Move entries of normal line pointers to the end of the page, so that
+    // space of dead and unused ones is merged into the free space.
+    // Line pointers are not moved because tids point to them.
+    pub fn page_repair_fragmentation(&mut self) {
+        let mut entries = Vec::new();
+        let mut has_free_lines = false;
+
+        for i in 0..self.entry_count() {
+            let item = self.get_item(i);
+
+            if item.is_normal() {
+                entries.push((i, self.get_entry(i).unwrap()));
+            } else if !item.is_used() {
+                has_free_lines = true;
+            }
+        }
+
+        let special = self.header().pd_special;
+        self.mut_header().pd_upper = special;
+
+        for (i, entry) in entries.iter() {
+            self.put_entry(entry.as_ptr() as *const libc::c_void, entry.len() as u16, *i);
+        }
+
+        if has_free_lines {
+            self.mut_header().pd_flags |= PD_HAS_FREE_LINES;
         } else {
-            Err(format!("Does not have enough space for {}", n))
+            self.mut_header().pd_flags &= !PD_HAS_FREE_LINES;
+        }
+    }
+
+    // `PageIndexMultiDelete` in pg.
+    //
+    // Delete entries at `offnums` and compact both line pointers and
+    // storage. Offset numbers of following entries are changed, so this
+    // is only for index pages.
+    pub fn page_index_multi_delete(&mut self, offnums: &[OffsetNumber]) {
+        let entries: Vec<Vec<u8>> = (0..self.entry_count())
+                                        .filter(|i| !offnums.contains(i))
+                                        .map(|i| self.get_entry(i).unwrap())
+                                        .collect();
+
+        let special = self.header().pd_special;
+        self.mut_header().pd_lower = SIZE_OF_PAGE_HEADER_DATA as u16;
+        self.mut_header().pd_upper = special;
+        self.mut_header().pd_flags &= !PD_HAS_FREE_LINES;
+
+        for entry in entries.iter() {
+            self.add_vec_entry(entry).unwrap();
         }
     }
 
-    pub fn add_tuple_slot_entry(&mut self, slot: &TupleTableSlot) -> Result<(), String> {
+    pub fn add_tuple_slot_entry(&mut self, slot: &TupleTableSlot) -> Result<OffsetNumber, String> {
         self.add_entry(slot.data_ptr(), slot.len() as u16)
     }
 
-    pub fn add_vec_entry(&mut self, entry: &Vec<u8>) -> Result<(), String> {
+    pub fn add_vec_entry(&mut self, entry: &Vec<u8>) -> Result<OffsetNumber, String> {
         self.add_entry(entry.as_ptr() as *const libc::c_void, entry.len() as u16)
     }
 
@@ -217,6 +451,11 @@ impl Page {
         (len as usize) <= self.page_get_free_space()
     }
 
+    // `PageGetExactFreeSpace` in pg.
+    pub fn page_get_exact_free_space(&self) -> usize {
+        (self.header().pd_upper - self.header().pd_lower) as usize
+    }
+
     // `PageGetFreeSpace` in pd.
     // See also `PageGetHeapFreeSpace` in pd.
     pub fn page_get_free_space(&self) -> usize {
@@ -256,6 +495,10 @@ impl Page {
         }
     }
 
+    unsafe fn get_item_mut(&mut self, index: u16) -> &mut ItemIdData {
+        &mut *((self.header as *mut u8).add(SIZE_OF_PAGE_HEADER_DATA + ITEM_ID_DATA_BYTE_SIZE * (index as usize)) as *mut ItemIdData)
+    }
+
     // PageGetItem in pg.
     // index is 0-origin.
     pub fn get_entry_pointer(&self, index: u16) -> Result<*const libc::c_void, String> {
@@ -377,6 +620,80 @@ mod tests {
         assert_eq!(page.get_entry(1).unwrap(), entry2);
     }
 
+    #[test]
+    fn test_add_entry_at() {
+        let mut page = Page::new(DEFAULT_BLOCK_SIZE);
+        page.page_init_with_special(DEFAULT_BLOCK_SIZE, 16);
+        let entry1: Vec<u8> = vec![1, 1];
+        let entry2: Vec<u8> = vec![2, 2, 2];
+        let entry3: Vec<u8> = vec![3];
+
+        assert_eq!(page.header().pd_upper, DEFAULT_BLOCK_SIZE - 16);
+        assert_eq!(page.page_get_special_pointer(), unsafe { (page.header as *mut u8).add((DEFAULT_BLOCK_SIZE - 16) as usize) });
+
+        page.add_entry_at(entry1.as_ptr() as *const libc::c_void, 2, 0).unwrap();
+        page.add_entry_at(entry3.as_ptr() as *const libc::c_void, 1, 1).unwrap();
+        page.add_entry_at(entry2.as_ptr() as *const libc::c_void, 3, 1).unwrap();
+
+        assert_eq!(page.entry_count(), 3);
+        assert_eq!(page.get_entry(0).unwrap(), entry1);
+        assert_eq!(page.get_entry(1).unwrap(), entry2);
+        assert_eq!(page.get_entry(2).unwrap(), entry3);
+        assert!(page.add_entry_at(entry3.as_ptr() as *const libc::c_void, 1, 4).is_err());
+    }
+
+    #[test]
+    fn test_page_repair_fragmentation() {
+        let mut page = Page::new(DEFAULT_BLOCK_SIZE);
+        let entry1: Vec<u8> = vec![1, 1];
+        let entry2: Vec<u8> = vec![2, 2, 2];
+        let entry3: Vec<u8> = vec![3];
+        let entry4: Vec<u8> = vec![4, 4];
+
+        assert_eq!(page.add_vec_entry(&entry1).unwrap(), 0);
+        assert_eq!(page.add_vec_entry(&entry2).unwrap(), 1);
+        assert_eq!(page.add_vec_entry(&entry3).unwrap(), 2);
+        assert!(page.get_item_ref(1).is_normal());
+
+        page.item_id_set_dead(0);
+        page.item_id_set_unused(1);
+        page.page_repair_fragmentation();
+
+        assert!(page.get_item_ref(0).is_dead());
+        assert!(!page.get_item_ref(1).is_used());
+        assert_eq!(page.header().pd_upper, DEFAULT_BLOCK_SIZE - 1);
+        assert_eq!(page.get_entry(2).unwrap(), entry3);
+
+        // Unused line pointer is reused, but dead one is not.
+        assert_eq!(page.add_vec_entry(&entry4).unwrap(), 1);
+        assert_eq!(page.add_vec_entry(&entry1).unwrap(), 3);
+        assert_eq!(page.entry_count(), 4);
+        assert_eq!(page.get_entry(1).unwrap(), entry4);
+
+        assert!(page.add_entry_overwrite(entry1.as_ptr() as *const libc::c_void, 2, 0).is_err());
+        page.item_id_set_unused(0);
+        assert_eq!(page.add_entry_overwrite(entry2.as_ptr() as *const libc::c_void, 3, 0).unwrap(), 0);
+        assert_eq!(page.get_entry(0).unwrap(), entry2);
+    }
+
+    #[test]
+    fn test_page_index_multi_delete() {
+        let mut page = Page::new(DEFAULT_BLOCK_SIZE);
+        page.page_init_with_special(DEFAULT_BLOCK_SIZE, 16);
+        let entry1: Vec<u8> = vec![1, 1];
+        let entry2: Vec<u8> = vec![2, 2, 2];
+        let entry3: Vec<u8> = vec![3];
+
+        page.add_vec_entry(&entry1).unwrap();
+        page.add_vec_entry(&entry2).unwrap();
+        page.add_vec_entry(&entry3).unwrap();
+        page.page_index_multi_delete(&[0, 1]);
+
+        assert_eq!(page.entry_count(), 1);
+        assert_eq!(page.get_entry(0).unwrap(), entry3);
+        assert_eq!(page.header().pd_upper, DEFAULT_BLOCK_SIZE - 16 - 1);
+    }
+
     #[test]
     fn test_add_tuple_slot_entry() {
         let mut page = Page::new(DEFAULT_BLOCK_SIZE);
@@ -407,6 +724,6 @@ mod tests {
         assert_eq!(page.is_empty(), false);
         assert_eq!(page.entry_count(), 1);
         assert_eq!(page.get_item_ref(0).lp_len(), slot_data_size);
-        assert_eq!(page.get_entry(0).unwrap(), vec![0, 0, 0, 0, 10, 0, 0, 0, 22, 0, 0, 0]);
+        assert_eq!(page.get_entry(0).unwrap(), vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 22, 0, 0, 0]);
     }
 }
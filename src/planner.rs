@@ -0,0 +1,191 @@
+// Choose how to scan a relation.
+//
+// If `qual` compares an indexed column with a constant, use index scan.
+// Otherwise use sequential scan. See `create_index_paths` in pg.
+use std::cell::RefCell;
+use std::sync::RwLock;
+
+use ast::Expr;
+use btree::{ScanKeyData, Strategy};
+use buffer_manager::BufferManager;
+use catalog::catalog::RecordManeger;
+use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+use storage_manager::RelationData;
+use tqual::Snapshot;
+use executor::exec_indexing::IndexInfo;
+use executor::node_indexscan::IndexScanState;
+use executor::node_seqscan::ScanState;
+use executor::plan_node::PlanNode;
+use ty::{Datum, int4in};
+
+pub fn create_scan_plan<'a>(
+    relation: &'a RefCell<RelationData>,
+    rm: &RecordManeger<MiniAttributeRecord>,
+    indices: &'a Vec<IndexInfo>,
+    bufmrg: &'a RwLock<BufferManager>,
+    qual: &'a Option<Box<Expr>>,
+    snapshot: &'a Snapshot<'a>
+) -> Box<PlanNode + 'a> {
+    if let Some(expr) = qual {
+        for index in indices.iter() {
+            let mut scan_keys = Vec::new();
+            match_clauses_to_index(index, expr, &mut scan_keys);
+
+            // The whole qual is rechecked by the index scan, so conditions
+            // which are not scan keys are also applied.
+            if !scan_keys.is_empty() {
+                debug!("Use index scan with {} index ({:?})", index.name, scan_keys);
+                return Box::new(IndexScanState::new(relation, rm, index, scan_keys, bufmrg, qual, snapshot));
+            }
+        }
+    }
+
+    Box::new(ScanState::new(relation, rm, bufmrg, qual, snapshot))
+}
+
+// `match_clauses_to_index` in pg.
+//
+// Collect scan keys from conjuncts of AND. "column BETWEEN a AND b" is
+// matched as "column >= a" and "column <= b".
+fn match_clauses_to_index(index: &IndexInfo, expr: &Expr, scan_keys: &mut Vec<ScanKeyData>) {
+    match expr {
+        Expr::And(e1, e2) => {
+            match_clauses_to_index(index, e1, scan_keys);
+            match_clauses_to_index(index, e2, scan_keys);
+        },
+        Expr::Between(e, lower, upper) => {
+            if is_index_column(index, e) {
+                if let (Some(l), Some(u)) = (const_to_key(index, lower), const_to_key(index, upper)) {
+                    scan_keys.push(ScanKeyData::new(Strategy::GreaterEqual, l));
+                    scan_keys.push(ScanKeyData::new(Strategy::LessEqual, u));
+                }
+            }
+        },
+        _ => {
+            if let Some(scan_key) = match_clause_to_index(index, expr) {
+                scan_keys.push(scan_key);
+            }
+        },
+    }
+}
+
+// `match_clause_to_index` in pg.
+//
+// Build scan key if the clause is "column op constant" (or "constant op
+// column") and the column is the key of the index.
+fn match_clause_to_index(index: &IndexInfo, expr: &Expr) -> Option<ScanKeyData> {
+    let (strategy, e1, e2) = match expr {
+        Expr::OpEq(e1, e2) => (Strategy::Equal, e1, e2),
+        Expr::OpLt(e1, e2) => (Strategy::Less, e1, e2),
+        Expr::OpLe(e1, e2) => (Strategy::LessEqual, e1, e2),
+        Expr::OpGt(e1, e2) => (Strategy::Greater, e1, e2),
+        Expr::OpGe(e1, e2) => (Strategy::GreaterEqual, e1, e2),
+        _ => return None,
+    };
+
+    if is_index_column(index, e1) {
+        return const_to_key(index, e2).map(|key| ScanKeyData::new(strategy, key));
+    }
+
+    // "constant op column" is commuted, e.g. "1 < id" is "id > 1"
+    // (`CommuteOpExpr` in pg).
+    if is_index_column(index, e2) {
+        return const_to_key(index, e1).map(|key| ScanKeyData::new(commute_strategy(strategy), key));
+    }
+
+    None
+}
+
+fn is_index_column(index: &IndexInfo, expr: &Expr) -> bool {
+    match expr {
+        Expr::ColumnRef(col_name) => col_name == &index.attr.name,
+        _ => false,
+    }
+}
+
+fn commute_strategy(strategy: Strategy) -> Strategy {
+    match strategy {
+        Strategy::Less => Strategy::Greater,
+        Strategy::LessEqual => Strategy::GreaterEqual,
+        Strategy::Equal => Strategy::Equal,
+        Strategy::GreaterEqual => Strategy::LessEqual,
+        Strategy::Greater => Strategy::Less,
+    }
+}
+
+// Returns None if the expression is not a constant or the index can not
+// compare it in the same way as `Datum::compare`. In that case sequential
+// scan is used, which also reports errors of the comparison.
+//
+// A string literal is converted to integer for an integer column, but an
+// integer is not converted for a text column because `Datum::compare`
+// compares column values as integers.
+fn const_to_key(index: &IndexInfo, expr: &Expr) -> Option<Vec<u8>> {
+    let datum = match (&index.attr.ty, expr) {
+        (TypeLabel::Integer, Expr::Number(n)) => Datum::Integer(*n),
+        (TypeLabel::Integer, Expr::String(s)) => Datum::Integer(int4in(s).ok()?),
+        (TypeLabel::Text, Expr::String(s)) | (TypeLabel::Varchar, Expr::String(s)) => Datum::Text(s.clone()),
+        _ => return None,
+    };
+
+    let mut key = Vec::new();
+    datum.into_type_value().write_bytes(&mut key).unwrap();
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::Stmt;
+    use spi::parser::StatementParser;
+    use storage_manager::RelationData;
+
+    fn index(ty: TypeLabel) -> IndexInfo {
+        IndexInfo {
+            name: "idx".to_string(),
+            relation: RelationData::new(10000, 10002),
+            attr: MiniAttributeRecord::new("id".to_string(), 10000, 10001, ty, 4),
+            attr_index: 0,
+        }
+    }
+
+    fn strategies(index: &IndexInfo, qual: &str) -> Vec<Strategy> {
+        let expr = match StatementParser::new().parse(&format!("select * from db.tbl where {}", qual)).unwrap() {
+            Stmt::SelectStmt(_, _, _, Some(expr), _) => expr,
+            _ => panic!("where clause should be parsed"),
+        };
+        let mut scan_keys = Vec::new();
+        match_clauses_to_index(index, &expr, &mut scan_keys);
+        scan_keys.into_iter().map(|k| k.strategy).collect()
+    }
+
+    #[test]
+    fn test_match_clauses_to_index() {
+        let index = index(TypeLabel::Integer);
+
+        assert_eq!(strategies(&index, "id = 1"), vec![Strategy::Equal]);
+        assert_eq!(strategies(&index, "1 < id"), vec![Strategy::Greater]);
+        assert_eq!(strategies(&index, "id <= 3 and 1 <= id"), vec![Strategy::LessEqual, Strategy::GreaterEqual]);
+        assert_eq!(strategies(&index, "age > 30 AND id > 5 AND id <> 9"), vec![Strategy::Greater]);
+        assert_eq!(strategies(&index, "id between 1 and 3"), vec![Strategy::GreaterEqual, Strategy::LessEqual]);
+        assert_eq!(strategies(&index, "id > 1 or id < 0"), vec![]);
+        assert_eq!(strategies(&index, "not id between 1 and 3"), vec![]);
+        assert_eq!(strategies(&index, "id + 1 = 2"), vec![]);
+        assert_eq!(strategies(&index, "age = 1"), vec![]);
+    }
+
+    #[test]
+    fn test_const_to_key() {
+        let int_index = index(TypeLabel::Integer);
+        assert_eq!(strategies(&int_index, "id = '9'"), vec![Strategy::Equal]);
+        assert_eq!(strategies(&int_index, "id = 'a'"), vec![]);
+
+        // Text column compared with integer is left to sequential scan.
+        let text_index = index(TypeLabel::Text);
+        assert_eq!(strategies(&text_index, "id = 'a'"), vec![Strategy::Equal]);
+        assert_eq!(strategies(&text_index, "id = 9"), vec![]);
+
+        let varchar_index = index(TypeLabel::Varchar);
+        assert_eq!(strategies(&varchar_index, "id < 'abcdef'"), vec![Strategy::Less]);
+    }
+}
@@ -0,0 +1,635 @@
+// Server speaking the frontend/backend protocol version 3.0 (`postmaster.c`
+// and `postgres.c` in pg), so that psql and client libraries can connect.
+//
+// Only the simple query protocol is supported, and clients are accepted
+// without authentication (`trust` in pg). Each connection is served by its
+// own thread, but sessions do not share the buffer pool, so only one session
+// can run at a time. Other clients are rejected with "too many clients"
+// instead of waiting for the session to end.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use catalog::mini_attribute::TypeLabel;
+use config::Config;
+use error::Error;
+use executor::tuptable::{ColumnDesc, Row};
+use pqformat::{Message, MessageReader, get_message, get_startup_packet};
+use spi::Executor;
+use xact::TBlockState;
+
+// `PG_PROTOCOL(3, 0)` in pg.
+const PG_PROTOCOL_3_0: i32 = 3 << 16;
+// Special request codes in startup packets (`pqcomm.h` in pg).
+const CANCEL_REQUEST_CODE: i32 = 1234 << 16 | 5678;
+const NEGOTIATE_SSL_CODE: i32 = 1234 << 16 | 5679;
+const NEGOTIATE_GSS_CODE: i32 = 1234 << 16 | 5680;
+
+// `MaxBackends` in pg.
+const MAX_BACKENDS: usize = 1;
+
+// `PQ_SEND_BUFFER_SIZE` in pg. Messages are sent to the client when the
+// buffer is full, so that rows of a large result are not kept in memory.
+const PQ_SEND_BUFFER_SIZE: usize = 8192;
+
+// Reported as `server_version` parameter. psql changes its behavior by this.
+const SERVER_VERSION: &str = "10.0";
+
+// OIDs of types in pg_type of pg.
+const INT4OID: i32 = 23;
+const TEXTOID: i32 = 25;
+const VARCHAROID: i32 = 1043;
+
+pub struct Postmaster {
+    config: Rc<Config>,
+    // The number of running sessions, shared by threads.
+    sessions: Arc<AtomicUsize>,
+}
+
+// A session holds a slot until it ends. The slot is released even if the
+// session panics.
+struct SessionSlot {
+    sessions: Arc<AtomicUsize>,
+}
+
+impl SessionSlot {
+    // `canAcceptConnections` in pg.
+    fn acquire(sessions: &Arc<AtomicUsize>) -> Option<SessionSlot> {
+        if sessions.fetch_add(1, Ordering::SeqCst) >= MAX_BACKENDS {
+            sessions.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(SessionSlot { sessions: sessions.clone() })
+    }
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.sessions.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Postmaster {
+    pub fn new(config: Rc<Config>) -> Postmaster {
+        Postmaster {
+            config: config,
+            sessions: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // `ServerLoop` in pg for TCP socket, e.g. "127.0.0.1:5432".
+    pub fn serve_tcp(&self, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!("listening on {}", listener.local_addr()?);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => self.backend_startup(stream),
+                Err(e) => warn!("could not accept new connection: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    // `ServerLoop` in pg for Unix domain socket, e.g. "/tmp/.s.PGSQL.5432".
+    pub fn serve_unix(&self, path: &Path) -> io::Result<()> {
+        // Remove the socket file left by the previous server.
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        info!("listening on {:?}", path);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => self.backend_startup(stream),
+                Err(e) => warn!("could not accept new connection: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    // `BackendStartup` in pg.
+    //
+    // Serve the connection in a new thread (pg forks a process), so that
+    // the server can reject other clients while a session is running.
+    // Config is shared by `Rc` in the thread, so it is copied.
+    fn backend_startup<S: Read + Write + Send + 'static>(&self, stream: S) {
+        let config = (*self.config).clone();
+        let sessions = self.sessions.clone();
+
+        let result = thread::Builder::new().name("backend".to_string()).spawn(move || {
+            let postmaster = Postmaster {
+                config: Rc::new(config),
+                sessions: sessions,
+            };
+            postmaster.backend_run(stream);
+        });
+
+        if let Err(e) = result {
+            warn!("could not start thread for new connection: {}", e);
+        }
+    }
+
+    // `BackendRun` in pg.
+    fn backend_run<S: Read + Write>(&self, mut stream: S) {
+        if let Err(e) = self.postgres_main(&mut stream) {
+            warn!("connection is closed: {}", e);
+        }
+    }
+
+    // `PostgresMain` in pg.
+    //
+    // Process messages from the client until it terminates the session.
+    pub fn postgres_main<S: Read + Write>(&self, stream: &mut S) -> io::Result<()> {
+        let mut out = Vec::new();
+
+        if !self.process_startup_packet(stream, &mut out)? {
+            return stream.write_all(&out);
+        }
+
+        // Dropped after the executor, which writes dirty pages at the end.
+        let _slot = match SessionSlot::acquire(&self.sessions) {
+            Some(slot) => slot,
+            None => {
+                out.clear();
+                send_error_response("FATAL", "53300", "sorry, too many clients already", &mut out)?;
+                return stream.write_all(&out);
+            },
+        };
+
+        let mut executor = match Executor::new(self.config.clone()) {
+            Ok(executor) => executor,
+            Err(e) => {
+                send_error_response("FATAL", e.code(), &e.to_string(), &mut out)?;
+                return stream.write_all(&out);
+            },
+        };
+        // Messages of the extended query protocol are skipped until Sync
+        // after an error is reported (`ignore_till_sync` in pg).
+        let mut ignore_till_sync = false;
+
+        loop {
+            if !ignore_till_sync {
+                send_ready_for_query(&executor, &mut out)?;
+            }
+
+            stream.write_all(&out)?;
+            stream.flush()?;
+            out.clear();
+
+            let (msgtype, body) = match get_message(stream)? {
+                Some(message) => message,
+                // The client closed the connection without Terminate.
+                None => return Ok(()),
+            };
+
+            match msgtype {
+                // Query
+                b'Q' => {
+                    let query = MessageReader::new(&body).get_string()?;
+                    exec_simple_query(&mut executor, &query, stream, &mut out)?;
+                },
+                // Terminate
+                b'X' => return Ok(()),
+                // Sync
+                b'S' => ignore_till_sync = false,
+                // Parse, Bind, Execute, Describe, Close, Flush and FunctionCall
+                b'P' | b'B' | b'E' | b'D' | b'C' | b'H' | b'F' => {
+                    if !ignore_till_sync {
+                        send_error(&Error::FeatureNotSupported("extended query protocol is not supported".to_string()), &mut out)?;
+                        ignore_till_sync = true;
+                    }
+                },
+                _ => {
+                    send_error_response("FATAL", "08P01", &format!("invalid frontend message type {}", msgtype), &mut out)?;
+                    return stream.write_all(&out);
+                },
+            }
+        }
+    }
+
+    // `ProcessStartupPacket` in pg.
+    //
+    // Returns false if the connection should be closed.
+    fn process_startup_packet<S: Read + Write>(&self, stream: &mut S, out: &mut Vec<u8>) -> io::Result<bool> {
+        loop {
+            let packet = match get_startup_packet(stream)? {
+                Some(packet) => packet,
+                None => return Ok(false),
+            };
+            let mut reader = MessageReader::new(&packet);
+            let code = reader.get_int32()?;
+
+            match code {
+                // Encryption is not supported, so the client continues
+                // without it.
+                NEGOTIATE_SSL_CODE | NEGOTIATE_GSS_CODE => {
+                    stream.write_all(b"N")?;
+                    stream.flush()?;
+                },
+                CANCEL_REQUEST_CODE => return Ok(false),
+                PG_PROTOCOL_3_0 => {
+                    // Parameters, e.g. "user" and "database". Tables are
+                    // always qualified with database names, so they are
+                    // not used.
+                    loop {
+                        let name = reader.get_string()?;
+
+                        if name.is_empty() {
+                            break;
+                        }
+
+                        let value = reader.get_string()?;
+                        debug!("startup parameter {}: {}", name, value);
+                    }
+
+                    // AuthenticationOk
+                    let mut msg = Message::new(b'R');
+                    msg.send_int32(0);
+                    msg.end_message(out)?;
+
+                    send_parameter_status("server_version", SERVER_VERSION, out)?;
+                    send_parameter_status("server_encoding", "UTF8", out)?;
+                    send_parameter_status("client_encoding", "UTF8", out)?;
+                    send_parameter_status("DateStyle", "ISO, MDY", out)?;
+                    send_parameter_status("integer_datetimes", "on", out)?;
+                    send_parameter_status("standard_conforming_strings", "on", out)?;
+                    return Ok(true);
+                },
+                _ => {
+                    let msg = format!("unsupported frontend protocol {}.{}", code >> 16, code & 0xffff);
+                    send_error_response("FATAL", "0A000", &msg, out)?;
+                    return Ok(false);
+                },
+            }
+        }
+    }
+}
+
+// `exec_simple_query` in pg.
+//
+// The query may have multiple statements. Statements after the failed one
+// are not executed. Rows are sent while they are fetched, so the client may
+// receive some rows before an error.
+fn exec_simple_query<S: Write>(executor: &mut Executor, query: &str, stream: &mut S, out: &mut Vec<u8>) -> io::Result<()> {
+    let stmts = match Executor::parse_script(query) {
+        Ok(stmts) => stmts,
+        Err(e) => return send_error(&e, out),
+    };
+
+    if stmts.is_empty() {
+        // EmptyQueryResponse
+        return Message::new(b'I').end_message(out);
+    }
+
+    for stmt in stmts.into_iter() {
+        // The connection is lost. The statement is aborted, then the
+        // session ends.
+        let mut client_error = None;
+
+        let result = executor.portal_run(stmt, &mut |portal| {
+            to_client_error(send_row_description(portal.columns(), out), &mut client_error)?;
+
+            while let Some(row) = portal.fetch()? {
+                to_client_error(printtup(&row, out), &mut client_error)?;
+
+                // `internal_flush` in pg.
+                if out.len() >= PQ_SEND_BUFFER_SIZE {
+                    to_client_error(stream.write_all(out), &mut client_error)?;
+                    out.clear();
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Some(e) = client_error {
+            return Err(e);
+        }
+
+        match result {
+            Ok(result) => {
+                // CommandComplete
+                let mut msg = Message::new(b'C');
+                msg.send_string(&result.completion_tag());
+                msg.end_message(out)?;
+            },
+            Err(e) => return send_error(&e, out),
+        }
+    }
+
+    Ok(())
+}
+
+// Keep the error of the connection, and abort the statement by the error.
+fn to_client_error(result: io::Result<()>, client_error: &mut Option<io::Error>) -> Result<(), Error> {
+    result.map_err(|e| {
+        let err = Error::Io(io::Error::new(e.kind(), format!("could not send data to client: {}", e)));
+        *client_error = Some(e);
+        err
+    })
+}
+
+// `SendRowDescriptionMessage` in pg.
+fn send_row_description(columns: &[ColumnDesc], out: &mut Vec<u8>) -> io::Result<()> {
+    let mut msg = Message::new(b'T');
+    msg.send_int16(columns.len() as i16);
+
+    for column in columns.iter() {
+        let (type_oid, typlen) = match column.ty {
+            TypeLabel::Integer => (INT4OID, 4),
+            TypeLabel::Text => (TEXTOID, -1),
+            TypeLabel::Varchar => (VARCHAROID, -1),
+        };
+
+        msg.send_string(&column.name);
+        // table oid and column number, which are unknown
+        msg.send_int32(0);
+        msg.send_int16(0);
+        msg.send_int32(type_oid);
+        msg.send_int16(typlen);
+        // typmod
+        msg.send_int32(-1);
+        // text format
+        msg.send_int16(0);
+    }
+
+    msg.end_message(out)
+}
+
+// `printtup` in pg. Values are sent in text format.
+fn printtup(row: &Row, out: &mut Vec<u8>) -> io::Result<()> {
+    let mut msg = Message::new(b'D');
+    msg.send_int16(row.len() as i16);
+
+    for value in row.iter() {
+        if value.is_null() {
+            msg.send_int32(-1);
+        } else {
+            let s = value.as_string();
+            msg.send_int32(s.len() as i32);
+            msg.send_bytes(s.as_bytes());
+        }
+    }
+
+    msg.end_message(out)
+}
+
+// ReadyForQuery with the transaction status (`ReadyForQuery` and
+// `TransactionBlockStatusCode` in pg).
+fn send_ready_for_query(executor: &Executor, out: &mut Vec<u8>) -> io::Result<()> {
+    let status = match executor.block_state() {
+        TBlockState::Default => b'I',
+        TBlockState::InProgress => b'T',
+        TBlockState::Abort => b'E',
+    };
+
+    let mut msg = Message::new(b'Z');
+    msg.send_byte(status);
+    msg.end_message(out)
+}
+
+fn send_parameter_status(name: &str, value: &str, out: &mut Vec<u8>) -> io::Result<()> {
+    let mut msg = Message::new(b'S');
+    msg.send_string(name);
+    msg.send_string(value);
+    msg.end_message(out)
+}
+
+fn send_error(e: &Error, out: &mut Vec<u8>) -> io::Result<()> {
+    send_error_response("ERROR", e.code(), &e.to_string(), out)
+}
+
+// `send_message_to_frontend` in pg.
+fn send_error_response(severity: &str, code: &str, message: &str, out: &mut Vec<u8>) -> io::Result<()> {
+    let mut msg = Message::new(b'E');
+
+    for (field, value) in [(b'S', severity), (b'V', severity), (b'C', code), (b'M', message)].iter() {
+        msg.send_byte(*field);
+        msg.send_string(value);
+    }
+
+    msg.send_byte(0);
+    msg.end_message(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use tempfile::tempdir;
+    use init::InitCommand;
+
+    // Client messages are read from `input`, and server messages are
+    // written to `output`.
+    struct TestStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for TestStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for TestStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn startup_packet(code: i32, params: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.write_i32::<BigEndian>(code).unwrap();
+
+        if code == PG_PROTOCOL_3_0 {
+            for (name, value) in params.iter() {
+                body.extend_from_slice(name.as_bytes());
+                body.push(0);
+                body.extend_from_slice(value.as_bytes());
+                body.push(0);
+            }
+
+            body.push(0);
+        }
+
+        let mut packet = Vec::new();
+        packet.write_i32::<BigEndian>(body.len() as i32 + 4).unwrap();
+        packet.extend(body);
+        packet
+    }
+
+    fn query(q: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut msg = Message::new(b'Q');
+        msg.send_string(q);
+        msg.end_message(&mut buf).unwrap();
+        buf
+    }
+
+    fn run(postmaster: &Postmaster, input: Vec<u8>) -> Vec<(u8, Vec<u8>)> {
+        let mut stream = TestStream { input: Cursor::new(input), output: Vec::new() };
+        postmaster.postgres_main(&mut stream).unwrap();
+
+        let mut r = Cursor::new(stream.output);
+        let mut messages = Vec::new();
+
+        while let Some(message) = get_message(&mut r).unwrap() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    fn types(messages: &[(u8, Vec<u8>)]) -> String {
+        messages.iter().map(|m| m.0 as char).collect()
+    }
+
+    // Returns the value of the field of ErrorResponse.
+    fn error_field(body: &[u8], field: u8) -> String {
+        let mut reader = MessageReader::new(body);
+
+        loop {
+            let f = reader.get_bytes(1).unwrap()[0];
+            let value = reader.get_string().unwrap();
+
+            if f == field {
+                return value;
+            }
+        }
+    }
+
+    fn setup() -> (tempfile::TempDir, Postmaster) {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        InitCommand::new(config.clone()).execute().unwrap();
+        (dir, Postmaster::new(config))
+    }
+
+    #[test]
+    fn test_simple_query() {
+        let (_dir, postmaster) = setup();
+        let mut input = startup_packet(PG_PROTOCOL_3_0, &[("user", "minidb"), ("database", "db")]);
+        input.extend(query("create database db; create table db.tbl (id int, name text)"));
+        input.extend(query("insert into db.tbl (id, name) values (1, 'a'), (2, null); select * from db.tbl"));
+        input.extend(query("begin; select * from db.notbl"));
+        input.extend(query(""));
+        input.extend(query("rollback"));
+        Message::new(b'X').end_message(&mut input).unwrap();
+
+        let messages = run(&postmaster, input);
+        // AuthenticationOk, ParameterStatus and ReadyForQuery
+        assert_eq!(types(&messages[..8]), "RSSSSSSZ");
+        assert_eq!(messages[7].1, vec![b'I']);
+
+        let messages = &messages[8..];
+        assert_eq!(types(messages), "CCZCTDDCZCEZIZCZ");
+        assert_eq!(messages[0].1, b"CREATE DATABASE\0".to_vec());
+        assert_eq!(messages[3].1, b"INSERT 0 2\0".to_vec());
+
+        let mut reader = MessageReader::new(&messages[4].1);
+        assert_eq!(reader.get_int16().unwrap(), 2);
+        assert_eq!(reader.get_string().unwrap(), "id");
+        reader.get_bytes(6).unwrap();
+        assert_eq!(reader.get_int32().unwrap(), INT4OID);
+
+        let mut reader = MessageReader::new(&messages[5].1);
+        assert_eq!(reader.get_int16().unwrap(), 2);
+        assert_eq!(reader.get_int32().unwrap(), 1);
+        assert_eq!(reader.get_bytes(1).unwrap(), b"1");
+        let mut reader = MessageReader::new(&messages[6].1);
+        reader.get_bytes(2 + 4 + 1).unwrap();
+        assert_eq!(reader.get_int32().unwrap(), -1);
+        assert_eq!(messages[7].1, b"SELECT 2\0".to_vec());
+
+        // The error aborts the transaction block.
+        assert_eq!(error_field(&messages[10].1, b'C'), "42P01");
+        assert_eq!(messages[11].1, vec![b'E']);
+        assert_eq!(messages[15].1, vec![b'I']);
+    }
+
+    #[test]
+    fn test_rows_before_error() {
+        let (_dir, postmaster) = setup();
+        let values: Vec<String> = (0..1000).map(|i| format!("({})", i)).collect();
+        let mut input = startup_packet(PG_PROTOCOL_3_0, &[]);
+        input.extend(query(&format!("create database db; create table db.tbl (id int); insert into db.tbl (id) values {}", values.join(", "))));
+        input.extend(query("select 1 / (id - 900) from db.tbl; select id from db.tbl"));
+        Message::new(b'X').end_message(&mut input).unwrap();
+
+        let messages = run(&postmaster, input);
+        let messages = &messages[8 + 4..];
+
+        // Rows fetched before the error are sent, which are more than the
+        // send buffer. The statement after the error is not executed.
+        assert_eq!(types(messages), format!("T{}EZ", "D".repeat(900)));
+        assert_eq!(error_field(&messages[901].1, b'C'), "22012");
+    }
+
+    #[test]
+    fn test_ssl_request() {
+        let (_dir, postmaster) = setup();
+        let mut input = startup_packet(NEGOTIATE_SSL_CODE, &[]);
+        input.extend(startup_packet(PG_PROTOCOL_3_0, &[]));
+
+        let mut stream = TestStream { input: Cursor::new(input), output: Vec::new() };
+        postmaster.postgres_main(&mut stream).unwrap();
+        assert_eq!(stream.output[0], b'N');
+        assert_eq!(stream.output[1], b'R');
+    }
+
+    #[test]
+    fn test_too_many_clients() {
+        let (_dir, postmaster) = setup();
+        let slot = SessionSlot::acquire(&postmaster.sessions).unwrap();
+
+        let messages = run(&postmaster, startup_packet(PG_PROTOCOL_3_0, &[]));
+        assert_eq!(types(&messages), "E");
+        assert_eq!(error_field(&messages[0].1, b'S'), "FATAL");
+        assert_eq!(error_field(&messages[0].1, b'C'), "53300");
+
+        // The slot is available after the session ends.
+        drop(slot);
+        let messages = run(&postmaster, startup_packet(PG_PROTOCOL_3_0, &[]));
+        assert_eq!(types(&messages), "RSSSSSSZ");
+        assert_eq!(postmaster.sessions.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_unsupported_messages() {
+        let (_dir, postmaster) = setup();
+        let mut input = startup_packet(PG_PROTOCOL_3_0, &[]);
+
+        for msgtype in [b'P', b'B', b'E', b'S'].iter() {
+            Message::new(*msgtype).end_message(&mut input).unwrap();
+        }
+
+        Message::new(b'?').end_message(&mut input).unwrap();
+
+        let messages = run(&postmaster, input);
+        assert_eq!(types(&messages[8..]), "EZE");
+        assert_eq!(error_field(&messages[8].1, b'C'), "0A000");
+        assert_eq!(error_field(&messages[10].1, b'S'), "FATAL");
+
+        let messages = run(&postmaster, startup_packet(2 << 16, &[]));
+        assert_eq!(types(&messages), "E");
+    }
+}
@@ -0,0 +1,188 @@
+// Messages of the frontend/backend protocol (`pqformat.c` and `pqcomm.c`
+// in pg).
+//
+// A message is a type byte followed by the length (Int32, including itself)
+// and the body. Integers are in network byte order and strings are
+// terminated by '\0'. Startup packets do not have the type byte.
+
+use std::io::{self, Read, Write, ErrorKind};
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+// `MAX_STARTUP_PACKET_LENGTH` in pg.
+const MAX_STARTUP_PACKET_LENGTH: usize = 10000;
+// `PQ_LARGE_MESSAGE_LIMIT` in pg.
+const MAX_MESSAGE_LENGTH: usize = 0x3fffffff;
+
+// `StringInfoData` used with `pq_beginmessage` and `pq_endmessage` in pg.
+pub struct Message {
+    msgtype: u8,
+    data: Vec<u8>,
+}
+
+impl Message {
+    // `pq_beginmessage` in pg.
+    pub fn new(msgtype: u8) -> Message {
+        Message {
+            msgtype: msgtype,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn send_byte(&mut self, b: u8) {
+        self.data.push(b);
+    }
+
+    pub fn send_int16(&mut self, i: i16) {
+        self.data.write_i16::<BigEndian>(i).unwrap();
+    }
+
+    pub fn send_int32(&mut self, i: i32) {
+        self.data.write_i32::<BigEndian>(i).unwrap();
+    }
+
+    pub fn send_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // `pq_sendstring` in pg.
+    pub fn send_string(&mut self, s: &str) {
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+    }
+
+    // `pq_endmessage` in pg.
+    pub fn end_message(&self, w: &mut Write) -> io::Result<()> {
+        w.write_all(&[self.msgtype])?;
+        w.write_i32::<BigEndian>(self.data.len() as i32 + 4)?;
+        w.write_all(&self.data)
+    }
+}
+
+// `pq_getmsgint` and `pq_getmsgstring` in pg.
+pub struct MessageReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> MessageReader<'a> {
+    pub fn new(data: &'a [u8]) -> MessageReader<'a> {
+        MessageReader {
+            data: data,
+            cursor: 0,
+        }
+    }
+
+    pub fn get_int16(&mut self) -> io::Result<i16> {
+        let bytes = self.get_bytes(2)?;
+        Ok(BigEndian::read_i16(bytes))
+    }
+
+    pub fn get_int32(&mut self) -> io::Result<i32> {
+        let bytes = self.get_bytes(4)?;
+        Ok(BigEndian::read_i32(bytes))
+    }
+
+    pub fn get_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.cursor < len {
+            return Err(invalid_message("insufficient data left in message"));
+        }
+
+        let bytes = &self.data[self.cursor..self.cursor + len];
+        self.cursor += len;
+        Ok(bytes)
+    }
+
+    pub fn get_string(&mut self) -> io::Result<String> {
+        let len = self.data[self.cursor..].iter().position(|b| *b == 0)
+                      .ok_or_else(|| invalid_message("invalid string in message"))?;
+        let bytes = self.get_bytes(len)?;
+        self.cursor += 1;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_message("invalid byte sequence for encoding \"UTF8\""))
+    }
+}
+
+fn invalid_message(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// Read the length and the body. Returns None at EOF.
+fn get_message_body(r: &mut Read, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0; 4];
+
+    match r.read_exact(&mut len_buf) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = BigEndian::read_i32(&len_buf);
+
+    if len < 4 || len as usize - 4 > max_len {
+        return Err(invalid_message(&format!("invalid message length {}", len)));
+    }
+
+    let mut body = vec![0; len as usize - 4];
+    r.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+// `ProcessStartupPacket` in pg reads the packet in this way.
+pub fn get_startup_packet(r: &mut Read) -> io::Result<Option<Vec<u8>>> {
+    get_message_body(r, MAX_STARTUP_PACKET_LENGTH)
+}
+
+// `pq_getmessage` in pg. Returns the type and the body of the message, or
+// None if the client closed the connection.
+pub fn get_message(r: &mut Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut msgtype = [0; 1];
+
+    match r.read_exact(&mut msgtype) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    match get_message_body(r, MAX_MESSAGE_LENGTH)? {
+        Some(body) => Ok(Some((msgtype[0], body))),
+        None => Err(invalid_message("unexpected EOF within message")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_message() {
+        let mut buf = Vec::new();
+        let mut msg = Message::new(b'Q');
+        msg.send_string("select");
+        msg.send_int16(-1);
+        msg.send_int32(196608);
+        msg.end_message(&mut buf).unwrap();
+        assert_eq!(&buf[..5], &[b'Q', 0, 0, 0, 17]);
+
+        let mut r = Cursor::new(buf);
+        let (msgtype, body) = get_message(&mut r).unwrap().unwrap();
+        assert_eq!(msgtype, b'Q');
+
+        let mut reader = MessageReader::new(&body);
+        assert_eq!(reader.get_string().unwrap(), "select");
+        assert_eq!(reader.get_int16().unwrap(), -1);
+        assert_eq!(reader.get_int32().unwrap(), 196608);
+        assert!(reader.get_int32().is_err());
+        assert!(get_message(&mut r).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let mut r = Cursor::new(vec![b'Q', 0, 0, 0, 3]);
+        assert_eq!(get_message(&mut r).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        let mut r = Cursor::new(vec![0, 1, 0, 0]);
+        assert!(get_startup_packet(&mut r).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+// Interactive terminal (`psql` in pg).
+//
+// Statements end with `;` and may span multiple lines. Lines which start
+// with a backslash are meta-commands handled by the shell itself. All
+// statements run in one session, so the catalog cache and the buffer pool
+// are kept until the shell exits.
+
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+use config::Config;
+use error::Error;
+use executor::tuptable::{Portal, QueryResult};
+use spi::Executor;
+
+// `FETCH_COUNT` in psql. Rows of SELECT are fetched and printed in batches
+// of this size, so a large result is not kept in memory.
+const FETCH_COUNT: usize = 100;
+
+const HELP: &str = "\
+General
+  \\q          quit
+  \\?          show this help
+
+Informational
+  \\l          list databases
+  \\d          list tables and indexes
+  \\d DB.NAME  describe table
+";
+
+pub struct Shell {
+    executor: Executor,
+    // Lines of the statement being read (`query_buf` in pg).
+    query_buf: String,
+}
+
+impl Shell {
+    pub fn new(config: Rc<Config>) -> Result<Shell, Error> {
+        Ok(Shell {
+            executor: Executor::new(config)?,
+            query_buf: String::new(),
+        })
+    }
+
+    // `MainLoop` in pg.
+    pub fn run(&mut self) -> Result<(), ReadlineError> {
+        let mut editor = DefaultEditor::new()?;
+        let stdout = io::stdout();
+
+        loop {
+            let prompt = if self.query_buf.is_empty() { "minidb=> " } else { "minidb-> " };
+
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        editor.add_history_entry(line.as_str())?;
+                    }
+
+                    if !self.process_line(&line, &mut stdout.lock())? {
+                        break;
+                    }
+                },
+                // Ctrl-C discards the statement being read.
+                Err(ReadlineError::Interrupted) => self.query_buf.clear(),
+                // Ctrl-D
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Execute statements completed by the line. Returns false if the shell
+    // should exit.
+    pub fn process_line(&mut self, line: &str, out: &mut Write) -> io::Result<bool> {
+        if self.query_buf.is_empty() && line.trim_start().starts_with('\\') {
+            return self.exec_command(line.trim(), out);
+        }
+
+        self.query_buf.push_str(line);
+        self.query_buf.push('\n');
+
+        while let Some(end) = find_statement_end(&self.query_buf) {
+            let rest = self.query_buf.split_off(end + 1);
+            let query = self.query_buf[..end].trim().to_string();
+            self.query_buf = rest;
+
+            if !query.is_empty() {
+                self.send_query(&query, out)?;
+            }
+        }
+
+        if self.query_buf.trim().is_empty() {
+            self.query_buf.clear();
+        }
+
+        Ok(true)
+    }
+
+    // `SendQuery` in pg.
+    fn send_query(&mut self, query: &str, out: &mut Write) -> io::Result<()> {
+        match self.executor.execute_query_with(query, &mut |portal| print_rows(portal, out)) {
+            Ok(result) => print_result(&result, out),
+            Err(e) => print_error(&e, out),
+        }
+    }
+
+    // `HandleSlashCmds` in pg.
+    fn exec_command(&mut self, line: &str, out: &mut Write) -> io::Result<bool> {
+        let mut args = line[1..].split_whitespace();
+
+        match (args.next(), args.next()) {
+            (Some("q"), None) => return Ok(false),
+            (Some("?"), None) => write!(out, "{}", HELP)?,
+            (Some("l"), None) => self.list_databases(out)?,
+            (Some("d"), None) => self.list_tables(out)?,
+            (Some("d"), Some(name)) => self.describe_table(name, out)?,
+            _ => writeln!(out, "Invalid command {}. Try \\? for help.", line)?,
+        }
+
+        Ok(true)
+    }
+
+    // `listAllDbs` in pg.
+    fn list_databases(&self, out: &mut Write) -> io::Result<()> {
+        let cmgr = self.executor.catalog();
+        let mut rows: Vec<Vec<String>> = cmgr.database_rm.records.iter()
+                                             .map(|db| vec![db.name.clone(), db.oid.to_string()])
+                                             .collect();
+        rows.sort();
+
+        print_table(Some("List of databases"), &["Name", "Oid"], &[false, true], &rows, true, out)?;
+        writeln!(out, "")
+    }
+
+    // `listTables` in pg.
+    fn list_tables(&self, out: &mut Write) -> io::Result<()> {
+        let cmgr = self.executor.catalog();
+        let dbname = |db_oid| {
+            cmgr.database_rm.records.iter()
+                .find(|db| db.oid == db_oid)
+                .map(|db| db.name.clone())
+                .unwrap_or(db_oid.to_string())
+        };
+        let mut rows: Vec<Vec<String>> = Vec::new();
+
+        for class in cmgr.class_rm.records.iter() {
+            rows.push(vec![dbname(class.db_oid), class.name.clone(), "table".to_string()]);
+        }
+
+        for index in cmgr.index_rm.records.iter() {
+            rows.push(vec![dbname(index.db_oid), index.name.clone(), "index".to_string()]);
+        }
+
+        if rows.is_empty() {
+            return writeln!(out, "Did not find any relations.");
+        }
+
+        rows.sort();
+        print_table(Some("List of relations"), &["Database", "Name", "Type"], &[false, false, false], &rows, true, out)?;
+        writeln!(out, "")
+    }
+
+    // `describeOneTableDetails` in pg.
+    fn describe_table(&self, name: &str, out: &mut Write) -> io::Result<()> {
+        let cmgr = self.executor.catalog();
+        let names: Vec<&str> = name.split('.').collect();
+
+        if names.len() != 2 {
+            return print_error(&Error::UndefinedTable(format!("Did not find any relation named \"{}\".", name)), out);
+        }
+
+        let (db_oid, table_oid) = match cmgr.get_relation_oids(names[0], names[1]) {
+            Ok(oids) => oids,
+            Err(e) => return print_error(&e, out),
+        };
+        let rows: Vec<Vec<String>> = cmgr.attribute_rm.attributes(db_oid, table_oid).iter()
+                                         .map(|attr| vec![attr.name.clone(), format_type(attr)])
+                                         .collect();
+
+        print_table(Some(&format!("Table \"{}\"", name)), &["Column", "Type"], &[false, false], &rows, true, out)?;
+
+        let indexes = cmgr.index_rm.indexes(db_oid, table_oid);
+
+        if !indexes.is_empty() {
+            writeln!(out, "Indexes:")?;
+
+            for index in indexes.iter() {
+                writeln!(out, "    \"{}\" btree ({})", index.name, index.column)?;
+            }
+        }
+
+        writeln!(out, "")
+    }
+}
+
+// Returns the position of `;` which ends the first statement. `;` in
+// string literals and "--" comments is skipped.
+fn find_statement_end(buf: &str) -> Option<usize> {
+    let mut in_quote = false;
+    let mut in_comment = false;
+    let mut chars = buf.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\n' if in_comment => in_comment = false,
+            _ if in_comment => {},
+            '\'' => in_quote = !in_quote,
+            '-' if !in_quote && chars.peek().map(|&(_, c)| c) == Some('-') => in_comment = true,
+            ';' if !in_quote => return Some(i),
+            _ => {},
+        }
+    }
+
+    None
+}
+
+// `format_type_be` in pg.
+fn format_type(attr: &MiniAttributeRecord) -> String {
+    match attr.ty {
+        TypeLabel::Integer => "integer".to_string(),
+        TypeLabel::Text => "text".to_string(),
+        TypeLabel::Varchar if attr.len > 0 => format!("varchar({})", attr.len),
+        TypeLabel::Varchar => "varchar".to_string(),
+    }
+}
+
+fn print_error(e: &Error, out: &mut Write) -> io::Result<()> {
+    writeln!(out, "Error: '{}' (SQLSTATE {})", e, e.code())
+}
+
+// `ExecQueryUsingCursor` in pg.
+//
+// Rows are printed as a table in batches of `FETCH_COUNT`, and NULL is
+// printed as an empty string. Columns are aligned in each batch, and the
+// header is printed only with the first one.
+fn print_rows(portal: &mut Portal, out: &mut Write) -> Result<(), Error> {
+    let headers: Vec<String> = portal.columns().iter().map(|c| c.name.clone()).collect();
+    let headers: Vec<&str> = headers.iter().map(|h| h.as_str()).collect();
+    let right_align: Vec<bool> = portal.columns().iter().map(|c| c.ty == TypeLabel::Integer).collect();
+    let mut start_table = true;
+
+    loop {
+        let mut rows: Vec<Vec<String>> = Vec::new();
+
+        while rows.len() < FETCH_COUNT {
+            match portal.fetch()? {
+                Some(row) => rows.push(row.iter().map(|v| if v.is_null() { String::new() } else { v.as_string() }).collect()),
+                None => break,
+            }
+        }
+
+        if rows.is_empty() && !start_table {
+            break;
+        }
+
+        print_table(None, &headers, &right_align, &rows, start_table, out)?;
+        start_table = false;
+
+        if rows.len() < FETCH_COUNT {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// `PrintQueryResult` in pg.
+//
+// Rows are printed by `print_rows`, followed by the number of them. Other
+// statements print the command tag.
+fn print_result(result: &QueryResult, out: &mut Write) -> io::Result<()> {
+    if result.columns.is_empty() {
+        return writeln!(out, "{}", result.completion_tag());
+    }
+
+    writeln!(out, "({} {})", result.processed, if result.processed == 1 { "row" } else { "rows" })?;
+    writeln!(out, "")
+}
+
+// `print_aligned_text` in pg.
+//
+//  id | name
+// ----+------
+//   1 | foo
+//
+// The title and the header are printed only if `start_table` is true.
+fn print_table(title: Option<&str>, headers: &[&str], right_align: &[bool], rows: &[Vec<String>], start_table: bool, out: &mut Write) -> io::Result<()> {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+
+    for row in rows.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    if start_table {
+        if let Some(title) = title {
+            let total_width = widths.iter().map(|w| w + 3).sum::<usize>() - 1;
+            writeln!(out, "{}", format!("{:^w$}", title, w = total_width).trim_end())?;
+        }
+
+        let line: Vec<String> = headers.iter().zip(widths.iter())
+                                       .map(|(h, w)| format!(" {:^w$} ", h, w = w))
+                                       .collect();
+        writeln!(out, "{}", line.join("|").trim_end())?;
+
+        let line: Vec<String> = widths.iter().map(|w| "-".repeat(w + 2)).collect();
+        writeln!(out, "{}", line.join("+"))?;
+    }
+
+    for row in rows.iter() {
+        let line: Vec<String> = row.iter().enumerate().map(|(i, cell)| {
+            if right_align[i] {
+                format!(" {:>w$} ", cell, w = widths[i])
+            } else {
+                format!(" {:<w$} ", cell, w = widths[i])
+            }
+        }).collect();
+        writeln!(out, "{}", line.join("|").trim_end())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use init::InitCommand;
+
+    fn run(shell: &mut Shell, lines: &[&str]) -> String {
+        let mut out = Vec::new();
+
+        for line in lines.iter() {
+            shell.process_line(line, &mut out).unwrap();
+        }
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_find_statement_end() {
+        assert_eq!(find_statement_end("select 1"), None);
+        assert_eq!(find_statement_end("begin; commit;"), Some(5));
+        assert_eq!(find_statement_end("insert ... values ('a;b');"), Some(25));
+        assert_eq!(find_statement_end("insert ... values ('a'';"), None);
+        assert_eq!(find_statement_end("-- don't;\nbegin;"), Some(15));
+    }
+
+    #[test]
+    fn test_shell() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        InitCommand::new(config.clone()).execute().unwrap();
+        let mut shell = Shell::new(config.clone()).unwrap();
+
+        assert_eq!(run(&mut shell, &["create database db;"]), "CREATE DATABASE\n");
+        assert_eq!(run(&mut shell, &["create table db.tbl", "(id int, name varchar(10));"]), "CREATE TABLE\n");
+        assert_eq!(
+            run(&mut shell, &["insert into db.tbl (id, name) values (1, 'a;b'); insert into db.tbl (id) values (20);"]),
+            "INSERT 0 1\nINSERT 0 1\n"
+        );
+        assert_eq!(
+            run(&mut shell, &["select * from db.tbl", "order by id", ";"]),
+            concat!(
+                " id | name\n",
+                "----+------\n",
+                "  1 | a;b\n",
+                " 20 |\n",
+                "(2 rows)\n",
+                "\n",
+            )
+        );
+        assert_eq!(run(&mut shell, &["select * from db.notbl;"]), "Error: 'notbl table does not exist under the db database.' (SQLSTATE 42P01)\n");
+
+        assert_eq!(run(&mut shell, &["create index idx on db.tbl (id);"]), "CREATE INDEX\n");
+        assert_eq!(
+            run(&mut shell, &["\\d db.tbl"]),
+            concat!(
+                "    Table \"db.tbl\"\n",
+                " Column |    Type\n",
+                "--------+-------------\n",
+                " id     | integer\n",
+                " name   | varchar(10)\n",
+                "Indexes:\n",
+                "    \"idx\" btree (id)\n",
+                "\n",
+            )
+        );
+        assert!(run(&mut shell, &["\\l"]).contains(" db   |"));
+
+        // Rows are printed in batches with the header of the first one.
+        let values: Vec<String> = (0..150).map(|i| format!("({})", i)).collect();
+        run(&mut shell, &["create table db.nums (id int);", &format!("insert into db.nums (id) values {};", values.join(", "))]);
+        let output = run(&mut shell, &["select id from db.nums;"]);
+        assert_eq!(output.matches("---").count(), 1);
+        assert!(output.contains("\n 99\n 100\n"));
+        assert!(output.ends_with(" 149\n(150 rows)\n\n"));
+        assert!(!shell.process_line("\\q", &mut Vec::new()).unwrap());
+    }
+}
@@ -1,76 +1,259 @@
 // `spi.c` in pg.
+use std::io;
 use std::rc::Rc;
+use std::sync::RwLock;
 
-use ast::{Stmt, Expr};
-use dml::{InsertIntoCommand, SelectFromCommand, CountCommand, DeleteCommand};
+use ast::{Stmt, Expr, ResTarget, TransactionStmtKind};
+use ddl::{CreateDatabaseCommand, CreateTableCommand, CreateIndexCommand, DropDatabaseCommand, DropTableCommand};
+use dml::{InsertIntoCommand, SelectFromCommand, CountCommand, DeleteCommand, UpdateCommand};
 use tuple::{KeyValueBuilder};
-use config::{Config};
+use buffer_manager::BufferManager;
+use config::{Config, N_BUFFERS};
+use error::Error;
+use oid_manager::OidManager;
 use catalog::catalog_manager::CatalogManager;
+use executor::tuptable::{Dest, QueryResult};
+use miscinit::{DataDirLockFile, create_data_dir_lock_file};
+use tuplesort::remove_pg_temp_files;
+use vacuum::VacuumCommand;
+use xact::{TransactionState, TBlockState};
 
 lalrpop_mod!(pub parser);
 
-pub struct Executor<'a> {
+// Executor is a session. Transaction block started by BEGIN continues
+// across `execute_query` calls until COMMIT or ROLLBACK.
+pub struct Executor {
     config: Rc<Config>,
-    cmgr: &'a CatalogManager
+    // Catalog cache, which is rebuilt after DDL.
+    cmgr: CatalogManager,
+    // Buffer pool shared by statements of the session. Dirty pages are
+    // written when they are evicted or the session ends.
+    bm: RwLock<BufferManager>,
+    xact: TransactionState,
+    // Startup replays WAL and aborts transactions left in progress, so
+    // only one session can use the data directory at a time. Declared last
+    // to be released after the checkpoint of `bm` and the abort of `xact`.
+    _lock_file: DataDirLockFile,
 }
 
-impl<'a> Executor<'a> {
-    pub fn new(config: Rc<Config>, cmgr: &'a CatalogManager) -> Executor<'a> {
-        Executor {
-            config: config,
-            cmgr: cmgr
-        }
+impl Executor {
+    // Fails if another session uses the data directory, or WAL can not be
+    // read or replayed.
+    pub fn new(config: Rc<Config>) -> Result<Executor, Error> {
+        let lock_file = create_data_dir_lock_file(&config)?;
+        remove_pg_temp_files(&config).map_err(|e| {
+            io::Error::new(e.kind(), format!("could not remove temporary files in {:?}: {}", config.temp_dir_path(), e))
+        })?;
+
+        Ok(Executor {
+            config: config.clone(),
+            cmgr: CatalogManager::new(config.clone()),
+            bm: RwLock::new(BufferManager::new(N_BUFFERS, config.clone())?),
+            xact: TransactionState::new(config),
+            _lock_file: lock_file,
+        })
+    }
+
+    pub fn catalog(&self) -> &CatalogManager {
+        &self.cmgr
+    }
+
+    pub fn block_state(&self) -> TBlockState {
+        self.xact.block_state()
     }
 
     // See `SPI_execute` in pg.
-    pub fn execute_query(&self, query: &str) -> Result<(), String> {
+    //
+    // Returns the number of rows processed by the statement. Rows of SELECT
+    // are discarded. Use `execute_query_with` to fetch them.
+    pub fn execute_query(&mut self, query: &str) -> Result<QueryResult, Error> {
+        self.execute_query_with(query, &mut |portal| portal.discard())
+    }
+
+    // Rows of SELECT are fetched by `dest` one at a time.
+    pub fn execute_query_with(&mut self, query: &str, dest: &mut Dest) -> Result<QueryResult, Error> {
         let parser = parser::StatementParser::new();
-        let stmt = parser.parse(query).expect("Invalid syntax");
-        
+        let stmt = parser.parse(query).map_err(|e| Error::SyntaxError(format!("Invalid syntax: {}", e)))?;
+
+        self.portal_run(stmt, dest)
+    }
+
+    // `pg_parse_query` in pg.
+    //
+    // Parse `;` separated statements, e.g. contents of a SQL file. Callers
+    // run them by `portal_run` in order, so nothing is executed if the
+    // script has a syntax error.
+    pub fn parse_script(script: &str) -> Result<Vec<Stmt>, Error> {
+        let parser = parser::StatementListParser::new();
+        parser.parse(script).map_err(|e| Error::SyntaxError(format!("Invalid syntax: {}", e)))
+    }
+
+    // Execute `;` separated statements in order and returns the result of
+    // each statement. Rows of SELECT are discarded.
+    //
+    // If `on_error_stop` is true, statements after the failed one are not
+    // executed (`ON_ERROR_STOP` in psql).
+    pub fn execute_script(&mut self, script: &str, on_error_stop: bool) -> Vec<Result<QueryResult, Error>> {
+        let stmts = match Executor::parse_script(script) {
+            Ok(stmts) => stmts,
+            Err(e) => return vec![Err(e)],
+        };
+        let mut results = Vec::new();
+
+        for stmt in stmts.into_iter() {
+            let result = self.portal_run(stmt, &mut |portal| portal.discard());
+            let failed = result.is_err();
+            results.push(result);
+
+            if failed && on_error_stop {
+                break;
+            }
+        }
+
+        results
+    }
+
+    // `PortalRun` in pg.
+    //
+    // Run the statement in the current transaction block, or in its own
+    // transaction. Rows of SELECT are fetched by `dest` before the
+    // transaction is committed.
+    pub fn portal_run(&mut self, stmt: Stmt, dest: &mut Dest) -> Result<QueryResult, Error> {
+        if let Stmt::TransactionStmt(kind) = stmt {
+            let tag = match kind {
+                TransactionStmtKind::Begin => {
+                    self.xact.begin_transaction_block()?;
+                    "BEGIN"
+                },
+                TransactionStmtKind::Commit => {
+                    self.xact.end_transaction_block(&self.bm)?;
+                    "COMMIT"
+                },
+                TransactionStmtKind::Rollback => {
+                    self.xact.user_abort_transaction_block()?;
+                    "ROLLBACK"
+                },
+            };
+
+            return Ok(QueryResult::command(tag, 0));
+        }
+
+        self.xact.start_transaction_command()?;
+
+        let changes_catalog = match stmt {
+            Stmt::CreateTableStmt(..) | Stmt::CreateIndexStmt(..) | Stmt::CreateDatabaseStmt(..) |
+            Stmt::DropDatabaseStmt(..) | Stmt::DropTableStmt(..) => true,
+            _ => false,
+        };
+        let result = self.execute_stmt(stmt, dest);
+        self.bm.write().unwrap().release_all_buffers();
+
+        // `AcceptInvalidationMessages` in pg. DDL may change the catalog
+        // files even if it fails.
+        if changes_catalog {
+            self.cmgr = CatalogManager::new(self.config.clone());
+        }
+
+        match result {
+            Ok(result) => {
+                self.xact.commit_transaction_command(&self.bm)?;
+                Ok(result)
+            },
+            Err(e) => {
+                self.xact.abort_current_transaction()?;
+                Err(e)
+            }
+        }
+    }
+
+    fn execute_stmt(&mut self, stmt: Stmt, dest: &mut Dest) -> Result<QueryResult, Error> {
         match stmt {
-            Stmt::SelectStmt(expr, dbname, tablename, where_clause, sort_clause) => {
-                match *expr {
-                    Expr::All => {
-                        let select_from = SelectFromCommand::new(self.config.clone());
-                        select_from.execute(&dbname, &tablename, self.cmgr, &where_clause, &sort_clause)
-                    },
-                    Expr::Count => {
+            Stmt::SelectStmt(target_list, dbname, tablename, where_clause, sort_clause) => {
+                match target_list.as_slice() {
+                    [ResTarget { name, val }] if matches!(**val, Expr::Count) => {
+                        let col_name = name.clone().unwrap_or("count".to_string());
                         let count = CountCommand::new(self.config.clone());
-                        count.execute(&dbname, &tablename, self.cmgr, &where_clause)
-                    },
-                    Expr::Bool(_) => {
-                        panic!("Unknown expr ({:?})", expr);
+                        count.execute(&dbname, &tablename, &self.cmgr, &col_name, &where_clause, &self.xact.get_transaction_snapshot()?, &self.bm, dest)
                     },
-                    Expr::Number(_) => {
-                        panic!("Unknown expr ({:?})", expr);
-                    },
-                    Expr::OpEq(..) => {
-                        panic!("Unknown expr ({:?})", expr);
-                    },
-                    Expr::ColumnRef(..) => {
-                        panic!("Unknown expr ({:?})", expr);
+                    _ => {
+                        let select_from = SelectFromCommand::new(self.config.clone());
+                        select_from.execute(&dbname, &tablename, &self.cmgr, &target_list, &where_clause, &sort_clause, &self.xact.get_transaction_snapshot()?, &self.bm, dest)
                     },
                 }
             },
             Stmt::InsertStmt(dbname, tablename, keys, value_lists) => {
+                let xid = self.xact.get_current_transaction_id()?;
+                let mut processed = 0;
+
                 // TODO: Implement nodeValuesscan and change InsertIntoCommand
                 //       to fetch all records.
                 for values in value_lists.iter() {
                     let mut builder = KeyValueBuilder::new();
 
+                    if keys.len() != values.len() {
+                        return Err(Error::SyntaxError(format!("Length not match. keys: {}, values: {}", keys.len(), values.len())));
+                    }
+
                     for (k, v) in keys.iter().zip(values.iter()) {
-                        builder.add_pair(k, v)
+                        builder.add_pair(k, v.as_ref().map(|s| s.as_str()))
                     }
 
                     let insert_into = InsertIntoCommand::new(self.config.clone());
-                    insert_into.execute(&dbname, &tablename, builder.build(), self.cmgr)?;
+                    processed += insert_into.execute(&dbname, &tablename, builder.build(), &self.cmgr, xid, &self.bm)?.processed;
                 }
 
-                Ok(())
+                Ok(QueryResult::command("INSERT", processed))
             },
             Stmt::DeleteStmt(dbname, tablename, where_clause) => {
+                self.xact.get_current_transaction_id()?;
                 let delete = DeleteCommand::new(self.config.clone());
-                delete.execute(&dbname, &tablename, self.cmgr, &where_clause)
+                delete.execute(&dbname, &tablename, &self.cmgr, &where_clause, &self.xact.get_transaction_snapshot()?, &self.bm)
+            },
+            Stmt::UpdateStmt(dbname, tablename, target_list, where_clause) => {
+                self.xact.get_current_transaction_id()?;
+                let update = UpdateCommand::new(self.config.clone());
+                update.execute(&dbname, &tablename, &self.cmgr, &target_list, &where_clause, &self.xact.get_transaction_snapshot()?, &self.bm)
+            },
+            Stmt::CreateTableStmt(dbname, tablename, columns) => {
+                self.xact.prevent_in_transaction_block("CREATE TABLE")?;
+                let oid_manager = RwLock::new(OidManager::new(self.config.clone()));
+                let create_table = CreateTableCommand::new(self.config.clone(), oid_manager);
+                create_table.execute(&dbname, &tablename, &columns)?;
+                Ok(QueryResult::command("CREATE TABLE", 0))
+            },
+            Stmt::CreateIndexStmt(dbname, tablename, indexname, column) => {
+                self.xact.prevent_in_transaction_block("CREATE INDEX")?;
+                let oid_manager = RwLock::new(OidManager::new(self.config.clone()));
+                let create_index = CreateIndexCommand::new(self.config.clone(), oid_manager);
+                create_index.execute(&dbname, &tablename, &indexname, &column, &self.xact.get_transaction_snapshot()?, &self.bm)?;
+                Ok(QueryResult::command("CREATE INDEX", 0))
+            },
+            Stmt::CreateDatabaseStmt(dbname) => {
+                self.xact.prevent_in_transaction_block("CREATE DATABASE")?;
+                let oid_manager = RwLock::new(OidManager::new(self.config.clone()));
+                let create_db = CreateDatabaseCommand::new(self.config.clone(), oid_manager);
+                create_db.execute(&dbname)?;
+                Ok(QueryResult::command("CREATE DATABASE", 0))
+            },
+            Stmt::DropDatabaseStmt(dbname, missing_ok) => {
+                self.xact.prevent_in_transaction_block("DROP DATABASE")?;
+                let drop_db = DropDatabaseCommand::new(self.config.clone());
+                drop_db.execute(&dbname, missing_ok, &self.bm)?;
+                Ok(QueryResult::command("DROP DATABASE", 0))
+            },
+            Stmt::DropTableStmt(dbname, tablename, missing_ok) => {
+                self.xact.prevent_in_transaction_block("DROP TABLE")?;
+                let drop_table = DropTableCommand::new(self.config.clone());
+                drop_table.execute(&dbname, &tablename, missing_ok, &self.bm)?;
+                Ok(QueryResult::command("DROP TABLE", 0))
+            },
+            Stmt::VacuumStmt(dbname, tablename) => {
+                self.xact.prevent_in_transaction_block("VACUUM")?;
+                let vacuum = VacuumCommand::new();
+                vacuum.execute(&dbname, &tablename, &self.cmgr, &self.xact.get_transaction_snapshot()?, &self.bm)
+            },
+            Stmt::TransactionStmt(_) => {
+                unreachable!("Transaction statements are handled by portal_run");
             },
         }
     }
@@ -78,12 +261,48 @@ impl<'a> Executor<'a> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::fs;
+    use std::mem;
+    use tempfile::tempdir;
+    use init::InitCommand;
+    use catalog::mini_attribute::TypeLabel;
+    use executor::tuptable::Row;
+
     lalrpop_mod!(pub parser);
 
+    // Initialize a data directory and start a session with database "db".
+    fn setup() -> (tempfile::TempDir, Rc<Config>, Executor) {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        InitCommand::new(config.clone()).execute().unwrap();
+
+        let mut executor = Executor::new(config.clone()).unwrap();
+        executor.execute_query("create database db").unwrap();
+        (dir, config, executor)
+    }
+
+    // Run SELECT and fetch all rows.
+    fn select(executor: &mut Executor, query: &str) -> (QueryResult, Vec<Row>) {
+        let mut rows = Vec::new();
+        let result = executor.execute_query_with(query, &mut |portal| {
+            while let Some(row) = portal.fetch()? {
+                rows.push(row);
+            }
+            Ok(())
+        }).unwrap();
+        (result, rows)
+    }
+
     #[test]
     fn select_stmt() {
         assert!(parser::StatementParser::new().parse("select * from db.tbl").is_ok());
         assert!(parser::StatementParser::new().parse("select count() from db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("select id, name from db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("SELECT id AS key, 1 as one, 'a', *, null FROM db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("select count() as n from db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("select id, from db.tbl").is_err());
+        assert!(parser::StatementParser::new().parse("select id as from db.tbl").is_err());
     }
 
     #[test]
@@ -91,6 +310,36 @@ mod tests {
         assert!(parser::StatementParser::new().parse("select * from db.tbl where true").is_ok());
         assert!(parser::StatementParser::new().parse("select * from db.tbl where false").is_ok());
         assert!(parser::StatementParser::new().parse("select * from db.tbl where false = true").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where name = 'foo'").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where name is null").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where name IS NOT NULL").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where name = null").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where age > 30 AND id <> 5").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where id < 1 or id >= 3 and not (id <= 5 or id != 2)").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where id between 1 and 3 and id not between 2 and 4").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where id IN (1, 2) OR name NOT IN ('a')").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where id < 1 < 2").is_err());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where id in ()").is_err());
+    }
+
+    #[test]
+    fn select_stmt_with_expression() {
+        assert!(parser::StatementParser::new().parse("select id + 1, -age * (2 - id) / 3 % 4 as x from db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("select abs(-1), coalesce(name, 'a', 'b'), now() from db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl where length(name) > id * 2 order by abs(id - 5)").is_ok());
+        assert!(parser::StatementParser::new().parse("update db.tbl set age = age + 1, name = upper(name)").is_ok());
+        assert!(parser::StatementParser::new().parse("select 2147483647 from db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("select 2147483648 from db.tbl").is_err());
+        assert!(parser::StatementParser::new().parse("select id + from db.tbl").is_err());
+    }
+
+    #[test]
+    fn select_stmt_with_order_by() {
+        assert!(parser::StatementParser::new().parse("select * from db.tbl order by id").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl order by age desc, id asc, name").is_ok());
+        assert!(parser::StatementParser::new().parse("SELECT * FROM db.tbl ORDER BY age DESC NULLS LAST, id NULLS FIRST").is_ok());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl order by id nulls").is_err());
+        assert!(parser::StatementParser::new().parse("select * from db.tbl order by id,").is_err());
     }
 
     #[test]
@@ -98,6 +347,8 @@ mod tests {
         assert!(parser::StatementParser::new().parse("insert into db.tbl (id, age) values (4, 20)").is_ok());
         assert!(parser::StatementParser::new().parse(r#"insert into db.tbl (id, age) values ('a', 'b')"#).is_ok());
         assert!(parser::StatementParser::new().parse(r#"insert into db.tbl (id, age) values ("a", "b")"#).is_ok());
+        assert!(parser::StatementParser::new().parse(r#"insert into db.tbl (id, name) values (1, 'hello world!')"#).is_ok());
+        assert!(parser::StatementParser::new().parse("insert into db.tbl (id, age) values (1, null), (NULL, 2)").is_ok());
         assert!(parser::StatementParser::new().parse("insert into db.tbl (id, age) values (1, 10), (4, 20)").is_ok());
     }
 
@@ -105,4 +356,360 @@ mod tests {
     fn delete_stmt() {
         assert!(parser::StatementParser::new().parse("delete from db.tbl").is_ok());
     }
+
+    #[test]
+    fn update_stmt() {
+        assert!(parser::StatementParser::new().parse("update db.tbl set age = 10").is_ok());
+        assert!(parser::StatementParser::new().parse("UPDATE db.tbl SET id = age, age = 10 WHERE id = 1").is_ok());
+        assert!(parser::StatementParser::new().parse("update db.tbl set").is_err());
+    }
+
+    #[test]
+    fn create_table_stmt() {
+        assert!(parser::StatementParser::new().parse("create table db.tbl (id integer)").is_ok());
+        assert!(parser::StatementParser::new().parse("CREATE TABLE db.tbl (id int, age INTEGER)").is_ok());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (id int, name text, code varchar(10))").is_ok());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (code varchar)").is_err());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (code varchar(0))").is_err());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (code varchar(10485760))").is_ok());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (code varchar(10485761))").is_err());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (code varchar(99999999999999999999))").is_err());
+        assert!(parser::StatementParser::new().parse("create table db.tbl ()").is_err());
+        assert!(parser::StatementParser::new().parse("create table db.tbl (id)").is_err());
+    }
+
+    #[test]
+    fn create_index_stmt() {
+        assert!(parser::StatementParser::new().parse("create index idx on db.tbl (id)").is_ok());
+        assert!(parser::StatementParser::new().parse("CREATE INDEX idx ON db.tbl (id)").is_ok());
+        assert!(parser::StatementParser::new().parse("create index on db.tbl (id)").is_err());
+        assert!(parser::StatementParser::new().parse("create index idx on db.tbl ()").is_err());
+    }
+
+    #[test]
+    fn transaction_stmt() {
+        assert!(parser::StatementParser::new().parse("begin").is_ok());
+        assert!(parser::StatementParser::new().parse("BEGIN").is_ok());
+        assert!(parser::StatementParser::new().parse("commit").is_ok());
+        assert!(parser::StatementParser::new().parse("COMMIT").is_ok());
+        assert!(parser::StatementParser::new().parse("rollback").is_ok());
+        assert!(parser::StatementParser::new().parse("ROLLBACK").is_ok());
+    }
+
+    #[test]
+    fn vacuum_stmt() {
+        assert!(parser::StatementParser::new().parse("vacuum db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("VACUUM db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("vacuum tbl").is_err());
+    }
+
+    #[test]
+    fn create_database_stmt() {
+        assert!(parser::StatementParser::new().parse("create database db").is_ok());
+        assert!(parser::StatementParser::new().parse("CREATE DATABASE db").is_ok());
+    }
+
+    #[test]
+    fn drop_stmt() {
+        assert!(parser::StatementParser::new().parse("drop database db").is_ok());
+        assert!(parser::StatementParser::new().parse("DROP DATABASE IF EXISTS db").is_ok());
+        assert!(parser::StatementParser::new().parse("drop table db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("drop table if exists db.tbl").is_ok());
+        assert!(parser::StatementParser::new().parse("drop table tbl").is_err());
+    }
+
+    #[test]
+    fn statement_list() {
+        let stmts = parser::StatementListParser::new().parse("
+            -- comment; 'not a string
+            create database db;
+            /* multi-line
+               comment; */
+            create table db.tbl (id int); ;
+            insert into db.tbl (id) values (1) -- last semicolon is omitted
+        ").unwrap();
+        assert_eq!(stmts.len(), 3);
+
+        assert_eq!(parser::StatementListParser::new().parse("").unwrap().len(), 0);
+        assert!(parser::StatementListParser::new().parse("begin commit").is_err());
+    }
+
+    #[test]
+    fn execute_query_error_code() {
+        let (_dir, _config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int, name varchar(3))").unwrap();
+        executor.execute_query("create table db.big (id int, name text)").unwrap();
+
+        let mut code = |query| executor.execute_query(query).err().unwrap().code();
+
+        assert_eq!(code("select * form db.tbl"), "42601");
+        assert_eq!(code("select * from nodb.tbl"), "3D000");
+        assert_eq!(code("select * from db.notbl"), "42P01");
+        assert_eq!(code("select * from db.tbl where age = 1"), "42703");
+        assert_eq!(code("select * from db.tbl order by age"), "42703");
+        assert_eq!(code("select age from db.tbl"), "42703");
+        assert_eq!(code("select id, count() from db.tbl"), "0A000");
+        assert_eq!(code("insert into db.tbl (id) values ('a')"), "22P02");
+        assert_eq!(code("insert into db.tbl (name) values ('abcd')"), "22001");
+        let query = format!("insert into db.big (id, name) values (3, '{}')", "x".repeat(9000));
+        assert_eq!(code(&query), "54000");
+        assert_eq!(code("create table db.tbl (id int)"), "42P07");
+        assert_eq!(code("create database db"), "42P04");
+    }
+
+    #[test]
+    fn execute_query_io_error() {
+        let (_dir, config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int)").unwrap();
+
+        // WAL segment can not be opened for write, like a full disk.
+        let segment = config.wal_segment_file_path(0);
+        if segment.exists() {
+            fs::remove_file(&segment).unwrap();
+        }
+        fs::create_dir(&segment).unwrap();
+
+        let e = executor.execute_query("insert into db.tbl (id) values (1)").err().unwrap();
+        assert_eq!(e.code(), "58030");
+        assert_eq!(select(&mut executor, "select * from db.tbl").1.len(), 0);
+    }
+
+    #[test]
+    fn execute_query_commit_error() {
+        let (_dir, config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int)").unwrap();
+        executor.execute_query("begin").unwrap();
+        executor.execute_query("insert into db.tbl (id) values (1)").unwrap();
+
+        // The commit log can not be written.
+        let clog = config.clog_file_path();
+        let saved = clog.with_extension("saved");
+        fs::rename(&clog, &saved).unwrap();
+        fs::create_dir(&clog).unwrap();
+        assert_eq!(executor.execute_query("commit").err().unwrap().code(), "58030");
+        fs::remove_dir(&clog).unwrap();
+        fs::rename(&saved, &clog).unwrap();
+
+        // The next statement runs in a new transaction.
+        assert_eq!(executor.block_state(), TBlockState::Default);
+        executor.execute_query("insert into db.tbl (id) values (2)").unwrap();
+        let (_, rows) = select(&mut executor, "select * from db.tbl");
+        assert_eq!(rows.iter().map(|row| row[0].as_string()).collect::<Vec<_>>(), vec!["2"]);
+    }
+
+    #[test]
+    fn execute_query_result() {
+        let (_dir, _config, mut executor) = setup();
+        let result = executor.execute_query("create table db.tbl (id int, name varchar(10))").unwrap();
+        assert_eq!(result.tag, "CREATE TABLE");
+        let result = executor.execute_query("insert into db.tbl (id, name) values (1, 'a')").unwrap();
+        assert_eq!((result.tag.as_str(), result.processed), ("INSERT", 1));
+        executor.execute_query("insert into db.tbl (id) values (2)").unwrap();
+
+        let (result, rows) = select(&mut executor, "select * from db.tbl order by id");
+        assert_eq!(result.tag, "SELECT");
+        assert_eq!(result.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["id", "name"]);
+        assert_eq!(result.processed, 2);
+        assert_eq!(rows[0][0].as_string(), "1");
+        assert_eq!(rows[0][1].as_string(), "a");
+        assert!(rows[1][1].is_null());
+
+        // Rows which are not fetched are not counted.
+        assert_eq!(executor.execute_query("select * from db.tbl").unwrap().processed, 2);
+        let result = executor.execute_query_with("select * from db.tbl", &mut |portal| portal.fetch().map(|_| ())).unwrap();
+        assert_eq!(result.processed, 1);
+
+        let (result, rows) = select(&mut executor, "select count() from db.tbl");
+        assert_eq!(result.columns[0].name, "count");
+        assert_eq!(rows[0][0].as_string(), "2");
+
+        let (result, rows) = select(&mut executor, "select name, id as key, 3, 'x' as x, null, * from db.tbl order by id");
+        assert_eq!(result.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["name", "key", "?column?", "x", "?column?", "id", "name"]);
+        assert_eq!(result.columns.iter().map(|c| c.ty.clone()).collect::<Vec<_>>(),
+                   vec![TypeLabel::Varchar, TypeLabel::Integer, TypeLabel::Integer, TypeLabel::Text, TypeLabel::Text, TypeLabel::Integer, TypeLabel::Varchar]);
+        assert_eq!(rows[0].iter().map(|v| v.as_string()).collect::<Vec<_>>(), vec!["a", "1", "3", "x", "NULL", "1", "a"]);
+        assert!(rows[1][0].is_null());
+
+        let result = executor.execute_query("select count() as n from db.tbl").unwrap();
+        assert_eq!(result.columns[0].name, "n");
+
+        let result = executor.execute_query("update db.tbl set name = 'b' where id = 2").unwrap();
+        assert_eq!((result.tag.as_str(), result.processed), ("UPDATE", 1));
+
+        let result = executor.execute_query("delete from db.tbl").unwrap();
+        assert_eq!((result.tag.as_str(), result.processed), ("DELETE", 2));
+        assert!(result.columns.is_empty());
+    }
+
+    #[test]
+    fn execute_query_where() {
+        let (_dir, _config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int, age int, name text)").unwrap();
+        executor.execute_query("insert into db.tbl (id, age, name) values (1, 9, 'a'), (5, 40, 'b'), (9, 35, 'c'), (10, null, 'd')").unwrap();
+
+        let mut ids = |query| {
+            select(&mut executor, query).1.iter().map(|row| row[0].as_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids("select id from db.tbl where age > 30 AND id <> 5"), vec!["9"]);
+        assert_eq!(ids("select id from db.tbl where id >= 9"), vec!["9", "10"]);
+        assert_eq!(ids("select id from db.tbl where age < 10 or name <= 'b'"), vec!["1", "5"]);
+        assert_eq!(ids("select id from db.tbl where not (age > 10)"), vec!["1"]);
+        assert_eq!(ids("select id from db.tbl where age > 10 or id = 10"), vec!["5", "9", "10"]);
+        assert_eq!(ids("select id from db.tbl where id between 5 and 9"), vec!["5", "9"]);
+        assert_eq!(ids("select id from db.tbl where id not between 5 and 9"), vec!["1", "10"]);
+        assert_eq!(ids("select id from db.tbl where age in (9, 40)"), vec!["1", "5"]);
+        assert_eq!(ids("select id from db.tbl where age not in (9, null)"), Vec::<String>::new());
+        assert_eq!(ids("select id from db.tbl where id = '9'"), vec!["9"]);
+
+        let mut code = |query| executor.execute_query(query).err().unwrap().code();
+        assert_eq!(code("select id from db.tbl where id"), "42804");
+        assert_eq!(code("select id from db.tbl where id = true"), "42883");
+        assert_eq!(code("select id from db.tbl where id = 'a'"), "22P02");
+
+        let result = executor.execute_query("delete from db.tbl where age >= 35 and age <= 40").unwrap();
+        assert_eq!(result.processed, 2);
+        assert_eq!(select(&mut executor, "select count() from db.tbl").1[0][0].as_string(), "2");
+    }
+
+    #[test]
+    fn execute_query_expression() {
+        let (_dir, _config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int, age int, name text)").unwrap();
+        executor.execute_query("insert into db.tbl (id, age, name) values (1, 9, 'Ab'), (10, 40, 'bcd'), (9, null, null)").unwrap();
+
+        let (result, rows) = select(&mut executor, "select id * 2 + 1, -age % 7 as m, upper(name), coalesce(age, id), substr(name, 2) from db.tbl order by id");
+        assert_eq!(result.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["?column?", "m", "upper", "coalesce", "substr"]);
+        assert_eq!(result.columns.iter().map(|c| c.ty.clone()).collect::<Vec<_>>(),
+                   vec![TypeLabel::Integer, TypeLabel::Integer, TypeLabel::Text, TypeLabel::Integer, TypeLabel::Text]);
+        let rows = rows.iter().map(|row| row.iter().map(|v| v.as_string()).collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(rows, vec![
+            vec!["3", "-2", "AB", "9", "b"],
+            vec!["19", "NULL", "NULL", "9", "NULL"],
+            vec!["21", "-5", "BCD", "40", "cd"],
+        ]);
+
+        let (_, rows) = select(&mut executor, "select id from db.tbl where length(name) + 1 >= id - 7 order by -id");
+        assert_eq!(rows.iter().map(|row| row[0].as_string()).collect::<Vec<_>>(), vec!["10", "1"]);
+
+        let result = executor.execute_query("update db.tbl set age = coalesce(age, 0) + id, name = lower(name) where id <> 1").unwrap();
+        assert_eq!(result.processed, 2);
+        let (_, rows) = select(&mut executor, "select age, name from db.tbl where id = 10");
+        assert_eq!((rows[0][0].as_string(), rows[0][1].as_string()), ("50".to_string(), "bcd".to_string()));
+
+        let mut code = |query| executor.execute_query(query).err().unwrap().code();
+        assert_eq!(code("select id / 0 from db.tbl"), "22012");
+        assert_eq!(code("select id % (age - age) from db.tbl where id = 1"), "22012");
+        assert_eq!(code("select 2147483647 + id from db.tbl"), "22003");
+        assert_eq!(code("update db.tbl set age = -2147483647 - age"), "22003");
+        assert_eq!(code("select nofunc(id) from db.tbl"), "42883");
+        assert_eq!(code("select * from db.tbl where abs(id, 1) = 1"), "42883");
+        assert_eq!(code("select * from db.tbl order by nocolumn + 1"), "42703");
+    }
+
+    #[test]
+    fn execute_query_order_by() {
+        let (_dir, _config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int, age int, name text)").unwrap();
+        executor.execute_query("insert into db.tbl (id, age, name) values (1, 20, 'b'), (2, null, 'a'), (3, 10, 'a'), (4, 20, 'a'), (10, 10, null)").unwrap();
+
+        let mut ids = |query| {
+            select(&mut executor, query).1.iter().map(|row| row[0].as_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids("select id from db.tbl order by id desc"), vec!["10", "4", "3", "2", "1"]);
+        assert_eq!(ids("select id from db.tbl order by age, id desc"), vec!["10", "3", "4", "1", "2"]);
+        assert_eq!(ids("select id from db.tbl order by age desc, name, id"), vec!["2", "4", "1", "3", "10"]);
+        assert_eq!(ids("select id from db.tbl order by age desc nulls last, name desc nulls first"), vec!["1", "4", "10", "3", "2"]);
+        assert_eq!(ids("select id from db.tbl order by age nulls first, id % 3 asc, id"), vec!["2", "3", "10", "1", "4"]);
+    }
+
+    #[test]
+    fn execute_query_index_scan() {
+        let (_dir, _config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int, age int, name text)").unwrap();
+
+        for i in 0..50 {
+            executor.execute_query(&format!("insert into db.tbl (id, age, name) values ({}, {}, 'n{}')", i, i % 45, i)).unwrap();
+        }
+
+        let queries = vec![
+            "select id from db.tbl where age > 30 AND id <> 35 order by id",
+            "select id from db.tbl where 40 <= age and age < 43 order by id",
+            "select id from db.tbl where age between 3 and 5 and id > 10 order by id",
+            "select id from db.tbl where age = '7' order by id",
+            "select id from db.tbl where age > 40 or id = 1 order by id",
+        ];
+        let run = |executor: &mut Executor| {
+            queries.iter().map(|query| {
+                select(executor, query).1.iter().map(|row| row[0].as_string()).collect::<Vec<_>>()
+            }).collect::<Vec<_>>()
+        };
+
+        let expected = run(&mut executor);
+        assert_eq!(expected[0], vec!["31", "32", "33", "34", "36", "37", "38", "39", "40", "41", "42", "43", "44"]);
+        assert_eq!(expected[2], vec!["48", "49"]);
+
+        executor.execute_query("create index idx on db.tbl (age)").unwrap();
+        assert_eq!(run(&mut executor), expected);
+
+        // The result does not depend on whether the index exists.
+        let query = "select id from db.tbl where name = 9";
+        assert_eq!(executor.execute_query(query).err().unwrap().code(), "22P02");
+        executor.execute_query("create index nameidx on db.tbl (name)").unwrap();
+        assert_eq!(executor.execute_query(query).err().unwrap().code(), "22P02");
+    }
+
+    #[test]
+    fn execute_query_vacuum_after_crash() {
+        let (_dir, config, mut executor) = setup();
+        executor.execute_query("create table db.tbl (id int)").unwrap();
+        executor.execute_query("insert into db.tbl (id) values (1)").unwrap();
+        executor.execute_query("insert into db.tbl (id) values (2)").unwrap();
+
+        // Crash in the middle of a transaction.
+        executor.execute_query("begin").unwrap();
+        executor.execute_query("insert into db.tbl (id) values (3)").unwrap();
+        // Another session can not start and abort the transaction.
+        assert_eq!(Executor::new(config.clone()).err().unwrap().code(), "58030");
+        let Executor { bm, xact, _lock_file, .. } = executor;
+        mem::forget((bm, xact));
+        // The kernel releases the lock of the crashed process.
+        drop(_lock_file);
+
+        // The transaction left in progress does not hold back VACUUM.
+        let mut executor = Executor::new(config.clone()).unwrap();
+        executor.execute_query("delete from db.tbl where id = 1").unwrap();
+        assert_eq!(executor.execute_query("vacuum db.tbl").unwrap().processed, 2);
+        let (_, rows) = select(&mut executor, "select * from db.tbl");
+        assert_eq!(rows.iter().map(|row| row[0].as_string()).collect::<Vec<_>>(), vec!["2"]);
+    }
+
+    #[test]
+    fn execute_script() {
+        let (_dir, _config, mut executor) = setup();
+        let script = "
+            create table db.tbl (id int);
+            insert into db.tbl (id) values ('a');
+            insert into db.tbl (id) values (1);
+        ";
+        let results = executor.execute_script(script, true);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().err().unwrap().code(), "22P02");
+        assert_eq!(select(&mut executor, "select count() from db.tbl").1[0][0].as_string(), "0");
+
+        let script = "
+            insert into db.tbl (id) values ('a');
+            insert into db.tbl (id) values (1);
+        ";
+        let results = executor.execute_script(script, false);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().ok().unwrap().processed, 1);
+
+        let results = executor.execute_script("insert into db.tbl (id) values (2); select", false);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().err().unwrap().code(), "42601");
+        assert_eq!(select(&mut executor, "select count() from db.tbl").1[0][0].as_string(), "1");
+    }
 }
@@ -1,14 +1,14 @@
 use std::rc::Rc;
 use std::collections::HashMap;
-use std::io::{Seek, SeekFrom};
+use std::fmt;
+use std::io::{self, ErrorKind, Seek, SeekFrom};
 use std::fs::{File, OpenOptions};
-use std::os::unix::io::{AsRawFd};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
 use std::cell::RefCell;
 
-use errno::{Errno, errno, set_errno};
-
 use buffer_manager::{RelFileNode, BlockNumber, InvalidBlockNumber};
-use config::{Config, DEFAULT_BLOCK_SIZE};
+use config::{Config, DEFAULT_BLOCK_SIZE, RELSEG_SIZE};
 use oid_manager::{Oid, DUMMY_OID};
 
 // `HEAP_DEFAULT_FILLFACTOR` in pg.
@@ -24,7 +24,8 @@ pub struct RelationData {
 pub struct SMgrRelationData {
     config: Rc<Config>,
     pub smgr_rnode: RelFileNode,
-    file: Option<File>,
+    // opened segment files, index is segment number (`md_seg_fds` in pg)
+    files: Vec<File>,
     // current insertion target block
     pub smgr_targblock: BlockNumber,
 }
@@ -52,144 +53,197 @@ impl RelationManager {
         let cache = &mut self.cache;
 
         cache.entry(table_oid).or_insert_with(|| {
-            let rd_node = RelFileNode {
-                table_oid: table_oid,
-                db_oid: DUMMY_OID, // TODO
-            };
-            let smgr_rnode = RelFileNode {
-                table_oid: table_oid,
-                db_oid: db_oid,
-            };
-
-            RefCell::new(
-                RelationData {
-                    rd_node: rd_node,
-                    smgr_rnode: smgr_rnode,
-                }
-            )
+            RefCell::new(RelationData::new(db_oid, table_oid))
         })
     }
 }
 
-impl SMgrRelationData {
-    pub fn mdread(&mut self, block_num: BlockNumber, buffer: *mut libc::c_void) {
-        let s = DEFAULT_BLOCK_SIZE as u32;
-        self.mdopen();
+impl RelationData {
+    // Build relation descriptor without RelationManager cache.
+    // This is used when a command opens multiple relations at once,
+    // e.g. a table and its indexes.
+    pub fn new(db_oid: Oid, table_oid: Oid) -> RelationData {
+        let rd_node = RelFileNode {
+            table_oid: table_oid,
+            db_oid: DUMMY_OID, // TODO
+        };
+        let smgr_rnode = RelFileNode {
+            table_oid: table_oid,
+            db_oid: db_oid,
+        };
+
+        RelationData {
+            rd_node: rd_node,
+            smgr_rnode: smgr_rnode,
+        }
+    }
+}
 
-        let mut f = self.file.as_ref().unwrap();
+// Errors of storage managers and buffer manager, which are reported
+// as errors of the statement (`ereport(ERROR, ...)` in md.c and bufmgr.c).
+#[derive(Debug)]
+pub enum StorageError {
+    // description of the failed file operation and its cause
+    Io(String, io::Error),
+    // all buffers are pinned, the number of buffers
+    NoUnpinnedBuffers(usize),
+}
 
-        let seekpos = s * block_num;
-        if f.seek(SeekFrom::Start(seekpos.into())).unwrap() != seekpos.into() {
-            panic!("Failed to seek file. '{}'", seekpos);
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Io(op, e) => write!(f, "could not {}: {}", op, e),
+            StorageError::NoUnpinnedBuffers(n) => write!(f, "no unpinned buffers available (N_BUFFERS: {})", n),
         }
+    }
+}
 
-        let fd = f.as_raw_fd();
+// Relation files are divided into segments of RELSEG_SIZE blocks, whose
+// names are "data", "data.1", "data.2" and so on. All segments except
+// the last one have exactly RELSEG_SIZE blocks.
+impl SMgrRelationData {
+    pub fn mdread(&mut self, block_num: BlockNumber, buffer: *mut libc::c_void) -> Result<(), StorageError> {
+        let s = DEFAULT_BLOCK_SIZE as usize;
 
-        unsafe {
-            set_errno(Errno(0));
+        // Reading a block beyond the last segment reads nothing.
+        let fd = match self.mdfd_getseg(block_num, false)? {
+            Some(f) => f.as_raw_fd(),
+            None => return Ok(()),
+        };
 
-            let rbyte = libc::read(fd, buffer, s as usize);
+        let rbyte = unsafe { libc::read(fd, buffer, s) };
 
-            if rbyte == -1 {
-                panic!("Failed to read file. '{}'", errno());
-            }
+        if rbyte == -1 {
+            return Err(self.block_error("read", block_num, io::Error::last_os_error()));
+        }
 
-            if (rbyte != 0) && (rbyte != s as isize) {
-                panic!(
-                    "Failed to read file. Expect to read {} bytes but read only {} bytes",
-                    s, rbyte
-                );
-            }
+        if (rbyte != 0) && (rbyte != s as isize) {
+            let e = io::Error::new(ErrorKind::UnexpectedEof, format!("read only {} of {} bytes", rbyte, s));
+            return Err(self.block_error("read", block_num, e));
         }
+
+        Ok(())
     }
 
-    pub fn mdwrite(&mut self, block_num: BlockNumber, buffer: *const libc::c_void) {
-        let s = DEFAULT_BLOCK_SIZE as u32;
-        self.mdopen();
+    pub fn mdwrite(&mut self, block_num: BlockNumber, buffer: *const libc::c_void) -> Result<(), StorageError> {
+        let fd = self.mdfd_getseg(block_num, true)?.unwrap().as_raw_fd();
+        write_block(fd, buffer).map_err(|e| self.block_error("write", block_num, e))
+    }
 
-        let mut f = self.file.as_ref().unwrap();
+    pub fn mdextend(&mut self, block_num: BlockNumber, buffer: *const libc::c_void) -> Result<(), StorageError> {
+        // The first block of a new segment creates the segment file.
+        let fd = self.mdfd_getseg(block_num, true)?.unwrap().as_raw_fd();
+        write_block(fd, buffer).map_err(|e| self.block_error("extend", block_num, e))
+    }
 
-        let seekpos = s * block_num;
-        if f.seek(SeekFrom::Start(seekpos.into())).unwrap() != seekpos.into() {
-            panic!("Failed to seek file. '{}'", seekpos);
+    // `mdimmedsync` in pg.
+    pub fn mdimmedsync(&mut self) -> Result<(), StorageError> {
+        for (segno, f) in self.files.iter().enumerate() {
+            f.sync_all().map_err(|e| {
+                StorageError::Io(format!("fsync file {:?}", self.segment_file_path(segno as BlockNumber)), e)
+            })?;
         }
 
-        let fd = f.as_raw_fd();
-
-        unsafe {
-            set_errno(Errno(0));
+        Ok(())
+    }
 
-            let wbyte = libc::write(fd, buffer, s as usize);
+    // `mdnblocks` in pg.
+    //
+    // Count blocks of segments until a segment which is not full.
+    pub fn mdnblocks(&mut self) -> Result<BlockNumber, StorageError> {
+        let mut segno = 0;
+
+        loop {
+            let path = self.segment_file_path(segno);
+            let nblocks = match self.mdopenseg(segno, segno == 0)? {
+                Some(mut f) => {
+                    let len = f.seek(SeekFrom::End(0)).map_err(|e| StorageError::Io(format!("seek to end of file {:?}", path), e))?;
+                    (len / DEFAULT_BLOCK_SIZE as u64) as BlockNumber
+                },
+                None => 0,
+            };
 
-            if wbyte == -1 {
-                panic!("Failed to write file. '{}'", errno());
+            if nblocks > RELSEG_SIZE {
+                let e = io::Error::new(ErrorKind::InvalidData, format!("segment too big ({} blocks)", nblocks));
+                return Err(StorageError::Io(format!("count blocks of file {:?}", path), e));
             }
 
-            if wbyte != s as isize {
-                panic!(
-                    "failed to write file. Expect to write {} bytes but write only {} bytes",
-                    s, wbyte
-                );
+            if nblocks < RELSEG_SIZE {
+                return Ok(segno * RELSEG_SIZE + nblocks);
             }
+
+            segno += 1;
         }
     }
 
-    pub fn mdextend(&mut self, block_num: BlockNumber, buffer: *const libc::c_void) {
-        let s = DEFAULT_BLOCK_SIZE as u32;
-        self.mdopen();
+    // `_mdfd_getseg` in pg.
+    //
+    // Returns the segment file which has the block, seeking to the block.
+    // If `create` is false, returns None when the segment does not exist.
+    fn mdfd_getseg(&mut self, block_num: BlockNumber, create: bool) -> Result<Option<&File>, StorageError> {
+        let segno = block_num / RELSEG_SIZE;
+        let seekpos = (block_num % RELSEG_SIZE) as u64 * DEFAULT_BLOCK_SIZE as u64;
+        let path = self.segment_file_path(segno);
 
-        let mut f = self.file.as_ref().unwrap();
+        let mut f = match self.mdopenseg(segno, create)? {
+            Some(f) => f,
+            None => return Ok(None),
+        };
 
-        // Seek to start of the new page (BlockNumber is 0-origin).
-        let seekpos = s * block_num;
-        if f.seek(SeekFrom::Start(seekpos.into())).unwrap() != seekpos.into() {
-            panic!("Failed to seek file. '{}'", seekpos);
-        }
-
-        let fd = f.as_raw_fd();
+        f.seek(SeekFrom::Start(seekpos)).map_err(|e| StorageError::Io(format!("seek to block {} in file {:?}", block_num, path), e))?;
 
-        unsafe {
-            set_errno(Errno(0));
-
-            let wbyte = libc::write(fd, buffer, s as usize);
+        Ok(Some(f))
+    }
 
-            if wbyte == -1 {
-                panic!("Failed to write file. '{}'", errno());
+    // `_mdfd_openseg` in pg.
+    //
+    // Open segments up to `segno`. Segment files are created if `create`
+    // is true.
+    // TODO: Should we initalize file explicity?
+    // In pg mdopen function create a file only if bootstrap mode.
+    fn mdopenseg(&mut self, segno: BlockNumber, create: bool) -> Result<Option<&File>, StorageError> {
+        while self.files.len() <= segno as usize {
+            let path = self.segment_file_path(self.files.len() as BlockNumber);
+
+            if !create && !path.exists() {
+                return Ok(None);
             }
 
-            if wbyte != s as isize {
-                panic!(
-                    "failed to write file. Expect to write {} bytes but write only {} bytes",
-                    s, wbyte
-                );
-            }
+            let f = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .open(&path)
+                        .map_err(|e| StorageError::Io(format!("open file {:?}", path), e))?;
+
+            self.files.push(f);
         }
+
+        Ok(self.files.get(segno as usize))
     }
 
-    pub fn mdnblocks(&mut self) -> BlockNumber {
-        self.mdopen();
-        let mut f = self.file.as_ref().unwrap();
-        let len = f.seek(SeekFrom::End(0)).unwrap();
-        (len / DEFAULT_BLOCK_SIZE as u64) as BlockNumber
+    fn segment_file_path(&self, segno: BlockNumber) -> PathBuf {
+        self.config.data_segment_file_path(self.smgr_rnode.db_oid, self.smgr_rnode.table_oid, segno)
     }
 
-    fn mdopen(&mut self) {
-        if self.file.is_some() {
-            return
-        }
+    fn block_error(&self, op: &str, block_num: BlockNumber, e: io::Error) -> StorageError {
+        StorageError::Io(format!("{} block {} in file {:?}", op, block_num, self.segment_file_path(block_num / RELSEG_SIZE)), e)
+    }
+}
 
-        let path = self.config.data_file_path(self.smgr_rnode.db_oid, self.smgr_rnode.table_oid);
-        // TODO: Should we initalize file explicity?
-        // In pg mdopen function create a file only if bootstrap mode.
-        let f = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .open(&path)
-                    .unwrap();
+fn write_block(fd: RawFd, buffer: *const libc::c_void) -> io::Result<()> {
+    let s = DEFAULT_BLOCK_SIZE as usize;
+    let wbyte = unsafe { libc::write(fd, buffer, s) };
 
-        self.file = Some(f);
+    if wbyte == -1 {
+        return Err(io::Error::last_os_error());
     }
+
+    if wbyte != s as isize {
+        return Err(io::Error::new(ErrorKind::WriteZero, format!("wrote only {} of {} bytes", wbyte, s)));
+    }
+
+    Ok(())
 }
 
 impl StorageManager {
@@ -200,6 +254,20 @@ impl StorageManager {
         }
     }
 
+    // Sync all opened files, e.g. before checkpoint.
+    pub fn smgrimmedsync_all(&mut self) -> Result<(), StorageError> {
+        for (_, rel) in self.cache.iter() {
+            rel.borrow_mut().mdimmedsync()?;
+        }
+
+        Ok(())
+    }
+
+    // `smgrclose` in pg for relations which match `f`.
+    pub fn smgrclose<F>(&mut self, f: F) where F: Fn(&RelFileNode) -> bool {
+        self.cache.retain(|rnode, _| !f(rnode));
+    }
+
     pub fn relation_smgropen(&mut self, relation: &RelationData) -> &RefCell<SMgrRelationData> {
         self.smgropen(&relation.smgr_rnode)
     }
@@ -212,10 +280,66 @@ impl StorageManager {
                 SMgrRelationData {
                     config: config.clone(),
                     smgr_rnode: rd_node.clone(),
-                    file: None,
+                    files: Vec::new(),
                     smgr_targblock: InvalidBlockNumber, // TODO: When this will be updated?
                 }
             )
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_multi_segments() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.table_dir_path(10000, 10001)).unwrap();
+        let mut smgr = StorageManager::new(config.clone());
+        let rd_smgr = smgr.relation_smgropen(&RelationData::new(10000, 10001));
+        let mut rd_smgr = rd_smgr.borrow_mut();
+        let block1 = vec![1u8; DEFAULT_BLOCK_SIZE as usize];
+        let block2 = vec![2u8; DEFAULT_BLOCK_SIZE as usize];
+        let mut buf = vec![0u8; DEFAULT_BLOCK_SIZE as usize];
+
+        assert_eq!(rd_smgr.mdnblocks().unwrap(), 0);
+
+        // The first segment becomes a sparse file.
+        rd_smgr.mdextend(RELSEG_SIZE - 1, block1.as_ptr() as *const libc::c_void).unwrap();
+        assert_eq!(rd_smgr.mdnblocks().unwrap(), RELSEG_SIZE);
+        assert!(!config.data_segment_file_path(10000, 10001, 1).exists());
+
+        rd_smgr.mdextend(RELSEG_SIZE, block2.as_ptr() as *const libc::c_void).unwrap();
+        assert_eq!(rd_smgr.mdnblocks().unwrap(), RELSEG_SIZE + 1);
+        assert_eq!(fs::metadata(config.data_segment_file_path(10000, 10001, 1)).unwrap().len(), DEFAULT_BLOCK_SIZE as u64);
+
+        rd_smgr.mdread(RELSEG_SIZE - 1, buf.as_mut_ptr() as *mut libc::c_void).unwrap();
+        assert_eq!(buf, block1);
+        rd_smgr.mdread(RELSEG_SIZE, buf.as_mut_ptr() as *mut libc::c_void).unwrap();
+        assert_eq!(buf, block2);
+
+        // Beyond the last segment
+        rd_smgr.mdread(RELSEG_SIZE * 3, buf.as_mut_ptr() as *mut libc::c_void).unwrap();
+        assert_eq!(buf, block2);
+    }
+
+    #[test]
+    fn test_io_error() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        let mut smgr = StorageManager::new(config.clone());
+        let rd_smgr = smgr.relation_smgropen(&RelationData::new(10000, 10001));
+        let block = vec![0u8; DEFAULT_BLOCK_SIZE as usize];
+
+        // The directory of the table does not exist.
+        match rd_smgr.borrow_mut().mdextend(0, block.as_ptr() as *const libc::c_void) {
+            Err(StorageError::Io(_, e)) => assert_eq!(e.kind(), ErrorKind::NotFound),
+            _ => panic!("mdextend should fail"),
+        }
+        assert!(rd_smgr.borrow_mut().mdnblocks().is_err());
+    }
+}
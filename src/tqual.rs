@@ -0,0 +1,248 @@
+// Visibility of tuples (`tqual.c` in pg).
+//
+// A snapshot is taken at the beginning of each statement. Changes by
+// transactions which are committed before the snapshot and changes by the
+// current transaction are visible. Transactions which are in progress at
+// the snapshot, or start after it, are treated as not committed even if
+// they commit while the statement is running.
+
+use std::io;
+
+use transam::{TransactionId, TransactionManager, InvalidTransactionId};
+use tuple::HeapTupleHeaderData;
+
+// `SnapshotData` in pg.
+pub struct Snapshot<'a> {
+    // Transaction id of the current transaction. InvalidTransactionId
+    // if it is not assigned, e.g. read only transaction.
+    pub xid: TransactionId,
+    // all xids < xmin are finished (committed or aborted)
+    pub xmin: TransactionId,
+    // all xids >= xmax are invisible
+    pub xmax: TransactionId,
+    // in-progress xids (xmin <= xid < xmax) at the snapshot
+    pub xip: Vec<TransactionId>,
+    transam: &'a TransactionManager,
+}
+
+impl<'a> Snapshot<'a> {
+    // Status of transactions is looked up by this.
+    pub fn transam(&self) -> &'a TransactionManager {
+        self.transam
+    }
+
+    // `TransactionIdIsCurrentTransactionId` in pg.
+    fn is_current_transaction_id(&self, xid: TransactionId) -> bool {
+        self.xid != InvalidTransactionId && self.xid == xid
+    }
+
+    // `XidInMVCCSnapshot` in pg.
+    //
+    // Returns true if the transaction is still running for the snapshot.
+    fn xid_in_mvcc_snapshot(&self, xid: TransactionId) -> bool {
+        if xid < self.xmin {
+            return false;
+        }
+
+        if xid >= self.xmax {
+            return true;
+        }
+
+        self.xip.contains(&xid)
+    }
+}
+
+// `GetSnapshotData` in pg.
+pub fn get_snapshot_data<'a>(xid: TransactionId, transam: &'a TransactionManager) -> io::Result<Snapshot<'a>> {
+    let (next_xid, xip) = transam.get_running_transactions()?;
+    // The current transaction is also in progress, but its changes are
+    // checked by `is_current_transaction_id`.
+    let xip: Vec<TransactionId> = xip.into_iter().filter(|x| *x != xid).collect();
+    let xmin = xip.iter().min().cloned().unwrap_or(next_xid);
+
+    Ok(Snapshot {
+        xid: xid,
+        xmin: xmin,
+        xmax: next_xid,
+        xip: xip,
+        transam: transam,
+    })
+}
+
+// `HeapTupleSatisfiesVisibility` (`HeapTupleSatisfiesMVCC`) in pg.
+pub fn heap_tuple_satisfies_visibility(tuple: &HeapTupleHeaderData, snapshot: &Snapshot) -> io::Result<bool> {
+    let xmin = tuple.t_xmin();
+
+    if snapshot.is_current_transaction_id(xmin) {
+        // Inserted by the current transaction. The tuple is invisible
+        // only if the current transaction deleted it.
+        return Ok(!snapshot.is_current_transaction_id(tuple.t_xmax()));
+    }
+
+    // Inserter is still running for the snapshot
+    if snapshot.xid_in_mvcc_snapshot(xmin) {
+        return Ok(false);
+    }
+
+    // Inserter aborted or crashed
+    if !snapshot.transam.transaction_id_did_commit(xmin)? {
+        return Ok(false);
+    }
+
+    let xmax = tuple.t_xmax();
+
+    if xmax == InvalidTransactionId {
+        return Ok(true);
+    }
+
+    if snapshot.is_current_transaction_id(xmax) {
+        return Ok(false);
+    }
+
+    // Deleter is still running for the snapshot
+    if snapshot.xid_in_mvcc_snapshot(xmax) {
+        return Ok(true);
+    }
+
+    // Visible if deleter aborted or crashed
+    Ok(!snapshot.transam.transaction_id_did_commit(xmax)?)
+}
+
+// `HTSV_Result` in pg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HTSVResult {
+    // tuple is dead and deletable
+    Dead,
+    // tuple is dead, but not deletable yet
+    RecentlyDead,
+    // tuple is live (committed, no deleter)
+    Live,
+    // inserting transaction is still running
+    InsertInProgress,
+    // deleting transaction is still running
+    DeleteInProgress,
+}
+
+// `HeapTupleSatisfiesVacuum` in pg.
+//
+// Determine whether the tuple is dead for all transactions. A deleted
+// tuple is still visible to snapshots which see its deleter as running,
+// so it is removable only if the deleter is older than `oldest_xmin`.
+pub fn heap_tuple_satisfies_vacuum(tuple: &HeapTupleHeaderData, oldest_xmin: TransactionId, transam: &TransactionManager) -> io::Result<HTSVResult> {
+    let xmin = tuple.t_xmin();
+
+    if !transam.transaction_id_did_commit(xmin)? {
+        if transam.transaction_id_did_abort(xmin)? {
+            return Ok(HTSVResult::Dead);
+        }
+
+        // Transactions interrupted by a crash are aborted at startup,
+        // so the inserter is still running.
+        return Ok(HTSVResult::InsertInProgress);
+    }
+
+    let xmax = tuple.t_xmax();
+
+    if xmax == InvalidTransactionId || transam.transaction_id_did_abort(xmax)? {
+        return Ok(HTSVResult::Live);
+    }
+
+    if !transam.transaction_id_did_commit(xmax)? {
+        return Ok(HTSVResult::DeleteInProgress);
+    }
+
+    if xmax >= oldest_xmin {
+        return Ok(HTSVResult::RecentlyDead);
+    }
+
+    Ok(HTSVResult::Dead)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::rc::Rc;
+    use tempfile::tempdir;
+    use config::Config;
+    use transam::create_clog_file;
+    use tuple::HeapTupleData;
+
+    fn tuple(xmin: TransactionId, xmax: TransactionId) -> HeapTupleData {
+        let mut tuple = HeapTupleData::new(0);
+        tuple.t_data.set_t_xmin(xmin);
+        tuple.t_data.set_t_xmax(xmax);
+        tuple
+    }
+
+    #[test]
+    fn test_mvcc_visibility() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+        create_clog_file(&config).unwrap();
+        let transam = TransactionManager::new(config.clone());
+
+        let committed = transam.get_new_transaction_id().unwrap();
+        let in_progress = transam.get_new_transaction_id().unwrap();
+        let current = transam.get_new_transaction_id().unwrap();
+        transam.transaction_id_commit(committed).unwrap();
+
+        let snapshot = get_snapshot_data(current, &transam).unwrap();
+        assert_eq!(snapshot.xmin, in_progress);
+        assert_eq!(snapshot.xmax, current + 1);
+        assert_eq!(snapshot.xip, vec![in_progress]);
+
+        // Transactions which commit or start after the snapshot are invisible.
+        let later = transam.get_new_transaction_id().unwrap();
+        transam.transaction_id_commit(in_progress).unwrap();
+        transam.transaction_id_commit(later).unwrap();
+
+        assert!(heap_tuple_satisfies_visibility(&tuple(committed, InvalidTransactionId).t_data, &snapshot).unwrap());
+        assert!(heap_tuple_satisfies_visibility(&tuple(current, InvalidTransactionId).t_data, &snapshot).unwrap());
+        assert!(!heap_tuple_satisfies_visibility(&tuple(in_progress, InvalidTransactionId).t_data, &snapshot).unwrap());
+        assert!(!heap_tuple_satisfies_visibility(&tuple(later, InvalidTransactionId).t_data, &snapshot).unwrap());
+
+        assert!(!heap_tuple_satisfies_visibility(&tuple(committed, current).t_data, &snapshot).unwrap());
+        assert!(!heap_tuple_satisfies_visibility(&tuple(current, current).t_data, &snapshot).unwrap());
+        assert!(heap_tuple_satisfies_visibility(&tuple(committed, in_progress).t_data, &snapshot).unwrap());
+        assert!(heap_tuple_satisfies_visibility(&tuple(committed, later).t_data, &snapshot).unwrap());
+
+        // New snapshot sees them.
+        let snapshot = get_snapshot_data(InvalidTransactionId, &transam).unwrap();
+        assert!(heap_tuple_satisfies_visibility(&tuple(in_progress, InvalidTransactionId).t_data, &snapshot).unwrap());
+        assert!(!heap_tuple_satisfies_visibility(&tuple(committed, later).t_data, &snapshot).unwrap());
+        // `current` is not committed.
+        assert!(!heap_tuple_satisfies_visibility(&tuple(current, InvalidTransactionId).t_data, &snapshot).unwrap());
+    }
+
+    #[test]
+    fn test_vacuum_visibility() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+        create_clog_file(&config).unwrap();
+        let transam = TransactionManager::new(config.clone());
+
+        let committed = transam.get_new_transaction_id().unwrap();
+        let aborted = transam.get_new_transaction_id().unwrap();
+        let in_progress = transam.get_new_transaction_id().unwrap();
+        let recent = transam.get_new_transaction_id().unwrap();
+        transam.transaction_id_commit(committed).unwrap();
+        transam.transaction_id_abort(aborted).unwrap();
+        transam.transaction_id_commit(recent).unwrap();
+
+        // `in_progress` may see tuples deleted by `recent` as live.
+        let oldest_xmin = get_snapshot_data(InvalidTransactionId, &transam).unwrap().xmin;
+        assert_eq!(oldest_xmin, in_progress);
+
+        let vacuum = |xmin, xmax| heap_tuple_satisfies_vacuum(&tuple(xmin, xmax).t_data, oldest_xmin, &transam).unwrap();
+        assert_eq!(vacuum(committed, InvalidTransactionId), HTSVResult::Live);
+        assert_eq!(vacuum(committed, aborted), HTSVResult::Live);
+        assert_eq!(vacuum(aborted, InvalidTransactionId), HTSVResult::Dead);
+        assert_eq!(vacuum(in_progress, InvalidTransactionId), HTSVResult::InsertInProgress);
+        assert_eq!(vacuum(committed, in_progress), HTSVResult::DeleteInProgress);
+        assert_eq!(vacuum(committed, committed), HTSVResult::Dead);
+        assert_eq!(vacuum(committed, recent), HTSVResult::RecentlyDead);
+    }
+}
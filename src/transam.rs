@@ -0,0 +1,271 @@
+// Transaction ids and the commit log (`transam.c`, `varsup.c` and `clog.c` in pg).
+//
+// The commit log is stored in "root directory / global / clog" and has one
+// byte of status for each transaction id from FirstNormalTransactionId.
+// A new transaction id is assigned by appending IN_PROGRESS status to the
+// file, so the next transaction id is derived from the size of the file.
+//
+// If a process dies before the end of its transaction, the status of the
+// transaction stays IN_PROGRESS until the next startup, which marks it as
+// aborted after WAL is replayed. Otherwise it would stay in snapshots
+// forever and hold back VACUUM.
+//
+// The next transaction id and ids of in-progress transactions are read from
+// the file once, then kept in memory (the proc array in pg). This relies on
+// transaction ids being assigned only by one `TransactionManager` of the
+// session, which holds the lock of the data directory.
+
+#![allow(non_upper_case_globals)]
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+use config::Config;
+
+// `TransactionId` in pg.
+pub type TransactionId = u32;
+pub const InvalidTransactionId: TransactionId = 0;
+pub const FirstNormalTransactionId: TransactionId = 3;
+
+// `XidStatus` in pg.
+pub type XidStatus = u8;
+pub const TRANSACTION_STATUS_IN_PROGRESS: XidStatus = 0x00;
+pub const TRANSACTION_STATUS_COMMITTED: XidStatus = 0x01;
+pub const TRANSACTION_STATUS_ABORTED: XidStatus = 0x02;
+
+pub fn create_clog_file(config: &Config) -> io::Result<()> {
+    File::create(config.clog_file_path()).map(|_| ())
+}
+
+// `TransactionIdIsNormal` in pg.
+pub fn transaction_id_is_normal(xid: TransactionId) -> bool {
+    xid >= FirstNormalTransactionId
+}
+
+pub struct TransactionManager {
+    config: Rc<Config>,
+    // Status of committed or aborted transactions never changes,
+    // so they can be cached.
+    cache: RefCell<HashMap<TransactionId, XidStatus>>,
+    // None until the first snapshot.
+    running: RefCell<Option<RunningTransactions>>,
+}
+
+// `RunningTransactionsData` in pg.
+struct RunningTransactions {
+    next_xid: TransactionId,
+    xip: BTreeSet<TransactionId>,
+}
+
+impl TransactionManager {
+    pub fn new(config: Rc<Config>) -> TransactionManager {
+        TransactionManager {
+            config: config,
+            cache: RefCell::new(HashMap::new()),
+            running: RefCell::new(None),
+        }
+    }
+
+    // `GetNewTransactionId` in pg.
+    pub fn get_new_transaction_id(&self) -> io::Result<TransactionId> {
+        let mut f = OpenOptions::new().append(true).open(self.config.clog_file_path())?;
+        let len = f.metadata()?.len();
+        f.write_all(&[TRANSACTION_STATUS_IN_PROGRESS])?;
+        f.sync_data()?;
+
+        let xid = FirstNormalTransactionId + len as TransactionId;
+
+        if let Some(running) = self.running.borrow_mut().as_mut() {
+            running.next_xid = xid + 1;
+            running.xip.insert(xid);
+        }
+
+        Ok(xid)
+    }
+
+    // `TransactionIdCommitTree` in pg.
+    pub fn transaction_id_commit(&self, xid: TransactionId) -> io::Result<()> {
+        self.set_status(xid, TRANSACTION_STATUS_COMMITTED)
+    }
+
+    // `TransactionIdAbortTree` in pg.
+    pub fn transaction_id_abort(&self, xid: TransactionId) -> io::Result<()> {
+        self.set_status(xid, TRANSACTION_STATUS_ABORTED)
+    }
+
+    // `TransactionIdDidCommit` in pg.
+    pub fn transaction_id_did_commit(&self, xid: TransactionId) -> io::Result<bool> {
+        Ok(self.get_status(xid)? == TRANSACTION_STATUS_COMMITTED)
+    }
+
+    // `TransactionIdDidAbort` in pg.
+    pub fn transaction_id_did_abort(&self, xid: TransactionId) -> io::Result<bool> {
+        Ok(self.get_status(xid)? == TRANSACTION_STATUS_ABORTED)
+    }
+
+    // Returns the next transaction id and ids of in-progress transactions.
+    // Used to take a snapshot (pg reads them from the proc array). The
+    // commit log is read only at the first call.
+    pub fn get_running_transactions(&self) -> io::Result<(TransactionId, Vec<TransactionId>)> {
+        let mut running = self.running.borrow_mut();
+
+        if running.is_none() {
+            *running = Some(self.load_running_transactions()?);
+        }
+
+        let running = running.as_ref().unwrap();
+        Ok((running.next_xid, running.xip.iter().cloned().collect()))
+    }
+
+    // Mark transactions left IN_PROGRESS by a crashed process as aborted.
+    // pg treats xids which are not in the proc array after recovery as
+    // aborted. We do not have the proc array, so this should be called
+    // only when no other session is running. Returns the number of them.
+    pub fn abort_in_progress_transactions(&self) -> io::Result<usize> {
+        let mut f = OpenOptions::new().read(true).write(true).open(self.config.clog_file_path())?;
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf)?;
+
+        let mut n = 0;
+
+        for status in buf.iter_mut().filter(|status| **status == TRANSACTION_STATUS_IN_PROGRESS) {
+            *status = TRANSACTION_STATUS_ABORTED;
+            n += 1;
+        }
+
+        if n > 0 {
+            f.seek(SeekFrom::Start(0))?;
+            f.write_all(&buf)?;
+            f.sync_data()?;
+        }
+
+        if let Some(running) = self.running.borrow_mut().as_mut() {
+            running.xip.clear();
+        }
+
+        Ok(n)
+    }
+
+    fn load_running_transactions(&self) -> io::Result<RunningTransactions> {
+        let mut buf = Vec::new();
+        File::open(self.config.clog_file_path())?.read_to_end(&mut buf)?;
+
+        let xip = buf.iter().enumerate()
+                     .filter(|(_, status)| **status == TRANSACTION_STATUS_IN_PROGRESS)
+                     .map(|(i, _)| FirstNormalTransactionId + i as TransactionId)
+                     .collect();
+
+        Ok(RunningTransactions {
+            next_xid: FirstNormalTransactionId + buf.len() as TransactionId,
+            xip: xip,
+        })
+    }
+
+    // `TransactionIdSetTreeStatus` in pg.
+    fn set_status(&self, xid: TransactionId, status: XidStatus) -> io::Result<()> {
+        if !transaction_id_is_normal(xid) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid transaction id {}", xid)));
+        }
+
+        let mut f = OpenOptions::new().write(true).open(self.config.clog_file_path())?;
+        f.seek(SeekFrom::Start((xid - FirstNormalTransactionId) as u64))?;
+        f.write_all(&[status])?;
+        f.sync_data()?;
+
+        self.cache.borrow_mut().insert(xid, status);
+
+        if let Some(running) = self.running.borrow_mut().as_mut() {
+            running.xip.remove(&xid);
+        }
+
+        Ok(())
+    }
+
+    // `TransactionLogFetch` in pg.
+    fn get_status(&self, xid: TransactionId) -> io::Result<XidStatus> {
+        if !transaction_id_is_normal(xid) {
+            return Ok(TRANSACTION_STATUS_ABORTED);
+        }
+
+        if let Some(status) = self.cache.borrow().get(&xid) {
+            return Ok(*status);
+        }
+
+        let status = self.read_status(xid).map_err(|e| {
+            io::Error::new(e.kind(), format!("could not read commit log {:?}: {}", self.config.clog_file_path(), e))
+        })?;
+
+        if status != TRANSACTION_STATUS_IN_PROGRESS {
+            self.cache.borrow_mut().insert(xid, status);
+        }
+
+        Ok(status)
+    }
+
+    fn read_status(&self, xid: TransactionId) -> io::Result<XidStatus> {
+        let mut f = File::open(self.config.clog_file_path())?;
+        let mut buf = [TRANSACTION_STATUS_IN_PROGRESS; 1];
+        f.seek(SeekFrom::Start((xid - FirstNormalTransactionId) as u64))?;
+        f.read(&mut buf)?;
+
+        Ok(buf[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_transaction_status() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+        create_clog_file(&config).unwrap();
+
+        let transam = TransactionManager::new(config.clone());
+        let xid1 = transam.get_new_transaction_id().unwrap();
+        let xid2 = transam.get_new_transaction_id().unwrap();
+        let xid3 = transam.get_new_transaction_id().unwrap();
+        assert_eq!(xid1, FirstNormalTransactionId);
+        assert_eq!(xid2, xid1 + 1);
+        assert_eq!(xid3, xid1 + 2);
+
+        transam.transaction_id_commit(xid1).unwrap();
+        transam.transaction_id_abort(xid2).unwrap();
+
+        // Read statuses from the file.
+        let transam = TransactionManager::new(config.clone());
+        assert!(transam.transaction_id_did_commit(xid1).unwrap());
+        assert!(!transam.transaction_id_did_abort(xid1).unwrap());
+        assert!(transam.transaction_id_did_abort(xid2).unwrap());
+        assert!(!transam.transaction_id_did_commit(xid3).unwrap());
+        assert!(!transam.transaction_id_did_abort(xid3).unwrap());
+        assert!(!transam.transaction_id_did_commit(InvalidTransactionId).unwrap());
+
+        let (next_xid, xip) = transam.get_running_transactions().unwrap();
+        assert_eq!(next_xid, xid3 + 1);
+        assert_eq!(xip, vec![xid3]);
+
+        // In-progress transactions are kept in memory after the first read.
+        let xid4 = transam.get_new_transaction_id().unwrap();
+        assert_eq!(transam.get_running_transactions().unwrap(), (xid4 + 1, vec![xid3, xid4]));
+        transam.transaction_id_commit(xid4).unwrap();
+        let saved = config.clog_file_path().with_extension("saved");
+        fs::rename(config.clog_file_path(), &saved).unwrap();
+        assert_eq!(transam.get_running_transactions().unwrap(), (xid4 + 1, vec![xid3]));
+        fs::rename(&saved, config.clog_file_path()).unwrap();
+        let next_xid = xid4 + 1;
+
+        // `xid3` is left by a crashed process.
+        assert_eq!(transam.abort_in_progress_transactions().unwrap(), 1);
+        assert!(transam.transaction_id_did_abort(xid3).unwrap());
+        assert!(transam.transaction_id_did_commit(xid1).unwrap());
+        assert_eq!(transam.get_running_transactions().unwrap(), (next_xid, vec![]));
+    }
+}
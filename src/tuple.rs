@@ -1,13 +1,17 @@
 use std::mem;
+use std::slice;
 
 use catalog::mini_attribute::MiniAttributeRecord;
+use error::Error;
 use off::{OffsetNumber, FirstOffsetNumber, InvalidOffsetNumber};
-use ty::{TypeValue, load_type_value, build_type_value};
+use ty::{TypeValue, Datum, Null, VARHDRSZ, load_type_value, load_varlena_len, build_type_value};
 use buffer_manager::{BlockIdData, BlockNumber, InvalidBlockNumber};
+use transam::TransactionId;
 
+// `None` value means null.
 pub struct KeyValue<'a> {
     key: &'a str,
-    value: &'a str,
+    value: Option<&'a str>,
 }
 
 pub struct KeyValueBuilder<'a> {
@@ -19,7 +23,7 @@ impl<'a> KeyValueBuilder<'a> {
         KeyValueBuilder { key_values: Vec::new() }
     }
 
-    pub fn add_pair(&mut self, key: &'a str, value: &'a str) {
+    pub fn add_pair(&mut self, key: &'a str, value: Option<&'a str>) {
         self.key_values.push(KeyValue {
             key: key,
             value: value,
@@ -32,7 +36,8 @@ impl<'a> KeyValueBuilder<'a> {
 }
 
 // From itemptr.h in pg.
-#[derive(Debug, Clone)]
+// Ordered by block number and offset number (`ItemPointerCompare` in pg).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ItemPointerData
 {
     ip_blkid: BlockIdData,
@@ -83,12 +88,16 @@ pub struct HeapTupleData {
 // The contents of this struct are directly read from/write to
 // a tuple of pages.
 //
-// The data field of this struct includes t_infomask2
-// and t_infomask.
+// The data field of this struct includes t_xmin, t_xmax, t_infomask2
+// and t_infomask. If the tuple has null attributes (HEAP_HASNULL),
+// null bitmap follows them.
 //
 // struct HeapTupleHeaderData {
+//     t_xmin: TransactionId, // inserting xact ID
+//     t_xmax: TransactionId, // deleting xact ID
 //     t_infomask2: u16,
 //     t_infomask: u16,
+//     t_bits: [u8; BITMAPLEN(natts)], // only if HEAP_HASNULL
 //     data: *mut u8,
 // }
 #[derive(Debug)]
@@ -96,17 +105,36 @@ pub struct HeapTupleHeaderData {
     ptr: *mut u8,
 }
 
-// t_infomask2 and t_infomask
-const SIZE_OF_HEADER_HEADER: usize = mem::size_of::<u16>() * 2;
+// t_xmin, t_xmax, t_infomask2 and t_infomask
+const SIZE_OF_HEADER_HEADER: usize = mem::size_of::<TransactionId>() * 2 + mem::size_of::<u16>() * 2;
+// Offset of t_infomask2
+const INFOMASK_OFFSET: usize = mem::size_of::<TransactionId>() * 2;
 
 // tuple was updated and key cols modified, or tuple deleted
 const HEAP_KEYS_UPDATED: u16 = 0x2000;
 
+// has null attribute(s)
+const HEAP_HASNULL: u16 = 0x0001;
+
+// `BITMAPLEN` in pg.
+fn bitmap_len(natts: usize) -> usize {
+    (natts + 7) / 8
+}
+
+fn type_value_bytes(ty: &TypeValue) -> Option<Vec<u8>> {
+    if ty.is_null() {
+        return None;
+    }
+
+    let mut v = Vec::with_capacity(ty.len() as usize);
+    ty.write_bytes(&mut v).unwrap();
+    Some(v)
+}
+
 impl TupleTableSlot {
     pub fn new(attrs: Vec<MiniAttributeRecord>) -> TupleTableSlot {
-        let len = attrs.iter().fold(0, |acc, attr| acc + attr.len) as u32;
         let tuple_desc = TupleDesc::new(attrs);
-        let heap_tuple = HeapTupleData::new(len);
+        let heap_tuple = HeapTupleData::new(tuple_desc.attrs_min_len());
 
         TupleTableSlot {
             tuple_desc: Box::new(tuple_desc),
@@ -138,45 +166,115 @@ impl TupleTableSlot {
         self.tuple_desc.attrs.iter().position(|ref a| a.name == name).unwrap()
     }
 
+    // index is 0-origin.
+    pub fn get_attribute(&self, index: usize) -> &MiniAttributeRecord {
+        self.check_index(index);
+
+        &self.tuple_desc.attrs[index]
+    }
+
+    // index is 0-origin.
+    pub fn is_null(&self, index: usize) -> bool {
+        self.check_index(index);
+
+        self.heap_tuple.t_data.att_isnull(index)
+    }
+
     // index is 0-origin.
     pub fn get_column(&self, index: usize) -> Box<TypeValue> {
         self.check_index(index);
 
+        if self.is_null(index) {
+            return Box::new(Null);
+        }
+
         let attr = &self.tuple_desc.attrs[index];
         let ptr = self.attr_ptr(index) as *const libc::c_void;
         load_type_value(&attr.ty, ptr)
     }
 
+    // index is 0-origin.
+    pub fn get_datum(&self, index: usize) -> Datum {
+        self.get_column(index).datum()
+    }
+
+    // Length of value may be changed if the column is variable length,
+    // so this method rebuilds whole data of the tuple.
     pub fn set_column(&mut self, index: usize, ty: &TypeValue) {
         // TODO: we should also check type of passed `ty` matches with
         //       the type of `attr`.
         self.check_index(index);
 
-        let src = ty.as_pointer();
-        let n = ty.len();
-        let offset = self.tuple_desc.attrs_len(index) as usize;
-        self.heap_tuple.t_data.set_column(src, n, offset);
+        let mut columns: Vec<Option<Vec<u8>>> = Vec::with_capacity(self.attrs_count());
+
+        for i in 0..(self.attrs_count()) {
+            if i == index {
+                columns.push(type_value_bytes(ty));
+            } else if self.is_null(i) {
+                columns.push(None);
+            } else {
+                let n = self.attr_len(i);
+                let v = unsafe { slice::from_raw_parts(self.attr_ptr(i), n) };
+                columns.push(Some(v.to_vec()));
+            }
+        }
+
+        self.form_tuple_from_bytes(&columns);
+    }
+
+    fn form_tuple(&mut self, values: &Vec<Box<TypeValue>>) {
+        let columns = values.iter().map(|v| type_value_bytes(v.as_ref())).collect();
+        self.form_tuple_from_bytes(&columns);
     }
 
-    pub fn update_tuple(&mut self, key_values: Vec<KeyValue>) -> Result<(), String> {
-        if self.attrs_count() != key_values.len() {
-            return Err(format!("Length not match. attrs: {}, key_values: {}", self.attrs_count(), key_values.len()));
+    // `heap_form_tuple` in pg.
+    //
+    // `None` of `columns` means null.
+    fn form_tuple_from_bytes(&mut self, columns: &Vec<Option<Vec<u8>>>) {
+        let has_null = columns.iter().any(|c| c.is_none());
+        let mut bits: Vec<u8> = vec![0; bitmap_len(columns.len())];
+        let mut data: Vec<u8> = Vec::with_capacity(self.len() as usize);
+
+        for (i, c) in columns.iter().enumerate() {
+            if let Some(v) = c {
+                // Bit is set if the attribute is not null.
+                bits[i >> 3] |= 1 << (i & 0x07);
+                data.extend_from_slice(v);
+            }
         }
 
-        for (kv, attr) in key_values.iter().zip(self.tuple_desc.attrs.iter()) {
-            if kv.key != attr.name {
-                return Err(format!("Name not match. attrs: {}, key_values: {}", attr.name, kv.key));
+        if has_null {
+            self.heap_tuple.set_data(Some(&bits), &data);
+        } else {
+            self.heap_tuple.set_data(None, &data);
+        }
+    }
+
+    // Columns which are not included in `key_values` are set to null.
+    pub fn update_tuple(&mut self, key_values: Vec<KeyValue>) -> Result<(), Error> {
+        for (i, kv) in key_values.iter().enumerate() {
+            if !self.tuple_desc.attrs.iter().any(|attr| attr.name == kv.key) {
+                return Err(Error::UndefinedColumn(format!("Column ({}) does not exist.", kv.key)));
+            }
+
+            if key_values.iter().skip(i + 1).any(|other| other.key == kv.key) {
+                return Err(Error::DuplicateColumn(format!("Column ({}) is specified more than once.", kv.key)));
             }
         }
 
-        let v: Vec<Box<TypeValue>> = key_values.iter().zip(self.tuple_desc.attrs.iter()).map(|(kv, attr)|
-            build_type_value(&attr.ty, &kv.value)
-        ).collect();
+        let mut v: Vec<Box<TypeValue>> = Vec::with_capacity(self.attrs_count());
 
-        for (i, t) in v.iter().enumerate() {
-            self.set_column(i, t.as_ref());
+        for attr in self.tuple_desc.attrs.iter() {
+            let value = key_values.iter().find(|kv| kv.key == attr.name).and_then(|kv| kv.value);
+
+            match value {
+                Some(s) => v.push(build_type_value(attr, s)?),
+                None => v.push(Box::new(Null)),
+            }
         }
 
+        self.form_tuple(&v);
+
         Ok(())
     }
 
@@ -190,11 +288,31 @@ impl TupleTableSlot {
         }
     }
 
+    // `nocachegetattr` in pg.
+    //
+    // Walk attributes from the first one because offset of attributes
+    // after variable length attribute depends on data.
     fn attr_ptr(&self, index: usize) -> *const u8 {
-        unsafe {
-            let p = self.heap_tuple.t_data.data_ptr() as *const u8;
-            p.add(self.tuple_desc.attrs_len(index) as usize)
+        let t_data = &self.heap_tuple.t_data;
+        let mut p = unsafe { t_data.ptr.add(t_data.t_hoff(self.attrs_count())) as *const u8 };
+
+        for i in 0..index {
+            // Null attribute does not have data.
+            if t_data.att_isnull(i) {
+                continue;
+            }
+
+            unsafe {
+                p = p.add(self.tuple_desc.attr_len(i, p));
+            }
         }
+
+        p
+    }
+
+    // Byte length of the attribute including length header.
+    fn attr_len(&self, index: usize) -> usize {
+        self.tuple_desc.attr_len(index, self.attr_ptr(index))
     }
 }
 
@@ -223,12 +341,26 @@ impl TupleDesc {
         self.attrs.len()
     }
 
-    fn attrs_total_len(&self) -> u32 {
-        self.attrs.iter().fold(0, |acc, attr| acc + attr.len) as u32
+    // Byte length of data whose variable length attributes are all empty.
+    fn attrs_min_len(&self) -> u32 {
+        self.attrs.iter().fold(0, |acc, attr| {
+            if attr.is_varlena() {
+                acc + VARHDRSZ
+            } else {
+                acc + attr.len
+            }
+        }) as u32
     }
 
-    fn attrs_len(&self, index: usize) -> u32 {
-        self.attrs.iter().take(index).fold(0, |acc, attr| acc + attr.len) as u32
+    // `ptr` points the head of the attribute.
+    fn attr_len(&self, index: usize, ptr: *const u8) -> usize {
+        let attr = &self.attrs[index];
+
+        if attr.is_varlena() {
+            VARHDRSZ + load_varlena_len(ptr as *const libc::c_void)
+        } else {
+            attr.len
+        }
     }
 }
 
@@ -254,15 +386,47 @@ impl HeapTupleData {
         }
     }
 
-    fn load(&mut self, src: *const libc::c_void, n: u32, t_self: ItemPointerData) {
-        if self.t_len < n {
-            panic!("Try to load over size data. t_len: {}, n: {}.", self.t_len, n);
+    // Length of tuples are different if the relation has variable
+    // length attributes, so reallocate data if needed.
+    pub fn load(&mut self, src: *const libc::c_void, n: u32, t_self: ItemPointerData) {
+        if self.t_len != n {
+            self.t_len = n;
+            self.t_data = Box::new(HeapTupleHeaderData::new(n));
         }
 
         self.t_self = t_self;
         self.t_data.load(src, n);
     }
 
+    // Replace null bitmap and data (after header) of the tuple.
+    fn set_data(&mut self, bits: Option<&Vec<u8>>, data: &Vec<u8>) {
+        let bits_len = bits.map_or(0, |b| b.len());
+        let len = (SIZE_OF_HEADER_HEADER + bits_len + data.len()) as u32;
+        let mut t_data = Box::new(HeapTupleHeaderData::new(len));
+        t_data.load(self.t_data.ptr as *const libc::c_void, SIZE_OF_HEADER_HEADER as u32);
+
+        let mask = t_data.t_infomask();
+
+        match bits {
+            Some(b) => {
+                t_data.set_t_infomask(mask | HEAP_HASNULL);
+                t_data.set_column(b.as_ptr() as *const libc::c_void, bits_len as u32, 0);
+            },
+            None => {
+                t_data.set_t_infomask(mask & !HEAP_HASNULL);
+            }
+        }
+
+        t_data.set_column(data.as_ptr() as *const libc::c_void, data.len() as u32, bits_len);
+
+        self.t_len = len;
+        self.t_data = t_data;
+    }
+
+    pub fn len(&self) -> u32 {
+        self.t_len
+    }
+
     pub fn load_without_len(&mut self, src: *const libc::c_void, t_self: ItemPointerData) {
         self.t_self = t_self;
         self.t_data.load(src, self.t_len);
@@ -284,9 +448,10 @@ impl HeapTupleData {
 impl HeapTupleHeaderData {
     fn new(data_size: u32) -> HeapTupleHeaderData {
         unsafe {
-            let data_p: *mut u8 = libc::malloc(data_size as libc::size_t) as *mut u8;
+            // Use calloc so that the header of a new tuple is cleared.
+            let data_p: *mut u8 = libc::calloc(1, data_size as libc::size_t) as *mut u8;
 
-            debug!("HeapTupleHeaderData malloc: {:?}, {}", data_p, data_size);
+            debug!("HeapTupleHeaderData calloc: {:?}, {}", data_p, data_size);
 
             if data_p.is_null() {
                 panic!("failed to allocate memory");
@@ -308,20 +473,92 @@ impl HeapTupleHeaderData {
         (mask2 & HEAP_KEYS_UPDATED) != 0
     }
 
+    // `HeapTupleHeaderGetXmin` in pg.
+    pub fn t_xmin(&self) -> TransactionId {
+        unsafe {
+            let p = self.ptr as *const TransactionId;
+            *p
+        }
+    }
+
+    // `HeapTupleHeaderSetXmin` in pg.
+    pub fn set_t_xmin(&mut self, xid: TransactionId) {
+        unsafe {
+            let p = self.ptr as *mut TransactionId;
+            *p = xid;
+        }
+    }
+
+    // `HeapTupleHeaderGetRawXmax` in pg.
+    pub fn t_xmax(&self) -> TransactionId {
+        unsafe {
+            let p = (self.ptr as *const TransactionId).add(1);
+            *p
+        }
+    }
+
+    // `HeapTupleHeaderSetXmax` in pg.
+    pub fn set_t_xmax(&mut self, xid: TransactionId) {
+        unsafe {
+            let p = (self.ptr as *mut TransactionId).add(1);
+            *p = xid;
+        }
+    }
+
     fn t_infomask2(&self) -> u16 {
         unsafe {
-            let p = self.ptr as *const u16;
+            let p = self.ptr.add(INFOMASK_OFFSET) as *const u16;
             *p
         }
     }
 
     fn set_t_infomask2(&mut self, mask: u16) {
         unsafe {
-            let p = self.ptr as *mut u16;
+            let p = self.ptr.add(INFOMASK_OFFSET) as *mut u16;
             *p = mask;
         }
     }
 
+    fn t_infomask(&self) -> u16 {
+        unsafe {
+            let p = (self.ptr.add(INFOMASK_OFFSET) as *const u16).add(1);
+            *p
+        }
+    }
+
+    fn set_t_infomask(&mut self, mask: u16) {
+        unsafe {
+            let p = (self.ptr.add(INFOMASK_OFFSET) as *mut u16).add(1);
+            *p = mask;
+        }
+    }
+
+    // `HeapTupleHasNulls` in pg.
+    pub fn has_null(&self) -> bool {
+        (self.t_infomask() & HEAP_HASNULL) != 0
+    }
+
+    // `att_isnull` in pg. index is 0-origin.
+    fn att_isnull(&self, index: usize) -> bool {
+        if !self.has_null() {
+            return false;
+        }
+
+        unsafe {
+            let bits = self.data_ptr() as *const u8;
+            (*bits.add(index >> 3) & (1 << (index & 0x07))) == 0
+        }
+    }
+
+    // Offset to user data (`t_hoff` in pg).
+    fn t_hoff(&self, natts: usize) -> usize {
+        if self.has_null() {
+            SIZE_OF_HEADER_HEADER + bitmap_len(natts)
+        } else {
+            SIZE_OF_HEADER_HEADER
+        }
+    }
+
     fn data_ptr(&self) -> *const libc::c_void {
         unsafe {
             self.ptr.add(SIZE_OF_HEADER_HEADER) as *const libc::c_void
@@ -389,7 +626,7 @@ impl ItemPointerData {
 mod tests {
     use super::*;
     use catalog::mini_attribute::TypeLabel;
-    use ty::Integer;
+    use ty::{Integer, Text};
 
     #[test]
     fn test_tuple_table_slot() {
@@ -442,4 +679,104 @@ mod tests {
         assert_eq!(slot.get_column(0).as_string(), "10".to_string());
         assert_eq!(slot.get_column(1).as_string(), "22".to_string());
     }
+
+    #[test]
+    fn test_tuple_table_slot_varlena_column() {
+        let mut attrs = Vec::new();
+        attrs.push(MiniAttributeRecord::new(
+            "id".to_string(),
+            30001,
+            30002,
+            TypeLabel::Integer,
+            4
+        ));
+        attrs.push(MiniAttributeRecord::new(
+            "name".to_string(),
+            30001,
+            30002,
+            TypeLabel::Text,
+            0
+        ));
+        attrs.push(MiniAttributeRecord::new(
+            "age".to_string(),
+            30001,
+            30002,
+            TypeLabel::Integer,
+            4
+        ));
+        let mut slot = TupleTableSlot::new(attrs);
+
+        assert_eq!(slot.len(), 12 + 4 + 4 + 4);
+        assert_eq!(slot.get_column(1).as_string(), "".to_string());
+
+        slot.set_column(0, &Integer { elem: 10 });
+        slot.set_column(1, &Text { elem: "hello".to_string() });
+        slot.set_column(2, &Integer { elem: 22 });
+
+        assert_eq!(slot.len(), 12 + 4 + (4 + 5) + 4);
+        assert_eq!(slot.get_column(0).as_string(), "10".to_string());
+        assert_eq!(slot.get_column(1).as_string(), "hello".to_string());
+        assert_eq!(slot.get_column(2).as_string(), "22".to_string());
+
+        slot.set_column(1, &Text { elem: "hi".to_string() });
+
+        assert_eq!(slot.len(), 12 + 4 + (4 + 2) + 4);
+        assert_eq!(slot.get_column(1).as_string(), "hi".to_string());
+        assert_eq!(slot.get_column(2).as_string(), "22".to_string());
+    }
+
+    #[test]
+    fn test_tuple_table_slot_null_column() {
+        let mut attrs = Vec::new();
+        attrs.push(MiniAttributeRecord::new(
+            "id".to_string(),
+            40001,
+            40002,
+            TypeLabel::Integer,
+            4
+        ));
+        attrs.push(MiniAttributeRecord::new(
+            "name".to_string(),
+            40001,
+            40002,
+            TypeLabel::Text,
+            0
+        ));
+        attrs.push(MiniAttributeRecord::new(
+            "age".to_string(),
+            40001,
+            40002,
+            TypeLabel::Integer,
+            4
+        ));
+        let mut slot = TupleTableSlot::new(attrs);
+        let mut builder = KeyValueBuilder::new();
+        builder.add_pair("age", Some("22"));
+        builder.add_pair("id", None);
+        slot.update_tuple(builder.build()).unwrap();
+
+        assert_eq!(slot.heap_tuple.t_data.has_null(), true);
+        // header + null bitmap + age
+        assert_eq!(slot.len(), 12 + 1 + 4);
+        assert_eq!(slot.is_null(0), true);
+        assert_eq!(slot.is_null(1), true);
+        assert_eq!(slot.is_null(2), false);
+        assert_eq!(slot.get_column(2).as_string(), "22".to_string());
+
+        slot.set_column(1, &Text { elem: "abc".to_string() });
+
+        assert_eq!(slot.is_null(0), true);
+        assert_eq!(slot.get_column(1).as_string(), "abc".to_string());
+        assert_eq!(slot.get_column(2).as_string(), "22".to_string());
+
+        slot.set_column(0, &Integer { elem: 10 });
+
+        assert_eq!(slot.heap_tuple.t_data.has_null(), false);
+        assert_eq!(slot.len(), 12 + 4 + (4 + 3) + 4);
+        assert_eq!(slot.get_column(0).as_string(), "10".to_string());
+
+        let mut builder = KeyValueBuilder::new();
+        builder.add_pair("foo", Some("1"));
+        assert!(slot.update_tuple(builder.build()).is_err());
+    }
 }
@@ -0,0 +1,592 @@
+// Sort tuples in memory or on disk (`tuplesort.c` and `logtape.c` in pg).
+//
+// Tuples are sorted in memory while they fit in `work_mem`. Otherwise tuples
+// in memory are sorted and written to a temporary file as a run whenever
+// `work_mem` is exceeded, and finally all runs are merged by k-way merge with
+// a binary heap of the first tuple of each run. Each run being merged keeps
+// a file open, so at most `merge order` runs are merged at once. If there
+// are more runs, the first runs are merged into a new run until the rest can
+// be merged at once (pg uses polyphase merge instead). Runs are removed when
+// the sort ends, and runs left by a crash are removed at startup.
+//
+// A tuple in a run is stored as below. Sort keys are not stored, they are
+// evaluated again when the tuple is read.
+//
+// +-----------+-------------------+---------------------+---------------------+
+// | len (u32) | tuple data        | block number (u32)  | offset number (u16) |
+// +-----------+-------------------+---------------------+---------------------+
+
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write, ErrorKind};
+use std::mem;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{self, AtomicUsize};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use ast::{SortBy, SortByDir};
+use config::{Config, DEFAULT_BLOCK_SIZE};
+use error::Error;
+use executor::node_seqscan::ExprEvaluator;
+use tuple::{TupleTableSlot, ItemPointerData, item_pointer_set, item_pointer_get_block_number};
+use ty::Datum;
+
+// Used to name temporary files uniquely in the process.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Bounds of the merge order (`MINORDER` and `MAXORDER` in pg).
+const MIN_MERGE_ORDER: usize = 6;
+const MAX_MERGE_ORDER: usize = 500;
+// Buffer used by each run during the merge (`MERGE_BUFFER_SIZE` and
+// `TAPE_BUFFER_OVERHEAD` in pg).
+const MERGE_BUFFER_SIZE: usize = DEFAULT_BLOCK_SIZE as usize * 32;
+const TAPE_BUFFER_OVERHEAD: usize = DEFAULT_BLOCK_SIZE as usize;
+
+// `RemovePgTempFiles` in pg.
+//
+// Remove temporary files left by a process which crashed during a sort.
+// Called at startup, when no sort is running.
+pub fn remove_pg_temp_files(config: &Config) -> io::Result<()> {
+    let entries = match fs::read_dir(config.temp_dir_path()) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if !path.file_name().map_or(false, |name| name.to_string_lossy().starts_with("pgsql_tmp")) {
+            continue;
+        }
+
+        match fs::remove_file(&path) {
+            Ok(_) => debug!("Removed temporary file {:?}", path),
+            Err(e) => warn!("could not remove file {:?}: {}", path, e),
+        }
+    }
+
+    Ok(())
+}
+
+// `tuplesort_merge_order` in pg. The number of runs merged at once.
+fn tuplesort_merge_order(allowed_mem: usize) -> usize {
+    let order = allowed_mem.saturating_sub(TAPE_BUFFER_OVERHEAD) / (MERGE_BUFFER_SIZE + TAPE_BUFFER_OVERHEAD);
+    order.max(MIN_MERGE_ORDER).min(MAX_MERGE_ORDER)
+}
+
+// `SortTuple` in pg.
+struct SortTuple {
+    keys: Vec<Datum>,
+    slot: Box<TupleTableSlot>,
+}
+
+// A sorted run in a temporary file (a tape of `LogicalTapeSet` in pg). The
+// file is removed when the run is dropped, even if the sort fails.
+struct Run {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TupSortStatus {
+    // Loading tuples. Tuples may be written to runs.
+    Initial,
+    // Tuples are sorted in memory.
+    SortedInMem,
+    // Runs are being merged.
+    FinalMerge,
+}
+
+pub struct Tuplesortstate<'a> {
+    config: Rc<Config>,
+    sort_keys: &'a Vec<SortBy>,
+    status: TupSortStatus,
+    // `allowedMem` in pg (bytes)
+    allowed_mem: usize,
+    // Approximate memory used by `memtuples` (bytes)
+    used_mem: usize,
+    // `maxTapes` in pg. The max number of runs merged at once.
+    merge_order: usize,
+    // `currentRun` in pg. The number of runs written by `dumptuples`.
+    current_run: usize,
+    memtuples: Vec<SortTuple>,
+    // Index of the next tuple of `memtuples` to return
+    current: usize,
+    runs: Vec<Run>,
+    // The first unread tuple of each run during the merge
+    run_heads: Vec<Option<SortTuple>>,
+    // Indexes of `runs` ordered by `run_heads` as a binary heap
+    heap: Vec<usize>,
+    // The tuple returned lastly during the merge
+    last: Option<SortTuple>,
+    // Copy of the first tuple. Tuples read from runs are loaded into its
+    // clone, so that they have the same attributes.
+    template: Option<TupleTableSlot>,
+}
+
+impl<'a> Tuplesortstate<'a> {
+    // `tuplesort_begin_heap` in pg.
+    pub fn new(config: Rc<Config>, sort_keys: &'a Vec<SortBy>) -> Tuplesortstate<'a> {
+        let allowed_mem = config.work_mem() * 1024;
+
+        Tuplesortstate {
+            config: config,
+            sort_keys: sort_keys,
+            status: TupSortStatus::Initial,
+            allowed_mem: allowed_mem,
+            used_mem: 0,
+            merge_order: tuplesort_merge_order(allowed_mem),
+            current_run: 0,
+            memtuples: Vec::new(),
+            current: 0,
+            runs: Vec::new(),
+            run_heads: Vec::new(),
+            heap: Vec::new(),
+            last: None,
+            template: None,
+        }
+    }
+
+    // Whether tuples are written to temporary files.
+    pub fn is_external(&self) -> bool {
+        !self.runs.is_empty()
+    }
+
+    pub fn tuplesort_puttupleslot(&mut self, slot: &TupleTableSlot) -> Result<(), Error> {
+        if self.template.is_none() {
+            self.template = Some(slot.clone());
+        }
+
+        let tuple = SortTuple {
+            keys: self.eval_keys(slot)?,
+            slot: Box::new(slot.clone()),
+        };
+
+        // `GetMemoryChunkSpace` in pg. Data of datums are not counted.
+        self.used_mem += tuple.slot.len() as usize + mem::size_of::<SortTuple>() + mem::size_of::<TupleTableSlot>() +
+                         tuple.keys.len() * mem::size_of::<Datum>();
+        self.memtuples.push(tuple);
+
+        if self.used_mem > self.allowed_mem {
+            self.dumptuples()?;
+        }
+
+        Ok(())
+    }
+
+    // Sort tuples in memory, or start merging runs if tuples do not fit in
+    // memory.
+    pub fn tuplesort_performsort(&mut self) -> Result<(), Error> {
+        if self.runs.is_empty() {
+            let memtuples = mem::replace(&mut self.memtuples, Vec::new());
+            self.memtuples = sort_tuples(self.sort_keys, memtuples)?;
+            self.status = TupSortStatus::SortedInMem;
+            return Ok(());
+        }
+
+        if !self.memtuples.is_empty() {
+            self.dumptuples()?;
+        }
+
+        self.mergeruns()?;
+        self.status = TupSortStatus::FinalMerge;
+        Ok(())
+    }
+
+    // Returns the next tuple in the sorted order.
+    pub fn tuplesort_gettupleslot(&mut self) -> Result<Option<&TupleTableSlot>, Error> {
+        match self.status {
+            TupSortStatus::SortedInMem => {
+                if self.current < self.memtuples.len() {
+                    self.current += 1;
+                    return Ok(Some(self.memtuples[self.current - 1].slot.as_ref()));
+                }
+
+                Ok(None)
+            },
+            TupSortStatus::FinalMerge => {
+                self.last = self.mergeonestep()?;
+                Ok(self.last.as_ref().map(|t| t.slot.as_ref()))
+            },
+            TupSortStatus::Initial => panic!("tuplesort_performsort is not called"),
+        }
+    }
+
+    fn eval_keys(&self, slot: &TupleTableSlot) -> Result<Vec<Datum>, Error> {
+        let mut keys = Vec::new();
+
+        for sort_key in self.sort_keys.iter() {
+            keys.push(ExprEvaluator::new(slot, &sort_key.node).eval_value()?);
+        }
+
+        Ok(keys)
+    }
+
+    // `dumptuples` in pg. Sort tuples in memory and write them as a new run.
+    fn dumptuples(&mut self) -> Result<(), Error> {
+        let memtuples = mem::replace(&mut self.memtuples, Vec::new());
+        let memtuples = sort_tuples(self.sort_keys, memtuples)?;
+        let run = self.create_run()?;
+
+        {
+            let mut writer = BufWriter::new(File::create(&run.path)?);
+
+            for tuple in memtuples.iter() {
+                writetup(&mut writer, &tuple.slot)?;
+            }
+
+            writer.flush()?;
+        }
+
+        debug!("Wrote {} tuples to {:?} (run {})", memtuples.len(), run.path, self.current_run);
+        self.runs.push(run);
+        self.current_run += 1;
+        self.used_mem = 0;
+        Ok(())
+    }
+
+    // `BufFileCreateTemp` in pg.
+    fn create_run(&self) -> Result<Run, Error> {
+        let dir = self.config.temp_dir_path();
+        fs::create_dir_all(&dir)?;
+
+        let n = TEMP_FILE_COUNTER.fetch_add(1, atomic::Ordering::SeqCst);
+        let path = dir.join(format!("pgsql_tmp{}.{}", process::id(), n));
+
+        Ok(Run {
+            path: path,
+            reader: None,
+        })
+    }
+
+    // `mergeruns` in pg.
+    //
+    // Merge the first `merge_order` runs into a new run while there are too
+    // many runs, then start the final merge.
+    fn mergeruns(&mut self) -> Result<(), Error> {
+        while self.runs.len() > self.merge_order {
+            let rest = self.runs.split_off(self.merge_order);
+            let output = self.create_run()?;
+            self.beginmerge()?;
+
+            {
+                let mut writer = BufWriter::new(File::create(&output.path)?);
+
+                while let Some(tuple) = self.mergeonestep()? {
+                    writetup(&mut writer, &tuple.slot)?;
+                }
+
+                writer.flush()?;
+            }
+
+            debug!("Merged {} runs into {:?}", self.runs.len(), output.path);
+            // Merged runs are removed here.
+            self.runs = rest;
+            self.runs.push(output);
+            self.run_heads.clear();
+        }
+
+        self.beginmerge()
+    }
+
+    // `beginmerge` in pg. Read the first tuple of each run and build a heap.
+    fn beginmerge(&mut self) -> Result<(), Error> {
+        for i in 0..self.runs.len() {
+            self.runs[i].reader = Some(BufReader::new(File::open(&self.runs[i].path)?));
+            let tuple = self.readtup(i)?;
+            self.run_heads.push(tuple);
+        }
+
+        for i in 0..self.runs.len() {
+            if self.run_heads[i].is_some() {
+                self.heap.push(i);
+                let n = self.heap.len() - 1;
+                self.heap_siftup(n)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // `mergeonestep` in pg. Take the smallest tuple and replace it with the
+    // next tuple of the same run. Returns None if all runs are exhausted.
+    fn mergeonestep(&mut self) -> Result<Option<SortTuple>, Error> {
+        if self.heap.is_empty() {
+            return Ok(None);
+        }
+
+        let run = self.heap[0];
+        let tuple = self.run_heads[run].take();
+        self.run_heads[run] = self.readtup(run)?;
+
+        if self.run_heads[run].is_none() {
+            let last = self.heap.pop().unwrap();
+
+            if self.heap.is_empty() {
+                return Ok(tuple);
+            }
+
+            self.heap[0] = last;
+        }
+
+        self.heap_siftdown(0)?;
+        Ok(tuple)
+    }
+
+    // `readtup_heap` in pg. Returns None at the end of the run.
+    fn readtup(&mut self, run: usize) -> Result<Option<SortTuple>, Error> {
+        let reader = self.runs[run].reader.as_mut().unwrap();
+        let len = match reader.read_u32::<LittleEndian>() {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(Error::from(e)),
+        };
+
+        let mut data = vec![0; len as usize];
+        reader.read_exact(&mut data)?;
+        let mut tid = ItemPointerData::new();
+        let blkno = reader.read_u32::<LittleEndian>()?;
+        let offnum = reader.read_u16::<LittleEndian>()?;
+        item_pointer_set(&mut tid, blkno, offnum);
+
+        let mut slot = self.template.as_ref().unwrap().clone();
+        slot.load_data(data.as_ptr() as *const libc::c_void, len, tid);
+
+        Ok(Some(SortTuple {
+            keys: self.eval_keys(&slot)?,
+            slot: Box::new(slot),
+        }))
+    }
+
+    fn compare_runs(&self, a: usize, b: usize) -> Result<Ordering, Error> {
+        let tuple_a = self.run_heads[a].as_ref().unwrap();
+        let tuple_b = self.run_heads[b].as_ref().unwrap();
+        comparetup(self.sort_keys, &tuple_a.keys, &tuple_b.keys)
+    }
+
+    // `tuplesort_heap_insert` in pg.
+    fn heap_siftup(&mut self, mut i: usize) -> Result<(), Error> {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+
+            if self.compare_runs(self.heap[i], self.heap[parent])? != Ordering::Less {
+                break;
+            }
+
+            self.heap.swap(i, parent);
+            i = parent;
+        }
+
+        Ok(())
+    }
+
+    // `tuplesort_heap_replace_top` in pg.
+    fn heap_siftdown(&mut self, mut i: usize) -> Result<(), Error> {
+        loop {
+            let left = 2 * i + 1;
+            let right = left + 1;
+            let mut smallest = i;
+
+            if left < self.heap.len() && self.compare_runs(self.heap[left], self.heap[smallest])? == Ordering::Less {
+                smallest = left;
+            }
+
+            if right < self.heap.len() && self.compare_runs(self.heap[right], self.heap[smallest])? == Ordering::Less {
+                smallest = right;
+            }
+
+            if smallest == i {
+                return Ok(());
+            }
+
+            self.heap.swap(i, smallest);
+            i = smallest;
+        }
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        self.reader = None;
+
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove temporary file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+// `writetup_heap` in pg.
+fn writetup(w: &mut Write, slot: &TupleTableSlot) -> io::Result<()> {
+    let data = unsafe { slice::from_raw_parts(slot.data_ptr() as *const u8, slot.len() as usize) };
+    let tid = slot.tid();
+
+    w.write_u32::<LittleEndian>(slot.len())?;
+    w.write_all(data)?;
+    w.write_u32::<LittleEndian>(item_pointer_get_block_number(tid))?;
+    w.write_u16::<LittleEndian>(tid.item_pointer_get_offset_number())
+}
+
+// Sort tuples in memory. The sort is stable.
+fn sort_tuples(sort_keys: &Vec<SortBy>, mut tuples: Vec<SortTuple>) -> Result<Vec<SortTuple>, Error> {
+    let mut error = None;
+
+    tuples.sort_by(|a, b| {
+        match comparetup(sort_keys, &a.keys, &b.keys) {
+            Ok(o) => o,
+            Err(e) => {
+                error.get_or_insert(e);
+                Ordering::Equal
+            },
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(tuples),
+    }
+}
+
+// `comparetup_heap` in pg. Keys are compared in order until they differ.
+fn comparetup(sort_keys: &Vec<SortBy>, keys_a: &Vec<Datum>, keys_b: &Vec<Datum>) -> Result<Ordering, Error> {
+    for (sort_key, (a, b)) in sort_keys.iter().zip(keys_a.iter().zip(keys_b.iter())) {
+        match apply_sort_comparator(sort_key, a, b)? {
+            Ordering::Equal => {},
+            o => return Ok(o),
+        }
+    }
+
+    Ok(Ordering::Equal)
+}
+
+// `ApplySortComparator` in pg.
+fn apply_sort_comparator(sort_key: &SortBy, a: &Datum, b: &Datum) -> Result<Ordering, Error> {
+    let o = match (a.is_null(), b.is_null()) {
+        (true, true) => return Ok(Ordering::Equal),
+        (true, false) => return Ok(if sort_key.nulls_first() { Ordering::Less } else { Ordering::Greater }),
+        (false, true) => return Ok(if sort_key.nulls_first() { Ordering::Greater } else { Ordering::Less }),
+        (false, false) => a.compare(b)?.unwrap(),
+    };
+
+    match sort_key.dir {
+        SortByDir::Asc => Ok(o),
+        SortByDir::Desc => Ok(o.reverse()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use ast::{Expr, SortByNulls};
+    use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+    use tuple::KeyValueBuilder;
+
+    // Returns the number of runs written and sorted rows.
+    fn sort(config: Rc<Config>, n: usize) -> (usize, Vec<String>) {
+        let attrs = vec![
+            MiniAttributeRecord::new("id".to_string(), 10001, 10002, TypeLabel::Integer, 4),
+            MiniAttributeRecord::new("name".to_string(), 10001, 10002, TypeLabel::Text, 0),
+        ];
+        let sort_keys = vec![
+            SortBy::new(Box::new(Expr::ColumnRef("name".to_string())), SortByDir::Asc, SortByNulls::First),
+            SortBy::new(Box::new(Expr::ColumnRef("id".to_string())), SortByDir::Desc, SortByNulls::Default),
+        ];
+        let mut state = Tuplesortstate::new(config, &sort_keys);
+
+        for i in 0..n {
+            let id = ((i * 7919) % n).to_string();
+            let name = format!("name{}", i % 3);
+            let mut builder = KeyValueBuilder::new();
+            builder.add_pair("id", Some(&id));
+            builder.add_pair("name", if i % 5 == 0 { None } else { Some(&name) });
+
+            let mut slot = TupleTableSlot::new(attrs.clone());
+            slot.update_tuple(builder.build()).unwrap();
+            state.tuplesort_puttupleslot(&slot).unwrap();
+        }
+
+        state.tuplesort_performsort().unwrap();
+        assert!(state.runs.len() <= state.merge_order);
+
+        let mut rows = Vec::new();
+
+        while let Some(slot) = state.tuplesort_gettupleslot().unwrap() {
+            rows.push(format!("{} {}", slot.get_column(1).as_string(), slot.get_column(0).as_string()));
+        }
+
+        (state.current_run, rows)
+    }
+
+    fn expected(n: usize) -> Vec<String> {
+        let mut tuples: Vec<(Option<String>, usize)> = (0..n).map(|i| {
+            ((if i % 5 == 0 { None } else { Some(format!("name{}", i % 3)) }), (i * 7919) % n)
+        }).collect();
+        tuples.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        tuples.iter().map(|(name, id)| format!("{} {}", name.clone().unwrap_or("NULL".to_string()), id)).collect()
+    }
+
+    #[test]
+    fn test_sort_in_memory() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+
+        let (nruns, rows) = sort(config.clone(), 100);
+        assert_eq!(nruns, 0);
+        assert_eq!(rows, expected(100));
+        assert!(!config.temp_dir_path().exists());
+    }
+
+    #[test]
+    fn test_external_sort() {
+        let dir = tempdir().unwrap();
+        let mut config = Config::new(dir.path().to_str().unwrap().to_string());
+        config.set_work_mem(1);
+        let config = Rc::new(config);
+
+        let (nruns, rows) = sort(config.clone(), 1000);
+        assert!(nruns > 0);
+        assert_eq!(rows, expected(1000));
+        assert_eq!(fs::read_dir(config.temp_dir_path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_remove_pg_temp_files() {
+        let dir = tempdir().unwrap();
+        let config = Config::new(dir.path().to_str().unwrap().to_string());
+        remove_pg_temp_files(&config).unwrap();
+
+        fs::create_dir_all(config.temp_dir_path()).unwrap();
+        fs::write(config.temp_dir_path().join("pgsql_tmp1.0"), b"run").unwrap();
+        fs::write(config.temp_dir_path().join("other"), b"other").unwrap();
+        remove_pg_temp_files(&config).unwrap();
+
+        assert!(!config.temp_dir_path().join("pgsql_tmp1.0").exists());
+        assert!(config.temp_dir_path().join("other").exists());
+    }
+
+    #[test]
+    fn test_merge_order() {
+        assert_eq!(tuplesort_merge_order(1024), MIN_MERGE_ORDER);
+        assert_eq!(tuplesort_merge_order(4 * 1024 * 1024), 15);
+        assert_eq!(tuplesort_merge_order(1024 * 1024 * 1024), MAX_MERGE_ORDER);
+    }
+
+    #[test]
+    fn test_multi_pass_merge() {
+        let dir = tempdir().unwrap();
+        let mut config = Config::new(dir.path().to_str().unwrap().to_string());
+        config.set_work_mem(1);
+        let config = Rc::new(config);
+
+        // Runs are merged in more than two passes.
+        let (nruns, rows) = sort(config.clone(), 3000);
+        assert!(nruns > MIN_MERGE_ORDER * MIN_MERGE_ORDER);
+        assert_eq!(rows, expected(3000));
+        assert_eq!(fs::read_dir(config.temp_dir_path()).unwrap().count(), 0);
+    }
+}
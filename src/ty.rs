@@ -1,50 +1,191 @@
 // Column type
 
+use std::cmp::Ordering;
 use std::slice;
 use std::io::{Write};
 use byteorder::{WriteBytesExt, ReadBytesExt};
 
-use catalog::mini_attribute::{TypeLabel, ty_byte_len};
+use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+use error::Error;
+
+// Byte length of length header of variable length value.
+//
+// Variable length value is stored as below (`varlena` in pg).
+//
+// +----------------------+----------------------+
+// | length of data (u32) | data                 |
+// +----------------------+----------------------+
+pub const VARHDRSZ: usize = 4;
 
 pub trait TypeValue {
     fn write_bytes(&self, wrt: &mut Write) -> std::io::Result<()>;
     fn len(&self) -> u32;
     fn as_string(&self) -> String;
-    fn as_pointer(&self) -> *const libc::c_void;
+    fn datum(&self) -> Datum;
+
+    fn is_null(&self) -> bool {
+        false
+    }
 }
 
 // This function transforms data in database to TypeValue, so use methods of
 // byteorder crate.
 pub fn load_type_value(tl: &TypeLabel, src: *const libc::c_void) -> Box<TypeValue> {
-    let len = ty_byte_len(tl);
-
     match tl {
         TypeLabel::Integer => {
             let ptr: *const u8 = src as *const u8;
-            let mut s = unsafe { slice::from_raw_parts(ptr, len as usize) };
+            let mut s = unsafe { slice::from_raw_parts(ptr, 4) };
             let i = s.read_i32::<byteorder::LittleEndian>().unwrap();
             Box::new(Integer { elem: i })
+        },
+        TypeLabel::Text | TypeLabel::Varchar => {
+            let ptr: *const u8 = src as *const u8;
+            let len = load_varlena_len(src);
+            let s = unsafe { slice::from_raw_parts(ptr.add(VARHDRSZ), len) };
+            Box::new(Text { elem: String::from_utf8_lossy(s).into_owned() })
         }
     }
 }
 
+// Read length header of variable length value. Returned length
+// does not include VARHDRSZ.
+pub fn load_varlena_len(src: *const libc::c_void) -> usize {
+    let ptr: *const u8 = src as *const u8;
+    let mut s = unsafe { slice::from_raw_parts(ptr, VARHDRSZ) };
+    s.read_u32::<byteorder::LittleEndian>().unwrap() as usize
+}
+
 // This function transforms input from user, mainly SQL, to
-// TypeValue, so use `parse` method. We can call `unwrap` because
-// `row` will be passed parser syntax check.
-pub fn build_type_value(tl: &TypeLabel, row: &str) -> Box<TypeValue> {
-    match tl {
+// TypeValue, so use `parse` method.
+pub fn build_type_value(attr: &MiniAttributeRecord, row: &str) -> Result<Box<TypeValue>, Error> {
+    match attr.ty {
         TypeLabel::Integer => {
-            let elem = row.parse::<i32>().unwrap();
-            Box::new(Integer { elem: elem })
+            match row.parse::<i32>() {
+                Ok(elem) => Ok(Box::new(Integer { elem: elem })),
+                Err(_) => Err(Error::InvalidTextRepresentation(format!("Invalid input for integer ({}). column: {}", row, attr.name))),
+            }
+        },
+        TypeLabel::Text => {
+            Ok(Box::new(Text { elem: row.to_string() }))
+        },
+        TypeLabel::Varchar => {
+            if attr.len != 0 && row.chars().count() > attr.len {
+                return Err(Error::StringDataRightTruncation(format!("Value too long for type varchar({}). column: {}", attr.len, attr.name)));
+            }
+
+            Ok(Box::new(Text { elem: row.to_string() }))
         }
     }
 }
 
+// Assign the datum to the column, e.g. by SET clause of UPDATE. Integer is
+// converted to text and text is converted to integer like assignment casts
+// in pg.
+pub fn build_type_value_from_datum(attr: &MiniAttributeRecord, datum: Datum) -> Result<Box<TypeValue>, Error> {
+    match (&attr.ty, datum) {
+        (_, Datum::Null) => Ok(Box::new(Null)),
+        (TypeLabel::Integer, Datum::Integer(i)) => Ok(Box::new(Integer { elem: i })),
+        (_, Datum::Integer(i)) => build_type_value(attr, &i.to_string()),
+        (_, Datum::Text(s)) => build_type_value(attr, &s),
+        (_, Datum::Bool(b)) => Err(Error::DatatypeMismatch(format!("Boolean value ({:?}) can not be assigned to {} column.", b, attr.name))),
+    }
+}
+
+// A value used in expressions and sort (`Datum` in pg).
+//
+// Unlike pg, a datum knows its type. `Text` is also used for string literals,
+// which are converted to the type of the other operand like literals of
+// unknown type in pg.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Bool(bool),
+    Integer(i32),
+    Text(String),
+    Null,
+}
+
+impl Datum {
+    pub fn is_null(&self) -> bool {
+        match self {
+            Datum::Null => true,
+            _ => false,
+        }
+    }
+
+    // `btint4cmp`, `bttextcmp` and `btboolcmp` in pg. Comparison with null is
+    // null (None).
+    //
+    // Integers are compared numerically and texts are compared byte-wise
+    // (like "C" collation in pg).
+    pub fn compare(&self, other: &Datum) -> Result<Option<Ordering>, Error> {
+        match (self, other) {
+            (Datum::Null, _) | (_, Datum::Null) => Ok(None),
+            (Datum::Bool(b1), Datum::Bool(b2)) => Ok(Some(b1.cmp(b2))),
+            (Datum::Integer(i1), Datum::Integer(i2)) => Ok(Some(i1.cmp(i2))),
+            (Datum::Text(s1), Datum::Text(s2)) => Ok(Some(s1.cmp(s2))),
+            (Datum::Integer(i1), Datum::Text(s2)) => Ok(Some(i1.cmp(&int4in(s2)?))),
+            (Datum::Text(s1), Datum::Integer(i2)) => Ok(Some(int4in(s1)?.cmp(i2))),
+            (Datum::Bool(b1), Datum::Text(s2)) => Ok(Some(b1.cmp(&boolin(s2)?))),
+            (Datum::Text(s1), Datum::Bool(b2)) => Ok(Some(boolin(s1)?.cmp(b2))),
+            (Datum::Bool(_), Datum::Integer(_)) | (Datum::Integer(_), Datum::Bool(_)) => {
+                Err(Error::UndefinedFunction(format!("Operator does not exist: {} and {} can not be compared.", self.type_name(), other.type_name())))
+            },
+        }
+    }
+
+    // `int4eq`, `texteq` and `booleq` in pg.
+    pub fn equal(&self, other: &Datum) -> Result<Option<bool>, Error> {
+        Ok(self.compare(other)?.map(|o| o == Ordering::Equal))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Datum::Bool(_) => "boolean",
+            Datum::Integer(_) => "integer",
+            Datum::Text(_) => "text",
+            Datum::Null => "unknown",
+        }
+    }
+
+    // Convert to a value of the output column.
+    pub fn into_type_value(self) -> Box<TypeValue> {
+        match self {
+            Datum::Integer(i) => Box::new(Integer { elem: i }),
+            Datum::Text(s) => Box::new(Text { elem: s }),
+            Datum::Null => Box::new(Null),
+            Datum::Bool(b) => panic!("Datum::Bool can not be an output column. ({:?})", b),
+        }
+    }
+}
+
+// `int4in` in pg.
+pub fn int4in(s: &str) -> Result<i32, Error> {
+    s.trim().parse::<i32>().map_err(|_| Error::InvalidTextRepresentation(format!("Invalid input syntax for type integer: \"{}\"", s)))
+}
+
+// `boolin` in pg.
+pub fn boolin(s: &str) -> Result<bool, Error> {
+    match s.trim().to_lowercase().as_str() {
+        "t" | "true" | "y" | "yes" | "on" | "1" => Ok(true),
+        "f" | "false" | "n" | "no" | "off" | "0" => Ok(false),
+        _ => Err(Error::InvalidTextRepresentation(format!("Invalid input syntax for type boolean: \"{}\"", s))),
+    }
+}
+
 // Signed 4 bytes integer
 pub struct Integer {
     pub elem: i32,
 }
 
+// Variable length string, used for text and varchar.
+pub struct Text {
+    pub elem: String,
+}
+
+// Null value of any type. Null value does not have data in a tuple,
+// it is represented by null bitmap of the tuple.
+pub struct Null;
+
 impl TypeValue for Integer {
     fn write_bytes(&self, wrt: &mut Write) -> std::io::Result<()> {
         wrt.write_i32::<byteorder::LittleEndian>(self.elem)
@@ -58,8 +199,93 @@ impl TypeValue for Integer {
         self.elem.to_string()
     }
 
-    fn as_pointer(&self) -> *const libc::c_void {
-        let p: *const i32 = &self.elem;
-        p as *const libc::c_void
+    fn datum(&self) -> Datum {
+        Datum::Integer(self.elem)
+    }
+}
+
+impl TypeValue for Text {
+    fn write_bytes(&self, wrt: &mut Write) -> std::io::Result<()> {
+        wrt.write_u32::<byteorder::LittleEndian>(self.elem.len() as u32)?;
+        wrt.write_all(self.elem.as_bytes())
+    }
+
+    fn len(&self) -> u32 {
+        (VARHDRSZ + self.elem.len()) as u32
+    }
+
+    fn as_string(&self) -> String {
+        self.elem.clone()
+    }
+
+    fn datum(&self) -> Datum {
+        Datum::Text(self.elem.clone())
+    }
+}
+
+impl TypeValue for Null {
+    fn write_bytes(&self, _wrt: &mut Write) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn len(&self) -> u32 {
+        0
+    }
+
+    fn as_string(&self) -> String {
+        "NULL".to_string()
+    }
+
+    fn datum(&self) -> Datum {
+        Datum::Null
+    }
+
+    fn is_null(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_write_and_load() {
+        let text = Text { elem: "abc".to_string() };
+        let mut v = Vec::new();
+        text.write_bytes(&mut v).unwrap();
+
+        assert_eq!(text.len(), 7);
+        assert_eq!(v, vec![3, 0, 0, 0, 97, 98, 99]);
+        assert_eq!(load_varlena_len(v.as_ptr() as *const libc::c_void), 3);
+        assert_eq!(load_type_value(&TypeLabel::Text, v.as_ptr() as *const libc::c_void).as_string(), "abc".to_string());
+    }
+
+    #[test]
+    fn test_datum_compare() {
+        assert_eq!(Datum::Integer(9).compare(&Datum::Integer(10)).unwrap(), Some(Ordering::Less));
+        assert_eq!(Datum::Text("9".to_string()).compare(&Datum::Text("10".to_string())).unwrap(), Some(Ordering::Greater));
+        assert_eq!(Datum::Integer(10).compare(&Datum::Text("9".to_string())).unwrap(), Some(Ordering::Greater));
+        assert_eq!(Datum::Bool(true).equal(&Datum::Text("t".to_string())).unwrap(), Some(true));
+        assert_eq!(Datum::Integer(1).equal(&Datum::Null).unwrap(), None);
+        assert_eq!(Datum::Integer(1).compare(&Datum::Text("a".to_string())).err().unwrap().code(), "22P02");
+        assert_eq!(Datum::Integer(1).compare(&Datum::Bool(true)).err().unwrap().code(), "42883");
+    }
+
+    #[test]
+    fn test_build_type_value() {
+        let int = MiniAttributeRecord::new("id".to_string(), 10001, 10002, TypeLabel::Integer, 4);
+        let varchar = MiniAttributeRecord::new("name".to_string(), 10001, 10002, TypeLabel::Varchar, 3);
+
+        assert_eq!(build_type_value(&int, "12").unwrap().as_string(), "12".to_string());
+        assert!(build_type_value(&int, "abc").is_err());
+        assert_eq!(build_type_value(&varchar, "abc").unwrap().as_string(), "abc".to_string());
+        assert!(build_type_value(&varchar, "abcd").is_err());
+
+        assert_eq!(build_type_value_from_datum(&int, Datum::Text("12".to_string())).unwrap().as_string(), "12".to_string());
+        assert_eq!(build_type_value_from_datum(&varchar, Datum::Integer(12)).unwrap().as_string(), "12".to_string());
+        assert!(build_type_value_from_datum(&varchar, Datum::Integer(1234)).is_err());
+        assert!(build_type_value_from_datum(&int, Datum::Null).unwrap().is_null());
+        assert!(build_type_value_from_datum(&int, Datum::Bool(true)).is_err());
     }
 }
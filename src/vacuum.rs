@@ -0,0 +1,159 @@
+// Manual vacuum (`vacuum.c` and `vacuumlazy.c` in pg).
+//
+// VACUUM removes tuples which are dead for all transactions and makes their
+// space and line pointers reusable:
+//
+// (1) Scan the table, mark line pointers of dead tuples as LP_DEAD and
+//     compact each page.
+// (2) Delete index tuples which point to the dead tuples.
+// (3) Mark the LP_DEAD line pointers as LP_UNUSED, then `Page::add_entry`
+//     reuses them for new tuples.
+//
+// Line pointers can not be reused before (2), otherwise index tuples would
+// point to other tuples. Changed pages are logged as full page images, and
+// free space of all pages is recorded in the free space map.
+
+use std::sync::RwLock;
+
+use buffer_manager::BufferManager;
+use catalog::catalog_manager::CatalogManager;
+use error::Error;
+use executor::exec_indexing::exec_open_indices;
+use executor::tuptable::QueryResult;
+use off::OffsetNumber;
+use storage_manager::{RelationData, StorageError};
+use tqual::{HTSVResult, Snapshot, heap_tuple_satisfies_vacuum};
+use transam::{TransactionId, TransactionManager};
+use tuple::{HeapTupleData, ItemPointerData, item_pointer_set, item_pointer_get_block_number};
+
+pub struct VacuumCommand {}
+
+impl VacuumCommand {
+    pub fn new() -> VacuumCommand {
+        VacuumCommand {}
+    }
+
+    // `vacuum` and `lazy_vacuum_rel` in pg.
+    //
+    // `snapshot` is a new snapshot taken outside of a transaction block.
+    pub fn execute(&self, dbname: &str, table_name: &str, cmgr: &CatalogManager, snapshot: &Snapshot, bm: &RwLock<BufferManager>) -> Result<QueryResult, Error> {
+        let (db_oid, table_oid) = cmgr.get_relation_oids(dbname, table_name)?;
+        let relation = RelationData::new(db_oid, table_oid);
+        let mut bufmrg = bm.write().unwrap();
+        let indices = exec_open_indices(cmgr, db_oid, table_oid);
+
+        // `GetOldestXmin` in pg. All transactions before xmin of a new
+        // snapshot are finished. pg also considers snapshots taken by other
+        // backends, but we do not track them.
+        let oldest_xmin = snapshot.xmin;
+
+        let dead_tuples = lazy_scan_heap(&mut bufmrg, &relation, oldest_xmin, snapshot.transam())?;
+
+        if !dead_tuples.is_empty() {
+            for index in indices.iter() {
+                let ndeleted = index.btree().bulk_delete(&mut bufmrg, &dead_tuples)?;
+                debug!("Deleted {} index records from {}", ndeleted, index.name);
+            }
+
+            lazy_vacuum_heap(&mut bufmrg, &relation, &dead_tuples)?;
+        }
+
+        Ok(QueryResult::command("VACUUM", dead_tuples.len() as u64))
+    }
+}
+
+// `lazy_scan_heap` in pg.
+//
+// Mark dead tuples as LP_DEAD and returns tids of them, which are sorted.
+// LP_DEAD line pointers left by an interrupted VACUUM are also returned.
+fn lazy_scan_heap(bufmrg: &mut BufferManager, relation: &RelationData, oldest_xmin: TransactionId, transam: &TransactionManager) -> Result<Vec<ItemPointerData>, Error> {
+    let mut dead_tuples = Vec::new();
+    let nblocks = bufmrg.relation_get_number_of_blocks(relation)?;
+
+    for blkno in 0..nblocks {
+        let buffer = bufmrg.read_buffer(relation, blkno)?;
+        let mut dead_offsets: Vec<OffsetNumber> = Vec::new();
+        let mut ndead_tuples = 0;
+
+        {
+            let page = bufmrg.get_page(buffer);
+
+            for lineoff in 0..page.page_get_max_offset_number() {
+                let item = page.get_item(lineoff);
+
+                if item.is_dead() {
+                    dead_offsets.push(lineoff);
+                    continue;
+                }
+
+                if !item.is_normal() {
+                    continue;
+                }
+
+                let mut tuple = HeapTupleData::new_with_full_len(item.lp_len() as u32);
+                tuple.load(page.get_entry_pointer(lineoff).unwrap(), item.lp_len() as u32, ItemPointerData::new());
+
+                if heap_tuple_satisfies_vacuum(&tuple.t_data, oldest_xmin, transam)? == HTSVResult::Dead {
+                    dead_offsets.push(lineoff);
+                    ndead_tuples += 1;
+                }
+            }
+        }
+
+        // `heap_page_prune` in pg.
+        if ndead_tuples > 0 {
+            {
+                let page = bufmrg.get_page_mut(buffer);
+
+                for lineoff in dead_offsets.iter() {
+                    page.item_id_set_dead(*lineoff);
+                }
+
+                page.page_repair_fragmentation();
+            }
+
+            bufmrg.log_newpage(buffer)?;
+        }
+
+        bufmrg.record_free_space(buffer);
+        bufmrg.release_buffer(buffer);
+
+        for lineoff in dead_offsets.into_iter() {
+            let mut tid = ItemPointerData::new();
+            item_pointer_set(&mut tid, blkno, lineoff);
+            dead_tuples.push(tid);
+        }
+    }
+
+    Ok(dead_tuples)
+}
+
+// `lazy_vacuum_heap` and `lazy_vacuum_page` in pg.
+//
+// Mark LP_DEAD line pointers of `dead_tuples` as LP_UNUSED. This should be
+// called after index tuples pointing to them are deleted.
+fn lazy_vacuum_heap(bufmrg: &mut BufferManager, relation: &RelationData, dead_tuples: &Vec<ItemPointerData>) -> Result<(), StorageError> {
+    let mut i = 0;
+
+    while i < dead_tuples.len() {
+        let blkno = item_pointer_get_block_number(&dead_tuples[i]);
+        let buffer = bufmrg.read_buffer(relation, blkno)?;
+
+        {
+            let page = bufmrg.get_page_mut(buffer);
+
+            while i < dead_tuples.len() && item_pointer_get_block_number(&dead_tuples[i]) == blkno {
+                page.item_id_set_unused(dead_tuples[i].item_pointer_get_offset_number());
+                i += 1;
+            }
+
+            page.page_repair_fragmentation();
+        }
+
+        bufmrg.log_newpage(buffer)?;
+        bufmrg.record_free_space(buffer);
+        bufmrg.release_buffer(buffer);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+// Transaction blocks (`xact.c` in pg).
+//
+// A statement runs in its own transaction unless it is in a transaction
+// block started by BEGIN. Transaction id is assigned when the transaction
+// changes a table for the first time, so read only transactions do not
+// write the commit log.
+
+use std::rc::Rc;
+use std::sync::RwLock;
+
+use buffer_manager::BufferManager;
+use config::Config;
+use error::Error;
+use tqual::{Snapshot, get_snapshot_data};
+use transam::{TransactionId, TransactionManager, InvalidTransactionId};
+
+// `TBlockState` in pg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TBlockState {
+    // not in a transaction block
+    Default,
+    // live transaction block
+    InProgress,
+    // failed transaction block, waiting for ROLLBACK
+    Abort,
+}
+
+pub struct TransactionState {
+    transam: TransactionManager,
+    // InvalidTransactionId if not assigned
+    xid: TransactionId,
+    block_state: TBlockState,
+}
+
+// The transaction which is not committed when the session ends is aborted.
+impl Drop for TransactionState {
+    fn drop(&mut self) {
+        if let Err(msg) = self.abort_transaction() {
+            warn!("Failed to abort transaction. '{}'", msg);
+        }
+    }
+}
+
+impl TransactionState {
+    pub fn new(config: Rc<Config>) -> TransactionState {
+        TransactionState {
+            transam: TransactionManager::new(config),
+            xid: InvalidTransactionId,
+            block_state: TBlockState::Default,
+        }
+    }
+
+    pub fn block_state(&self) -> TBlockState {
+        self.block_state
+    }
+
+    // `IsTransactionBlock` in pg.
+    pub fn is_transaction_block(&self) -> bool {
+        self.block_state != TBlockState::Default
+    }
+
+    // `GetCurrentTransactionId` in pg.
+    //
+    // Assign a new transaction id if the transaction does not have it yet.
+    pub fn get_current_transaction_id(&mut self) -> Result<TransactionId, Error> {
+        if self.xid == InvalidTransactionId {
+            self.xid = self.transam.get_new_transaction_id()?;
+            debug!("Transaction id {} is assigned", self.xid);
+        }
+
+        Ok(self.xid)
+    }
+
+    // `GetCurrentTransactionIdIfAny` in pg.
+    pub fn get_current_transaction_id_if_any(&self) -> TransactionId {
+        self.xid
+    }
+
+    // `GetTransactionSnapshot` in pg.
+    //
+    // New snapshot is taken for each statement, so a statement in a
+    // transaction block sees changes committed before it starts
+    // (READ COMMITTED in pg).
+    pub fn get_transaction_snapshot<'a>(&'a self) -> Result<Snapshot<'a>, Error> {
+        Ok(get_snapshot_data(self.xid, &self.transam)?)
+    }
+
+    // `StartTransactionCommand` in pg.
+    pub fn start_transaction_command(&self) -> Result<(), Error> {
+        if self.block_state == TBlockState::Abort {
+            return Err(Error::InFailedSqlTransaction("current transaction is aborted, commands ignored until end of transaction block".to_string()));
+        }
+
+        Ok(())
+    }
+
+    // `CommitTransactionCommand` in pg.
+    //
+    // Commit the transaction if the statement is not in a transaction block.
+    pub fn commit_transaction_command(&mut self, bufmrg: &RwLock<BufferManager>) -> Result<(), Error> {
+        match self.block_state {
+            TBlockState::Default => self.commit_transaction(bufmrg),
+            _ => Ok(()),
+        }
+    }
+
+    // `AbortCurrentTransaction` in pg.
+    //
+    // Called when a statement fails. The transaction block is kept until
+    // ROLLBACK (or COMMIT) is issued.
+    pub fn abort_current_transaction(&mut self) -> Result<(), Error> {
+        match self.block_state {
+            TBlockState::Default => self.abort_transaction(),
+            _ => {
+                self.block_state = TBlockState::Abort;
+                Ok(())
+            },
+        }
+    }
+
+    // `BeginTransactionBlock` in pg.
+    pub fn begin_transaction_block(&mut self) -> Result<(), Error> {
+        match self.block_state {
+            TBlockState::Default => {
+                self.block_state = TBlockState::InProgress;
+                Ok(())
+            },
+            TBlockState::InProgress => {
+                warn!("there is already a transaction in progress");
+                Ok(())
+            },
+            TBlockState::Abort => self.start_transaction_command(),
+        }
+    }
+
+    // `EndTransactionBlock` in pg.
+    //
+    // COMMIT of a failed transaction block rolls back the transaction.
+    pub fn end_transaction_block(&mut self, bufmrg: &RwLock<BufferManager>) -> Result<(), Error> {
+        match self.block_state {
+            TBlockState::Default => {
+                warn!("there is no transaction in progress");
+                Ok(())
+            },
+            TBlockState::InProgress => {
+                self.block_state = TBlockState::Default;
+                self.commit_transaction(bufmrg)
+            },
+            TBlockState::Abort => {
+                warn!("current transaction is aborted, rolled back instead of commit");
+                self.block_state = TBlockState::Default;
+                self.abort_transaction()
+            },
+        }
+    }
+
+    // `UserAbortTransactionBlock` in pg.
+    pub fn user_abort_transaction_block(&mut self) -> Result<(), Error> {
+        if self.block_state == TBlockState::Default {
+            warn!("there is no transaction in progress");
+        }
+
+        self.block_state = TBlockState::Default;
+        self.abort_transaction()
+    }
+
+    // `PreventInTransactionBlock` in pg.
+    //
+    // Some statements (e.g. DDL) can not be rolled back.
+    pub fn prevent_in_transaction_block(&self, stmt_type: &str) -> Result<(), Error> {
+        if self.is_transaction_block() {
+            return Err(Error::ActiveSqlTransaction(format!("{} cannot run inside a transaction block", stmt_type)));
+        }
+
+        Ok(())
+    }
+
+    // `CommitTransaction` in pg.
+    //
+    // WAL records of the transaction are flushed before the commit log is
+    // updated (`RecordTransactionCommit` in pg). Otherwise the transaction
+    // may be committed in the commit log after a crash but its changes may
+    // be lost. We flush all inserted WAL instead of tracking the end of the
+    // last record of the transaction (`XactLastRecEnd` in pg).
+    //
+    // If the commit fails, the transaction is aborted. Otherwise the next
+    // statement would reuse the transaction id and commit its changes.
+    fn commit_transaction(&mut self, bufmrg: &RwLock<BufferManager>) -> Result<(), Error> {
+        if self.xid == InvalidTransactionId {
+            return Ok(());
+        }
+
+        let xid = self.xid;
+        self.xid = InvalidTransactionId;
+
+        let result = {
+            let mut bufmrg = bufmrg.write().unwrap();
+            let lsn = bufmrg.xlog_insert_ptr();
+            bufmrg.xlog_flush(lsn).map_err(Error::from)
+        }.and_then(|_| Ok(self.transam.transaction_id_commit(xid)?));
+
+        match result {
+            Ok(()) => {
+                debug!("Transaction {} is committed", xid);
+                Ok(())
+            },
+            Err(e) => {
+                if let Err(msg) = self.transam.transaction_id_abort(xid) {
+                    warn!("Failed to abort transaction {}. '{}'", xid, msg);
+                }
+                Err(e)
+            },
+        }
+    }
+
+    // `AbortTransaction` in pg.
+    fn abort_transaction(&mut self) -> Result<(), Error> {
+        if self.xid != InvalidTransactionId {
+            let xid = self.xid;
+            self.xid = InvalidTransactionId;
+            self.transam.transaction_id_abort(xid)?;
+            debug!("Transaction {} is aborted", xid);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+    use catalog::mini_attribute::{MiniAttributeRecord, TypeLabel};
+    use config::N_BUFFERS;
+    use storage_manager::RelationData;
+    use transam::create_clog_file;
+    use tuple::TupleTableSlot;
+
+    fn setup() -> (tempfile::TempDir, Rc<Config>) {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+        fs::create_dir_all(config.table_dir_path(10000, 10001)).unwrap();
+        create_clog_file(&config).unwrap();
+        (dir, config)
+    }
+
+    #[test]
+    fn test_transaction_block() {
+        let (_dir, config) = setup();
+        let transam = TransactionManager::new(config.clone());
+        let bufmrg = RwLock::new(BufferManager::new(N_BUFFERS, config.clone()).unwrap());
+        let mut xact = TransactionState::new(config.clone());
+
+        // Single statement is committed at the end of it.
+        let xid1 = xact.get_current_transaction_id().unwrap();
+        xact.commit_transaction_command(&bufmrg).unwrap();
+        assert_eq!(xact.get_current_transaction_id_if_any(), InvalidTransactionId);
+
+        // Statements in a block share the transaction id.
+        xact.begin_transaction_block().unwrap();
+        let xid2 = xact.get_current_transaction_id().unwrap();
+        xact.commit_transaction_command(&bufmrg).unwrap();
+        assert_eq!(xact.get_current_transaction_id().unwrap(), xid2);
+        xact.commit_transaction_command(&bufmrg).unwrap();
+        assert!(!transam.transaction_id_did_commit(xid2).unwrap());
+        xact.end_transaction_block(&bufmrg).unwrap();
+
+        // Failed block rejects statements until ROLLBACK.
+        xact.begin_transaction_block().unwrap();
+        let xid3 = xact.get_current_transaction_id().unwrap();
+        xact.abort_current_transaction().unwrap();
+        assert_eq!(xact.block_state(), TBlockState::Abort);
+        assert!(xact.start_transaction_command().is_err());
+        xact.user_abort_transaction_block().unwrap();
+        assert!(xact.start_transaction_command().is_ok());
+
+        // Open transaction is aborted at the end of the session.
+        xact.begin_transaction_block().unwrap();
+        assert!(xact.prevent_in_transaction_block("CREATE TABLE").is_err());
+        let xid4 = xact.get_current_transaction_id().unwrap();
+        drop(xact);
+
+        assert!(transam.transaction_id_did_commit(xid1).unwrap());
+        assert!(transam.transaction_id_did_commit(xid2).unwrap());
+        assert!(transam.transaction_id_did_abort(xid3).unwrap());
+        assert!(transam.transaction_id_did_abort(xid4).unwrap());
+    }
+
+    #[test]
+    fn test_commit_flushes_wal() {
+        let (_dir, config) = setup();
+        let transam = TransactionManager::new(config.clone());
+        let bufmrg = RwLock::new(BufferManager::new(N_BUFFERS, config.clone()).unwrap());
+        let mut xact = TransactionState::new(config.clone());
+        let relation = RelationData::new(10000, 10001);
+        let attrs = vec![MiniAttributeRecord::new("id".to_string(), 10000, 10001, TypeLabel::Integer, 4)];
+        let slot = TupleTableSlot::new(attrs);
+
+        let xid = xact.get_current_transaction_id().unwrap();
+        bufmrg.write().unwrap().heap_insert(&relation, &slot, xid).unwrap();
+        let lsn = bufmrg.read().unwrap().xlog_insert_ptr();
+        assert!(bufmrg.read().unwrap().xlog_flushed_ptr() < lsn);
+
+        xact.commit_transaction_command(&bufmrg).unwrap();
+        assert!(bufmrg.read().unwrap().xlog_flushed_ptr() >= lsn);
+        assert!(transam.transaction_id_did_commit(xid).unwrap());
+    }
+}
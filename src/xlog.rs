@@ -0,0 +1,408 @@
+// Write-ahead log (`xlog.c` in pg).
+//
+// WAL is a sequence of records stored in segment files under
+// "root directory / wal /". Location in WAL is represented by XLogRecPtr
+// (LSN), which is the byte position from the beginning of the first segment.
+// A record never spans segments. If there is no enough space in the current
+// segment, the record is written at the beginning of the next segment.
+//
+// Record:
+//
+// +--------------------+-----------+-----------+-----------------------+
+// | total length (u32) | crc (u32) | info (u8) | payload               |
+// +--------------------+-----------+-----------+-----------------------+
+//
+// Payload:
+//
+// +---------------+------------------+-----------------+------------------+------+
+// | db oid (u32)  | table oid (u32)  | block num (u32) | offset num (u16) | data |
+// +---------------+------------------+-----------------+------------------+------+
+//
+// The control file has the redo pointer of the last checkpoint, and recovery
+// replays records after it. Each page has the LSN of the last record which
+// changed the page, so records older than the page are skipped.
+
+#![allow(non_upper_case_globals)]
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use buffer_manager::{BlockNumber, RelFileNode};
+use config::Config;
+use off::OffsetNumber;
+use storage_manager::StorageError;
+
+// `XLogRecPtr` in pg.
+pub type XLogRecPtr = u64;
+pub const InvalidXLogRecPtr: XLogRecPtr = 0;
+
+// `wal_segment_size` in pg.
+pub const WAL_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+// Kinds of records (`xl_info` in pg).
+pub const XLOG_HEAP_INSERT: u8 = 0x00;
+pub const XLOG_HEAP_DELETE: u8 = 0x10;
+pub const XLOG_BTREE_INSERT: u8 = 0x20;
+// Full page image (`XLOG_FPI` in pg).
+pub const XLOG_FPI: u8 = 0x30;
+
+// `SizeOfXLogRecord` in pg.
+const SIZE_OF_XLOG_RECORD: usize = 9;
+const SIZE_OF_XLOG_PAYLOAD_HEADER: usize = 14;
+
+// `XLogRecord` in pg. Each record changes one block of a relation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XLogRecord {
+    pub xl_info: u8,
+    pub rnode: RelFileNode,
+    pub block_num: BlockNumber,
+    pub offnum: OffsetNumber,
+    // tuple, index tuple or page image
+    pub data: Vec<u8>,
+}
+
+pub struct XLogManager {
+    config: Rc<Config>,
+    // Redo pointer of the last checkpoint
+    redo_ptr: XLogRecPtr,
+    // End of the last inserted record
+    insert_ptr: XLogRecPtr,
+    // WAL before this pointer has been synced
+    flushed_ptr: XLogRecPtr,
+    // Segment files written after the last flush
+    files: HashMap<u64, File>,
+}
+
+// CRC-32 (IEEE) of `data`. `COMP_CRC32C` in pg.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+
+    for b in data.iter() {
+        crc ^= *b as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+impl XLogRecord {
+    pub fn new(xl_info: u8, rnode: RelFileNode, block_num: BlockNumber, offnum: OffsetNumber, data: Vec<u8>) -> XLogRecord {
+        XLogRecord {
+            xl_info: xl_info,
+            rnode: rnode,
+            block_num: block_num,
+            offnum: offnum,
+            data: data,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let len = SIZE_OF_XLOG_RECORD + SIZE_OF_XLOG_PAYLOAD_HEADER + self.data.len();
+        let mut buf = vec![0; len];
+
+        LittleEndian::write_u32(&mut buf[0..4], len as u32);
+        buf[8] = self.xl_info;
+        LittleEndian::write_u32(&mut buf[9..13], self.rnode.db_oid);
+        LittleEndian::write_u32(&mut buf[13..17], self.rnode.table_oid);
+        LittleEndian::write_u32(&mut buf[17..21], self.block_num);
+        LittleEndian::write_u16(&mut buf[21..23], self.offnum);
+        buf[23..].copy_from_slice(&self.data);
+
+        let crc = crc32(&buf[8..]);
+        LittleEndian::write_u32(&mut buf[4..8], crc);
+        buf
+    }
+
+    // Returns None if the bytes are not a valid record, e.g. torn write.
+    fn from_bytes(buf: &[u8]) -> Option<XLogRecord> {
+        if buf.len() < SIZE_OF_XLOG_RECORD + SIZE_OF_XLOG_PAYLOAD_HEADER {
+            return None;
+        }
+
+        if crc32(&buf[8..]) != LittleEndian::read_u32(&buf[4..8]) {
+            return None;
+        }
+
+        let rnode = RelFileNode {
+            db_oid: LittleEndian::read_u32(&buf[9..13]),
+            table_oid: LittleEndian::read_u32(&buf[13..17]),
+        };
+
+        Some(XLogRecord::new(
+            buf[8],
+            rnode,
+            LittleEndian::read_u32(&buf[17..21]),
+            LittleEndian::read_u16(&buf[21..23]),
+            buf[23..].to_vec()
+        ))
+    }
+}
+
+pub fn create_control_file(config: &Config) -> std::io::Result<()> {
+    write_control_file(config, InvalidXLogRecPtr)
+}
+
+fn write_control_file(config: &Config, redo_ptr: XLogRecPtr) -> std::io::Result<()> {
+    let mut buf = vec![0; 8];
+    LittleEndian::write_u64(&mut buf, redo_ptr);
+
+    // Write to temporary file and rename it not to break the control file.
+    let tmp_path = config.control_file_path().with_extension("tmp");
+    let mut f = File::create(&tmp_path)?;
+    f.write_all(&buf)?;
+    f.sync_all()?;
+    fs::rename(tmp_path, config.control_file_path())
+}
+
+fn read_control_file(config: &Config) -> Result<XLogRecPtr, StorageError> {
+    let path = config.control_file_path();
+    let mut buf = Vec::new();
+
+    match File::open(&path) {
+        Ok(mut f) => {
+            f.read_to_end(&mut buf).map_err(|e| StorageError::Io(format!("read file {:?}", path), e))?;
+            Ok(LittleEndian::read_u64(&buf))
+        },
+        // Database cluster created before WAL support
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(InvalidXLogRecPtr),
+        Err(e) => Err(StorageError::Io(format!("open file {:?}", path), e)),
+    }
+}
+
+impl XLogManager {
+    // Open WAL and find the end of it.
+    pub fn open(config: Rc<Config>) -> Result<XLogManager, StorageError> {
+        fs::create_dir_all(config.wal_dir_path())
+            .map_err(|e| StorageError::Io(format!("create directory {:?}", config.wal_dir_path()), e))?;
+
+        let redo_ptr = read_control_file(&config)?;
+        let mut xlog = XLogManager {
+            config: config,
+            redo_ptr: redo_ptr,
+            insert_ptr: redo_ptr,
+            flushed_ptr: redo_ptr,
+            files: HashMap::new(),
+        };
+
+        let end = xlog.read_records()?.last().map_or(redo_ptr, |r| r.0);
+        xlog.insert_ptr = end;
+        xlog.flushed_ptr = end;
+        Ok(xlog)
+    }
+
+    pub fn redo_ptr(&self) -> XLogRecPtr {
+        self.redo_ptr
+    }
+
+    pub fn insert_ptr(&self) -> XLogRecPtr {
+        self.insert_ptr
+    }
+
+    pub fn flushed_ptr(&self) -> XLogRecPtr {
+        self.flushed_ptr
+    }
+
+    // `XLogInsert` in pg.
+    //
+    // Returns the end of the record, which is set to the LSN of the page.
+    pub fn insert(&mut self, record: &XLogRecord) -> Result<XLogRecPtr, StorageError> {
+        let buf = record.to_bytes();
+        let mut pos = self.insert_ptr;
+
+        if pos % WAL_SEGMENT_SIZE + buf.len() as u64 > WAL_SEGMENT_SIZE {
+            pos = (pos / WAL_SEGMENT_SIZE + 1) * WAL_SEGMENT_SIZE;
+        }
+
+        let segno = pos / WAL_SEGMENT_SIZE;
+        let path = self.config.wal_segment_file_path(segno);
+
+        if !self.files.contains_key(&segno) {
+            let f = OpenOptions::new().write(true).create(true).open(&path)
+                        .map_err(|e| StorageError::Io(format!("open file {:?}", path), e))?;
+            self.files.insert(segno, f);
+        }
+
+        let f = self.files.get_mut(&segno).unwrap();
+        f.seek(SeekFrom::Start(pos % WAL_SEGMENT_SIZE))
+         .and_then(|_| f.write_all(&buf))
+         .map_err(|e| StorageError::Io(format!("write to file {:?} at offset {}", path, pos % WAL_SEGMENT_SIZE), e))?;
+
+        self.insert_ptr = pos + buf.len() as u64;
+        debug!("WAL record is inserted (info: {:X}, end: {})", record.xl_info, self.insert_ptr);
+        Ok(self.insert_ptr)
+    }
+
+    // `XLogFlush` in pg.
+    //
+    // Ensure that WAL up to `lsn` is on disk.
+    //
+    // Files which fail to sync are kept, so the next flush retries them.
+    pub fn flush(&mut self, lsn: XLogRecPtr) -> Result<(), StorageError> {
+        if lsn <= self.flushed_ptr {
+            return Ok(());
+        }
+
+        let segnos: Vec<u64> = self.files.keys().cloned().collect();
+
+        for segno in segnos {
+            self.files[&segno].sync_data()
+                .map_err(|e| StorageError::Io(format!("fsync file {:?}", self.config.wal_segment_file_path(segno)), e))?;
+            self.files.remove(&segno);
+        }
+
+        self.flushed_ptr = self.insert_ptr;
+        Ok(())
+    }
+
+    // `CreateCheckPoint` in pg.
+    //
+    // Caller should write and sync all dirty pages before calling this.
+    // Records before the new redo pointer are not needed any more, so
+    // old segment files are removed.
+    pub fn checkpoint(&mut self) -> Result<(), StorageError> {
+        let insert_ptr = self.insert_ptr;
+        self.flush(insert_ptr)?;
+        write_control_file(&self.config, insert_ptr)
+            .map_err(|e| StorageError::Io(format!("write file {:?}", self.config.control_file_path()), e))?;
+        self.redo_ptr = insert_ptr;
+
+        let redo_segno = self.redo_ptr / WAL_SEGMENT_SIZE;
+
+        for segno in 0..redo_segno {
+            let path = self.config.wal_segment_file_path(segno);
+
+            if path.exists() {
+                fs::remove_file(&path).map_err(|e| StorageError::Io(format!("remove file {:?}", path), e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // `XLogReadRecord` in pg.
+    //
+    // Read all valid records after the redo pointer. Each record is returned
+    // with its end position.
+    pub fn read_records(&self) -> Result<Vec<(XLogRecPtr, XLogRecord)>, StorageError> {
+        let mut records = Vec::new();
+        let mut pos = self.redo_ptr;
+        let mut segment: Option<(u64, Vec<u8>)> = None;
+
+        loop {
+            let segno = pos / WAL_SEGMENT_SIZE;
+            let offset = (pos % WAL_SEGMENT_SIZE) as usize;
+
+            if segment.as_ref().map_or(true, |s| s.0 != segno) {
+                let path = self.config.wal_segment_file_path(segno);
+                let mut buf = Vec::new();
+
+                match File::open(&path) {
+                    Ok(mut f) => {
+                        f.read_to_end(&mut buf).map_err(|e| StorageError::Io(format!("read file {:?}", path), e))?;
+                    },
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => break,
+                    Err(e) => return Err(StorageError::Io(format!("open file {:?}", path), e)),
+                }
+
+                segment = Some((segno, buf));
+            }
+
+            let record = {
+                let buf = &segment.as_ref().unwrap().1;
+
+                if offset + SIZE_OF_XLOG_RECORD <= buf.len() {
+                    let len = LittleEndian::read_u32(&buf[offset..offset + 4]) as usize;
+
+                    if len >= SIZE_OF_XLOG_RECORD && offset + len <= buf.len() {
+                        XLogRecord::from_bytes(&buf[offset..offset + len]).map(|r| (len, r))
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            };
+
+            match record {
+                Some((len, r)) => {
+                    pos = pos + len as u64;
+                    records.push((pos, r));
+                },
+                None => {
+                    // The rest of the segment may be skipped, so check the next segment.
+                    if self.config.wal_segment_file_path(segno + 1).exists() {
+                        pos = (segno + 1) * WAL_SEGMENT_SIZE;
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use tempfile::tempdir;
+
+    fn rnode() -> RelFileNode {
+        RelFileNode {
+            db_oid: 10000,
+            table_oid: 10001,
+        }
+    }
+
+    #[test]
+    fn test_record_to_bytes_and_from_bytes() {
+        let record = XLogRecord::new(XLOG_HEAP_INSERT, rnode(), 3, 2, vec![1, 2, 3]);
+        let mut buf = record.to_bytes();
+
+        assert_eq!(buf.len(), SIZE_OF_XLOG_RECORD + SIZE_OF_XLOG_PAYLOAD_HEADER + 3);
+        assert_eq!(XLogRecord::from_bytes(&buf), Some(record));
+
+        buf[24] = 0;
+        assert_eq!(XLogRecord::from_bytes(&buf), None);
+    }
+
+    #[test]
+    fn test_insert_and_read_records() {
+        let dir = tempdir().unwrap();
+        let config = Rc::new(Config::new(dir.path().to_str().unwrap().to_string()));
+        fs::create_dir_all(config.global_dir_path()).unwrap();
+
+        let mut xlog = XLogManager::open(config.clone()).unwrap();
+        let lsn1 = xlog.insert(&XLogRecord::new(XLOG_HEAP_INSERT, rnode(), 0, 0, vec![1, 2, 3])).unwrap();
+        let lsn2 = xlog.insert(&XLogRecord::new(XLOG_HEAP_DELETE, rnode(), 0, 0, vec![])).unwrap();
+        xlog.flush(lsn2).unwrap();
+
+        let xlog = XLogManager::open(config.clone()).unwrap();
+        let records = xlog.read_records().unwrap();
+        assert_eq!(xlog.insert_ptr(), lsn2);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, lsn1);
+        assert_eq!(records[1].1.xl_info, XLOG_HEAP_DELETE);
+
+        // Torn record at the end of WAL is ignored.
+        let f = OpenOptions::new().write(true).open(config.wal_segment_file_path(0)).unwrap();
+        f.set_len(lsn2 - 1).unwrap();
+        let mut xlog = XLogManager::open(config.clone()).unwrap();
+        assert_eq!(xlog.read_records().unwrap().len(), 1);
+        assert_eq!(xlog.insert_ptr(), lsn1);
+
+        xlog.checkpoint().unwrap();
+        let xlog = XLogManager::open(config.clone()).unwrap();
+        assert_eq!(xlog.redo_ptr(), lsn1);
+        assert_eq!(xlog.read_records().unwrap().len(), 0);
+    }
+}